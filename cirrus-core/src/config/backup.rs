@@ -1,37 +1,358 @@
-use crate::config::repo;
-use serde::{Deserialize, Serialize};
+use crate::config::{repo, retry, UnknownSettings};
+use schemars::JsonSchema;
+use serde::{
+    de::{SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use std::{path::PathBuf, time::Duration};
 
-#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
+#[derive(
+    Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize, JsonSchema,
+)]
 #[serde(transparent)]
 pub struct Name(pub String);
 
-#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(transparent)]
 pub struct Path(pub String);
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(transparent)]
 pub struct Exclude(pub String);
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct Trigger(pub schedule_dsl::Schedule);
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+/// A time of day at which to run the backup, optionally only on some days.
+#[derive(JsonSchema)]
+#[schemars(deny_unknown_fields)]
+#[allow(dead_code)]
+struct TriggerSchema {
+    /// one or more times, e.g. `10:00` or `8am and 4pm`
+    at: String,
+    /// the days to run the backup on, e.g. `weekday` or `Monday and Friday`; every day if not set
+    every: Option<String>,
+}
+
+impl JsonSchema for Trigger {
+    fn schema_name() -> String {
+        "Trigger".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        TriggerSchema::json_schema(gen)
+    }
+}
+
+/// Which snapshots of a backup to keep when forgetting old snapshots.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Retention {
+    #[serde(default, alias = "keep-last")]
+    pub keep_last: Option<u32>,
+    #[serde(default, alias = "keep-hourly")]
+    pub keep_hourly: Option<u32>,
+    #[serde(default, alias = "keep-daily")]
+    pub keep_daily: Option<u32>,
+    #[serde(default, alias = "keep-weekly")]
+    pub keep_weekly: Option<u32>,
+    #[serde(default, alias = "keep-monthly")]
+    pub keep_monthly: Option<u32>,
+    #[serde(default, alias = "keep-yearly")]
+    pub keep_yearly: Option<u32>,
+    #[serde(default, with = "humantime_serde", alias = "keep-within")]
+    #[schemars(with = "Option<String>")]
+    pub keep_within: Option<Duration>,
+    /// also remove the data that's no longer referenced after forgetting snapshots
+    #[serde(default)]
+    pub prune: bool,
+}
+
+/// Shell commands run before and after a backup.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Hooks {
+    /// run before the backup
+    #[serde(default)]
+    pub before: Vec<String>,
+    /// run after the backup, whatever its outcome
+    #[serde(default)]
+    pub after: Vec<String>,
+    /// run after a successful backup
+    #[serde(default, alias = "on-success")]
+    pub on_success: Vec<String>,
+    /// run after a failed backup
+    #[serde(default, alias = "on-failure")]
+    pub on_failure: Vec<String>,
+    /// maximum time each command may take
+    #[serde(default = "Hooks::default_timeout", with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub timeout: Duration,
+    /// whether a failing `before` command prevents the backup from running
+    #[serde(
+        default = "Hooks::default_abort_on_before_failure",
+        alias = "abort-on-before-failure"
+    )]
+    pub abort_on_before_failure: bool,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Hooks {
+            before: Vec::new(),
+            after: Vec::new(),
+            on_success: Vec::new(),
+            on_failure: Vec::new(),
+            timeout: Self::default_timeout(),
+            abort_on_before_failure: Self::default_abort_on_before_failure(),
+        }
+    }
+}
+
+impl Hooks {
+    fn default_timeout() -> Duration {
+        Duration::from_secs(10 * 60)
+    }
+
+    fn default_abort_on_before_failure() -> bool {
+        true
+    }
+
+    pub fn is_default(&self) -> bool {
+        self == &Hooks::default()
+    }
+}
+
+impl Retention {
+    /// Whether any snapshots are kept at all; restic doesn't forget anything without a policy.
+    pub fn has_policy(&self) -> bool {
+        self.keep_last.is_some()
+            || self.keep_hourly.is_some()
+            || self.keep_daily.is_some()
+            || self.keep_weekly.is_some()
+            || self.keep_monthly.is_some()
+            || self.keep_yearly.is_some()
+            || self.keep_within.is_some()
+    }
+}
+
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[schemars(rename = "Paths")]
+#[allow(dead_code)]
+enum PathsSchema {
+    Single(Path),
+    Multiple(Vec<Path>),
+}
+
+struct PathsVisitor;
+
+impl<'de> Visitor<'de> for PathsVisitor {
+    type Value = Vec<Path>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a single source path in `path` or a list of source paths in `paths`")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(vec![Path(v.to_owned())])
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut paths = Vec::new();
+        while let Some(path) = seq.next_element()? {
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+fn deserialize_paths<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Path>, D::Error> {
+    d.deserialize_any(PathsVisitor)
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "Backup")]
+#[schemars(deny_unknown_fields)]
 pub struct Definition {
     pub repository: repo::Name,
-    pub path: Path,
+    /// source paths for the backup; `path` is accepted as an alias for a single path
+    #[serde(default, alias = "path", deserialize_with = "deserialize_paths")]
+    #[schemars(with = "PathsSchema")]
+    pub paths: Vec<Path>,
     #[serde(default)]
     pub excludes: Vec<Exclude>,
+    /// case-insensitive exclude patterns
+    #[serde(default)]
+    pub iexcludes: Vec<Exclude>,
+    /// files containing exclude patterns, relative to the configuration file
+    #[serde(default, alias = "exclude-files")]
+    pub exclude_files: Vec<PathBuf>,
+    /// files containing additional source paths, relative to the configuration file
+    #[serde(default, alias = "files-from")]
+    pub files_from: Vec<PathBuf>,
+    /// files containing additional NUL-separated source paths, relative to the configuration file
+    #[serde(default, alias = "files-from-raw")]
+    pub files_from_raw: Vec<PathBuf>,
+    /// command and arguments whose standard output is backed up instead of source paths
+    #[serde(default, alias = "stdin-command")]
+    pub stdin_command: Vec<String>,
+    /// file name of the backed up standard output in the snapshot
+    #[serde(default, alias = "stdin-filename")]
+    pub stdin_filename: Option<String>,
     #[serde(default, alias = "exclude-caches")]
     pub exclude_caches: bool,
     #[serde(default, alias = "exclude-larger-than")]
     pub exclude_larger_than: Option<String>,
     #[serde(default, alias = "ignore-unreadable-source-files")]
     pub ignore_unreadable_source_files: bool,
+    /// number of files restic reads in parallel, requires restic 0.15 or newer
+    #[serde(default, alias = "read-concurrency")]
+    pub read_concurrency: Option<u32>,
+    /// don't create a snapshot if nothing changed since the last one, requires restic 0.17 or
+    /// newer
+    #[serde(default, alias = "skip-if-unchanged")]
+    pub skip_if_unchanged: bool,
     #[serde(default, alias = "extra-args")]
     pub extra_args: Vec<String>,
     #[serde(default, alias = "disable-triggers")]
     pub disable_triggers: bool,
     #[serde(default)]
     pub triggers: Vec<Trigger>,
+    /// forget old snapshots of this backup after each backup run
+    #[serde(default)]
+    pub retention: Option<Retention>,
+    #[serde(default, skip_serializing_if = "Hooks::is_default")]
+    pub hooks: Hooks,
+    /// stop the backup if it's still running after this long, instead of the repository's
+    /// `timeout` setting
+    #[serde(default, with = "humantime_serde")]
+    #[schemars(with = "Option<String>")]
+    pub timeout: Option<Duration>,
+    /// retry failed runs of this backup instead of the repository's `retry` setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<retry::Policy>,
+    /// don't merge the global and repository backup defaults into this backup
+    #[serde(default, alias = "ignore-defaults")]
+    pub ignore_defaults: bool,
+    /// glob patterns of the hostnames this backup applies to, all hosts if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    #[serde(flatten)]
+    #[schemars(skip)]
+    pub unknown_settings: UnknownSettings,
+}
+
+impl Definition {
+    pub(crate) fn apply_defaults(&mut self, defaults: &Defaults) {
+        self.excludes = merge_unique(&defaults.excludes, &self.excludes);
+        self.iexcludes = merge_unique(&defaults.iexcludes, &self.iexcludes);
+        self.exclude_files = merge_unique(&defaults.exclude_files, &self.exclude_files);
+        self.exclude_caches |= defaults.exclude_caches.unwrap_or_default();
+        if self.exclude_larger_than.is_none() {
+            self.exclude_larger_than = defaults.exclude_larger_than.clone();
+        }
+        self.ignore_unreadable_source_files |=
+            defaults.ignore_unreadable_source_files.unwrap_or_default();
+        if self.retention.is_none() {
+            self.retention = defaults.retention.clone();
+        }
+        let mut extra_args = defaults.extra_args.clone();
+        extra_args.append(&mut self.extra_args);
+        self.extra_args = extra_args;
+    }
+
+    pub(crate) fn resolve_relative_paths(&mut self, base_dir: &std::path::Path) {
+        resolve_relative_paths(&mut self.exclude_files, base_dir);
+        resolve_relative_paths(&mut self.files_from, base_dir);
+        resolve_relative_paths(&mut self.files_from_raw, base_dir);
+    }
+
+    /// Whether this backup has anything to back up: source paths, files listing source paths or
+    /// a stdin command.
+    pub fn has_sources(&self) -> bool {
+        !self.paths.is_empty()
+            || !self.files_from.is_empty()
+            || !self.files_from_raw.is_empty()
+            || !self.stdin_command.is_empty()
+    }
+
+    /// All files referenced by this backup that have to exist when running it.
+    pub fn referenced_files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.exclude_files
+            .iter()
+            .chain(&self.files_from)
+            .chain(&self.files_from_raw)
+    }
+}
+
+/// Settings that are merged into backup definitions when the configuration is loaded.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Defaults {
+    #[serde(default)]
+    pub excludes: Vec<Exclude>,
+    #[serde(default)]
+    pub iexcludes: Vec<Exclude>,
+    #[serde(default, alias = "exclude-files")]
+    pub exclude_files: Vec<PathBuf>,
+    #[serde(default, alias = "exclude-caches")]
+    pub exclude_caches: Option<bool>,
+    #[serde(default, alias = "exclude-larger-than")]
+    pub exclude_larger_than: Option<String>,
+    #[serde(default, alias = "ignore-unreadable-source-files")]
+    pub ignore_unreadable_source_files: Option<bool>,
+    #[serde(default, alias = "extra-args")]
+    pub extra_args: Vec<String>,
+    #[serde(default)]
+    pub retention: Option<Retention>,
+}
+
+impl Defaults {
+    pub fn is_empty(&self) -> bool {
+        self == &Defaults::default()
+    }
+
+    /// Combines these defaults with more specific defaults, e.g. global with per-repository
+    /// defaults. Lists are concatenated and single values of the more specific defaults win.
+    pub(crate) fn merge(&self, more_specific: &Defaults) -> Defaults {
+        Defaults {
+            excludes: merge_unique(&self.excludes, &more_specific.excludes),
+            iexcludes: merge_unique(&self.iexcludes, &more_specific.iexcludes),
+            exclude_files: merge_unique(&self.exclude_files, &more_specific.exclude_files),
+            exclude_caches: more_specific.exclude_caches.or(self.exclude_caches),
+            exclude_larger_than: more_specific
+                .exclude_larger_than
+                .clone()
+                .or_else(|| self.exclude_larger_than.clone()),
+            ignore_unreadable_source_files: more_specific
+                .ignore_unreadable_source_files
+                .or(self.ignore_unreadable_source_files),
+            retention: more_specific
+                .retention
+                .clone()
+                .or_else(|| self.retention.clone()),
+            extra_args: self
+                .extra_args
+                .iter()
+                .chain(&more_specific.extra_args)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub(crate) fn resolve_relative_paths(&mut self, base_dir: &std::path::Path) {
+        resolve_relative_paths(&mut self.exclude_files, base_dir);
+    }
+}
+
+fn merge_unique<T: PartialEq + Clone>(first: &[T], second: &[T]) -> Vec<T> {
+    let mut merged = first.to_vec();
+    merged.extend(second.iter().filter(|v| !first.contains(v)).cloned());
+    merged
+}
+
+fn resolve_relative_paths(paths: &mut [PathBuf], base_dir: &std::path::Path) {
+    for path in paths {
+        if path.is_relative() {
+            *path = base_dir.join(&*path);
+        }
+    }
 }
@@ -0,0 +1,762 @@
+use crate::{
+    config::{backup, repo, Config},
+    restic::{Capability, ResticVersion},
+    secrets::{is_world_readable, Secrets},
+};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, thiserror::Error)]
+pub enum Issue {
+    #[error("backup '{}' references unknown repository '{}'", .backup.0, .repository.0)]
+    UnknownRepository {
+        backup: backup::Name,
+        repository: repo::Name,
+    },
+    #[error("secret '{}.{}' [{}] can't be resolved: {}", .repository.0, .secret, .label, .error)]
+    UnresolvableSecret {
+        repository: repo::Name,
+        secret: String,
+        label: String,
+        error: String,
+    },
+    #[error("secret file '{}' of secret '{}.{}' is readable by other users", .path, .repository.0, .secret)]
+    WorldReadableSecretFile {
+        repository: repo::Name,
+        secret: String,
+        path: String,
+    },
+    #[error("source path '{}' of backup '{}' does not exist", .path.0, .backup.0)]
+    MissingSourcePath {
+        backup: backup::Name,
+        path: backup::Path,
+    },
+    #[error("backup '{}' has both source paths and a stdin command", .backup.0)]
+    PathsWithStdinCommand { backup: backup::Name },
+    #[error("file '{}' referenced by backup '{}' does not exist", .path.display(), .backup.0)]
+    MissingReferencedFile { backup: backup::Name, path: PathBuf },
+    #[error("backup '{}' has a retention policy that doesn't keep any snapshots", .backup.0)]
+    EmptyRetention { backup: backup::Name },
+    #[error("backup '{}' has triggers enabled but no triggers configured", .backup.0)]
+    NoTriggers { backup: backup::Name },
+    #[error("unknown setting '{}' of repository '{}' is ignored", .setting, .repository.0)]
+    UnknownRepositorySetting {
+        repository: repo::Name,
+        setting: String,
+    },
+    #[error("unknown setting '{}' of backup '{}' is ignored", .setting, .backup.0)]
+    UnknownBackupSetting {
+        backup: backup::Name,
+        setting: String,
+    },
+    #[error(
+        "setting '{}' of repository '{}' requires restic {} or newer, but restic {} is installed",
+        .capability.setting(), .repository.0, .capability.min_version(), .installed
+    )]
+    UnsupportedRepositorySetting {
+        repository: repo::Name,
+        capability: Capability,
+        installed: ResticVersion,
+    },
+    #[error(
+        "setting '{}' of backup '{}' requires restic {} or newer, but restic {} is installed",
+        .capability.setting(), .backup.0, .capability.min_version(), .installed
+    )]
+    UnsupportedBackupSetting {
+        backup: backup::Name,
+        capability: Capability,
+        installed: ResticVersion,
+    },
+    #[error("extra argument '{}' of backup '{}' {}", .arg, .backup.0, .reason)]
+    SuspiciousExtraArg {
+        backup: backup::Name,
+        arg: String,
+        reason: &'static str,
+    },
+}
+
+impl Issue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Issue::UnknownRepository { .. }
+            | Issue::PathsWithStdinCommand { .. }
+            | Issue::MissingReferencedFile { .. }
+            | Issue::UnsupportedRepositorySetting { .. }
+            | Issue::UnsupportedBackupSetting { .. } => Severity::Error,
+            Issue::UnresolvableSecret { .. }
+            | Issue::MissingSourcePath { .. }
+            | Issue::WorldReadableSecretFile { .. }
+            | Issue::EmptyRetention { .. }
+            | Issue::NoTriggers { .. }
+            | Issue::UnknownRepositorySetting { .. }
+            | Issue::UnknownBackupSetting { .. }
+            | Issue::SuspiciousExtraArg { .. } => Severity::Warning,
+        }
+    }
+}
+
+const REPO_OPTION: &str = "is better set in the repository options, so it applies to all commands";
+
+/// restic arguments that conflict with the arguments cirrus sets itself or that have a dedicated
+/// setting
+const CONFLICTING_ARGS: &[(&str, &str)] = &[
+    ("-r", "conflicts with the configured repository"),
+    ("--repo", "conflicts with the configured repository"),
+    (
+        "--repository-file",
+        "conflicts with the configured repository",
+    ),
+    ("-p", "conflicts with the configured repository password"),
+    (
+        "--password-file",
+        "conflicts with the configured repository password",
+    ),
+    (
+        "--password-command",
+        "conflicts with the configured repository password",
+    ),
+    ("--json", "changes the output format cirrus expects"),
+    ("--limit-upload", REPO_OPTION),
+    ("--limit-download", REPO_OPTION),
+    ("--compression", REPO_OPTION),
+    ("--pack-size", REPO_OPTION),
+    ("--cache-dir", REPO_OPTION),
+    ("--insecure-tls", REPO_OPTION),
+    ("-o", REPO_OPTION),
+    ("--option", REPO_OPTION),
+];
+
+fn conflicting_arg(arg: &str) -> Option<&'static str> {
+    CONFLICTING_ARGS
+        .iter()
+        .find(|(name, _)| {
+            arg == *name || matches!(arg.strip_prefix(name), Some(rest) if rest.starts_with('='))
+        })
+        .map(|(_, reason)| *reason)
+}
+
+impl Config {
+    /// Checks the configuration for problems that would only show up when running a backup.
+    /// Secrets are only resolved if `secrets` is given, since that may prompt for a password or
+    /// run commands.
+    pub fn check(&self, secrets: Option<&Secrets>) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        let mut repositories = self.repositories.iter().collect::<Vec<_>>();
+        repositories.sort_by_key(|(name, _)| *name);
+        for (name, definition) in repositories {
+            self.check_repository(name, definition, secrets, &mut issues);
+        }
+
+        let mut backups = self.backups.iter().collect::<Vec<_>>();
+        backups.sort_by_key(|(name, _)| *name);
+        for (name, definition) in backups {
+            self.check_backup(name, definition, &mut issues);
+        }
+
+        issues
+    }
+
+    /// Checks that the installed restic version supports all configured settings.
+    pub fn check_restic_version(&self, installed: ResticVersion) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        let mut repositories = self.repositories.iter().collect::<Vec<_>>();
+        repositories.sort_by_key(|(name, _)| *name);
+        for (name, definition) in repositories {
+            for capability in Capability::required_by_repo(definition) {
+                if !installed.supports(capability) {
+                    issues.push(Issue::UnsupportedRepositorySetting {
+                        repository: name.clone(),
+                        capability,
+                        installed,
+                    });
+                }
+            }
+        }
+
+        let mut backups = self.backups.iter().collect::<Vec<_>>();
+        backups.sort_by_key(|(name, _)| *name);
+        for (name, definition) in backups {
+            for capability in Capability::required_by_backup(definition) {
+                if !installed.supports(capability) {
+                    issues.push(Issue::UnsupportedBackupSetting {
+                        backup: name.clone(),
+                        capability,
+                        installed,
+                    });
+                }
+            }
+        }
+
+        issues
+    }
+
+    fn check_repository(
+        &self,
+        name: &repo::Name,
+        definition: &repo::Definition,
+        secrets: Option<&Secrets>,
+        issues: &mut Vec<Issue>,
+    ) {
+        for setting in &definition.unknown_settings.0 {
+            issues.push(Issue::UnknownRepositorySetting {
+                repository: name.clone(),
+                setting: setting.clone(),
+            });
+        }
+
+        let mut all_secrets = vec![("<password>".to_string(), &definition.password)];
+        let mut named_secrets = definition
+            .secrets
+            .iter()
+            .map(|(secret_name, secret)| (secret_name.0.clone(), secret))
+            .collect::<Vec<_>>();
+        named_secrets.sort_by(|(a, _), (b, _)| a.cmp(b));
+        all_secrets.extend(named_secrets);
+
+        for (secret_name, secret) in all_secrets {
+            if let Some(Err(error)) = secrets.map(|secrets| secrets.get_secret(secret)) {
+                issues.push(Issue::UnresolvableSecret {
+                    repository: name.clone(),
+                    secret: secret_name.clone(),
+                    label: secret.label().to_string(),
+                    error: format!("{:#}", error),
+                });
+            }
+            if let repo::Secret::FromFile { file } = secret {
+                if matches!(is_world_readable(Path::new(file)), Ok(true)) {
+                    issues.push(Issue::WorldReadableSecretFile {
+                        repository: name.clone(),
+                        secret: secret_name,
+                        path: file.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn check_backup(
+        &self,
+        name: &backup::Name,
+        definition: &backup::Definition,
+        issues: &mut Vec<Issue>,
+    ) {
+        for setting in &definition.unknown_settings.0 {
+            issues.push(Issue::UnknownBackupSetting {
+                backup: name.clone(),
+                setting: setting.clone(),
+            });
+        }
+
+        if self.repository_for_backup(definition).is_err() {
+            issues.push(Issue::UnknownRepository {
+                backup: name.clone(),
+                repository: definition.repository.clone(),
+            });
+        }
+
+        for path in &definition.paths {
+            if !std::path::Path::new(&path.0).exists() {
+                issues.push(Issue::MissingSourcePath {
+                    backup: name.clone(),
+                    path: path.clone(),
+                });
+            }
+        }
+
+        let has_source_paths = !definition.paths.is_empty()
+            || !definition.files_from.is_empty()
+            || !definition.files_from_raw.is_empty();
+        if has_source_paths && !definition.stdin_command.is_empty() {
+            issues.push(Issue::PathsWithStdinCommand {
+                backup: name.clone(),
+            });
+        }
+
+        for path in definition.referenced_files() {
+            if !path.exists() {
+                issues.push(Issue::MissingReferencedFile {
+                    backup: name.clone(),
+                    path: path.clone(),
+                });
+            }
+        }
+
+        if matches!(&definition.retention, Some(retention) if !retention.has_policy()) {
+            issues.push(Issue::EmptyRetention {
+                backup: name.clone(),
+            });
+        }
+
+        if !definition.disable_triggers && definition.triggers.is_empty() {
+            issues.push(Issue::NoTriggers {
+                backup: name.clone(),
+            });
+        }
+
+        for arg in &definition.extra_args {
+            if let Some(reason) = conflicting_arg(arg) {
+                issues.push(Issue::SuspiciousExtraArg {
+                    backup: name.clone(),
+                    arg: arg.clone(),
+                    reason,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Config {
+        toml::from_str(s).unwrap()
+    }
+
+    struct EnvGuard(String);
+
+    impl Drop for EnvGuard {
+        fn drop(&mut self) {
+            std::env::remove_var(&self.0);
+        }
+    }
+
+    fn set_env(key: &str, value: &str) -> EnvGuard {
+        std::env::set_var(key, value);
+        EnvGuard(key.to_owned())
+    }
+
+    #[test]
+    fn should_find_no_issues_in_valid_config() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [repositories.test]
+            url = "/srv/repo"
+            password = { env-var = "CIRRUS_CHECK_TEST_VALID_CONFIG_PASSWORD" }
+
+            [backups.test]
+            repository = "test"
+            path = "/"
+            extra-args = ["--one-file-system"]
+            [[backups.test.triggers]]
+            at = "12:00"
+            "#,
+        );
+        let _env = set_env("CIRRUS_CHECK_TEST_VALID_CONFIG_PASSWORD", "pwd");
+
+        let issues = config.check(Some(&Secrets));
+
+        assert_eq!(issues, vec![]);
+    }
+
+    #[test]
+    fn should_report_unknown_repository() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "missing"
+            path = "/"
+            disable-triggers = true
+            "#,
+        );
+
+        let issues = config.check(Some(&Secrets));
+
+        assert_eq!(
+            issues,
+            vec![Issue::UnknownRepository {
+                backup: backup::Name("test".to_string()),
+                repository: repo::Name("missing".to_string()),
+            }]
+        );
+        assert_eq!(issues[0].severity(), Severity::Error);
+    }
+
+    #[test]
+    fn should_report_unresolvable_secrets() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [repositories.test]
+            url = "/srv/repo"
+            password = { env-var = "CIRRUS_CHECK_TEST_UNRESOLVABLE_PASSWORD" }
+
+            [repositories.test.secrets]
+            SECRET = { toml = "/tmp/nopenopenope.toml", key = "secret" }
+            "#,
+        );
+
+        let issues = config.check(Some(&Secrets));
+
+        let secret_names = issues
+            .iter()
+            .map(|issue| match issue {
+                Issue::UnresolvableSecret { secret, .. } => secret.as_str(),
+                _ => panic!("unexpected issue {:?}", issue),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(secret_names, vec!["<password>", "SECRET"]);
+    }
+
+    #[test]
+    fn should_not_resolve_secrets_without_secrets() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [repositories.test]
+            url = "/srv/repo"
+            password = { env-var = "CIRRUS_CHECK_TEST_UNRESOLVED_PASSWORD" }
+            "#,
+        );
+
+        let issues = config.check(None);
+
+        assert_eq!(issues, vec![]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_report_world_readable_secret_file() {
+        use std::os::unix::fs::PermissionsExt as _;
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("password");
+        std::fs::write(&path, "secret").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let config = parse(&format!(
+            //language=TOML
+            r#"
+            [repositories.test]
+            url = "/srv/repo"
+            password = {{ file = "{}" }}
+            "#,
+            path.display()
+        ));
+
+        let issues = config.check(Some(&Secrets));
+
+        assert_eq!(
+            issues,
+            vec![Issue::WorldReadableSecretFile {
+                repository: repo::Name("test".to_string()),
+                secret: "<password>".to_string(),
+                path: path.display().to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn should_report_missing_source_path() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            paths = ["/", "/nopenopenope"]
+            disable-triggers = true
+            "#,
+        );
+
+        let issues = config.check(Some(&Secrets));
+
+        assert!(issues.contains(&Issue::MissingSourcePath {
+            backup: backup::Name("test".to_string()),
+            path: backup::Path("/nopenopenope".to_string()),
+        }));
+        assert!(!issues.contains(&Issue::MissingSourcePath {
+            backup: backup::Name("test".to_string()),
+            path: backup::Path("/".to_string()),
+        }));
+    }
+
+    #[test]
+    fn should_accept_files_from_as_source_paths() {
+        let files_from = tempfile::NamedTempFile::new().unwrap();
+        let config = parse(&format!(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            files-from = ["{}"]
+            disable-triggers = true
+            "#,
+            files_from.path().display()
+        ));
+
+        let issues = config.check(Some(&Secrets));
+
+        assert!(!issues
+            .iter()
+            .any(|issue| matches!(issue, Issue::MissingReferencedFile { .. })));
+    }
+
+    #[test]
+    fn should_warn_about_unknown_settings() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [repositories.test]
+            url = "/srv/repo"
+            password = { env-var = "CIRRUS_CHECK_TEST_UNKNOWN_SETTINGS_PASSWORD" }
+            parallel-job = 2
+
+            [backups.test]
+            repository = "test"
+            path = "/"
+            exclude-cache = true
+            disable-triggers = true
+            "#,
+        );
+
+        let issues = config.check(None);
+
+        assert_eq!(
+            issues,
+            vec![
+                Issue::UnknownRepositorySetting {
+                    repository: repo::Name("test".to_string()),
+                    setting: "parallel-job".to_string(),
+                },
+                Issue::UnknownBackupSetting {
+                    backup: backup::Name("test".to_string()),
+                    setting: "exclude-cache".to_string(),
+                },
+            ]
+        );
+        assert_eq!(issues[0].severity(), Severity::Warning);
+    }
+
+    #[test]
+    fn should_report_settings_unsupported_by_restic_version() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [repositories.test]
+            url = "/srv/repo"
+            password = { env-var = "CIRRUS_CHECK_TEST_RESTIC_VERSION_PASSWORD" }
+            options = { compression = "max" }
+
+            [backups.test]
+            repository = "test"
+            path = "/"
+            read-concurrency = 4
+            skip-if-unchanged = true
+            disable-triggers = true
+            "#,
+        );
+
+        let issues = config.check_restic_version(ResticVersion::new(0, 15, 2));
+
+        assert_eq!(
+            issues,
+            vec![Issue::UnsupportedBackupSetting {
+                backup: backup::Name("test".to_string()),
+                capability: Capability::SkipIfUnchanged,
+                installed: ResticVersion::new(0, 15, 2),
+            }]
+        );
+        assert_eq!(issues[0].severity(), Severity::Error);
+        assert_eq!(
+            config.check_restic_version(ResticVersion::new(0, 13, 0)),
+            vec![
+                Issue::UnsupportedRepositorySetting {
+                    repository: repo::Name("test".to_string()),
+                    capability: Capability::Compression,
+                    installed: ResticVersion::new(0, 13, 0),
+                },
+                Issue::UnsupportedBackupSetting {
+                    backup: backup::Name("test".to_string()),
+                    capability: Capability::ReadConcurrency,
+                    installed: ResticVersion::new(0, 13, 0),
+                },
+                Issue::UnsupportedBackupSetting {
+                    backup: backup::Name("test".to_string()),
+                    capability: Capability::SkipIfUnchanged,
+                    installed: ResticVersion::new(0, 13, 0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_stdin_command_with_source_paths() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            path = "/"
+            stdin-command = ["pg_dump", "db"]
+            disable-triggers = true
+            "#,
+        );
+
+        let issues = config.check(Some(&Secrets));
+
+        assert!(issues.contains(&Issue::PathsWithStdinCommand {
+            backup: backup::Name("test".to_string()),
+        }));
+    }
+
+    #[test]
+    fn should_report_missing_referenced_files() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            path = "/"
+            exclude-files = ["/nopenopenope/excludes.txt"]
+            files-from-raw = ["/nopenopenope/files"]
+            disable-triggers = true
+            "#,
+        );
+
+        let issues = config.check(Some(&Secrets));
+
+        let paths = issues
+            .iter()
+            .filter_map(|issue| match issue {
+                Issue::MissingReferencedFile { path, .. } => Some(path.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/nopenopenope/excludes.txt"),
+                PathBuf::from("/nopenopenope/files"),
+            ]
+        );
+        assert_eq!(
+            Issue::MissingReferencedFile {
+                backup: backup::Name("test".to_string()),
+                path: PathBuf::from("/x")
+            }
+            .severity(),
+            Severity::Error
+        );
+    }
+
+    #[test]
+    fn should_report_retention_without_policy() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            path = "/"
+            disable-triggers = true
+            retention = { prune = true }
+            "#,
+        );
+
+        let issues = config.check(Some(&Secrets));
+
+        assert!(issues.contains(&Issue::EmptyRetention {
+            backup: backup::Name("test".to_string()),
+        }));
+    }
+
+    #[test]
+    fn should_report_enabled_triggers_without_triggers() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            path = "/"
+            "#,
+        );
+
+        let issues = config.check(Some(&Secrets));
+
+        assert!(issues.contains(&Issue::NoTriggers {
+            backup: backup::Name("test".to_string()),
+        }));
+    }
+
+    #[test]
+    fn should_report_conflicting_extra_args() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            path = "/"
+            disable-triggers = true
+            extra-args = ["--repo=/srv/other", "--one-file-system", "--json", "--password-file", "/pwd"]
+            "#,
+        );
+
+        let issues = config.check(Some(&Secrets));
+
+        let args = issues
+            .iter()
+            .filter_map(|issue| match issue {
+                Issue::SuspiciousExtraArg { arg, .. } => Some(arg.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(args, vec!["--repo=/srv/other", "--json", "--password-file"]);
+    }
+
+    #[test]
+    fn should_suggest_repository_options_for_global_extra_args() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            path = "/"
+            disable-triggers = true
+            extra-args = ["--limit-upload=1000", "-o", "sftp.command=ssh host", "--insecure-tls"]
+            "#,
+        );
+
+        let issues = config.check(Some(&Secrets));
+
+        let args = issues
+            .iter()
+            .filter_map(|issue| match issue {
+                Issue::SuspiciousExtraArg { arg, reason, .. } => Some((arg.as_str(), *reason)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            vec![
+                ("--limit-upload=1000", REPO_OPTION),
+                ("-o", REPO_OPTION),
+                ("--insecure-tls", REPO_OPTION)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_not_match_args_with_conflicting_prefix() {
+        assert_eq!(
+            conflicting_arg("--repository-file"),
+            Some("conflicts with the configured repository")
+        );
+        assert_eq!(conflicting_arg("--repo-extra"), None);
+        assert_eq!(conflicting_arg("-prune"), None);
+    }
+}
@@ -0,0 +1,36 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const DEFAULT_SECRETS_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Settings that only affect the daemon.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Settings {
+    /// how long the daemon keeps secrets in memory after reading them; `0s` disables the cache
+    #[serde(
+        default = "default_secrets_cache_ttl",
+        with = "humantime_serde",
+        alias = "secrets-cache-ttl"
+    )]
+    #[schemars(with = "String")]
+    pub secrets_cache_ttl: Duration,
+}
+
+fn default_secrets_cache_ttl() -> Duration {
+    DEFAULT_SECRETS_CACHE_TTL
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            secrets_cache_ttl: DEFAULT_SECRETS_CACHE_TTL,
+        }
+    }
+}
+
+impl Settings {
+    pub fn is_default(&self) -> bool {
+        self == &Settings::default()
+    }
+}
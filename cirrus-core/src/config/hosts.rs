@@ -0,0 +1,320 @@
+use super::{backup, repo, Config, ConfigLoadError};
+use std::fmt::{Display, Formatter};
+
+/// A repository or backup that was dropped from the configuration because it doesn't apply to
+/// this host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filtered {
+    Repository {
+        name: repo::Name,
+        hosts: Vec<String>,
+        hostname: Option<String>,
+    },
+    Backup {
+        name: backup::Name,
+        hosts: Vec<String>,
+        hostname: Option<String>,
+    },
+    BackupOfFilteredRepository {
+        name: backup::Name,
+        repository: repo::Name,
+    },
+}
+
+fn describe_host(hostname: &Option<String>) -> String {
+    match hostname {
+        Some(hostname) => format!("'{}'", hostname),
+        None => "unknown".to_owned(),
+    }
+}
+
+impl Display for Filtered {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Filtered::Repository {
+                name,
+                hosts,
+                hostname,
+            } => write!(
+                f,
+                "repository '{}' only applies to hosts {} and this host is {}",
+                name.0,
+                hosts.join(", "),
+                describe_host(hostname)
+            ),
+            Filtered::Backup {
+                name,
+                hosts,
+                hostname,
+            } => write!(
+                f,
+                "backup '{}' only applies to hosts {} and this host is {}",
+                name.0,
+                hosts.join(", "),
+                describe_host(hostname)
+            ),
+            Filtered::BackupOfFilteredRepository { name, repository } => write!(
+                f,
+                "backup '{}' uses repository '{}', which doesn't apply to this host",
+                name.0, repository.0
+            ),
+        }
+    }
+}
+
+/// Checks whether the hostname matches any of the glob patterns. Hostnames are compared
+/// case-insensitively and an empty list of patterns matches every host.
+fn matches_hosts(hosts: &[String], hostname: Option<&str>) -> Result<bool, ConfigLoadError> {
+    if hosts.is_empty() {
+        return Ok(true);
+    }
+    let options = glob::MatchOptions {
+        case_sensitive: false,
+        ..Default::default()
+    };
+    for host in hosts {
+        let pattern = glob::Pattern::new(host)
+            .map_err(|e| ConfigLoadError::InvalidHostPattern(host.clone(), e))?;
+        if matches!(hostname, Some(hostname) if pattern.matches_with(hostname, options)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+impl Config {
+    /// Removes the repositories and backups whose `hosts` don't match the hostname.
+    pub(super) fn filter_hosts(&mut self, hostname: Option<&str>) -> Result<(), ConfigLoadError> {
+        let mut filtered_repos = Vec::new();
+        for (name, definition) in &self.repositories.0 {
+            if !matches_hosts(&definition.hosts, hostname)? {
+                filtered_repos.push(name.clone());
+            }
+        }
+        filtered_repos.sort();
+        for name in filtered_repos {
+            let definition = self.repositories.0.remove(&name).unwrap();
+            self.filtered.push(Filtered::Repository {
+                name,
+                hosts: definition.hosts,
+                hostname: hostname.map(ToOwned::to_owned),
+            });
+        }
+
+        let mut filtered_backups = Vec::new();
+        for (name, definition) in &self.backups.0 {
+            if !matches_hosts(&definition.hosts, hostname)? {
+                filtered_backups.push(name.clone());
+            }
+        }
+        filtered_backups.sort();
+        for name in filtered_backups {
+            let definition = self.backups.0.remove(&name).unwrap();
+            self.filtered.push(Filtered::Backup {
+                name,
+                hosts: definition.hosts,
+                hostname: hostname.map(ToOwned::to_owned),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Removes the backups to repositories that were filtered out. Only possible once all
+    /// configuration files are merged since the repository could be defined in any of them.
+    pub(super) fn filter_backups_of_filtered_repositories(&mut self) {
+        let filtered_repos = self
+            .filtered
+            .iter()
+            .filter_map(|filtered| match filtered {
+                Filtered::Repository { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .filter(|name| !self.repositories.0.contains_key(name))
+            .collect::<Vec<_>>();
+        let mut backups = self
+            .backups
+            .iter()
+            .filter(|(_, definition)| filtered_repos.contains(&definition.repository))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        backups.sort();
+        for name in backups {
+            let definition = self.backups.0.remove(&name).unwrap();
+            self.filtered.push(Filtered::BackupOfFilteredRepository {
+                name,
+                repository: definition.repository,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::interpolate;
+
+    fn parse(s: &str, hostname: Option<&str>) -> Config {
+        let mut config: Config = toml::from_str(s).unwrap();
+        config.filter_hosts(hostname).unwrap();
+        config.filter_backups_of_filtered_repositories();
+        config
+    }
+
+    #[test]
+    fn should_match_hosts_with_glob_patterns() {
+        let hosts = vec!["laptop-*".to_string(), "server".to_string()];
+
+        assert!(matches_hosts(&hosts, Some("laptop-work")).unwrap());
+        assert!(matches_hosts(&hosts, Some("Laptop-Home")).unwrap());
+        assert!(matches_hosts(&hosts, Some("server")).unwrap());
+        assert!(!matches_hosts(&hosts, Some("server2")).unwrap());
+        assert!(!matches_hosts(&hosts, None).unwrap());
+    }
+
+    #[test]
+    fn should_match_any_host_without_patterns() {
+        assert!(matches_hosts(&[], Some("laptop")).unwrap());
+        assert!(matches_hosts(&[], None).unwrap());
+    }
+
+    #[test]
+    fn should_reject_invalid_host_pattern() {
+        let result = matches_hosts(&["[".to_string()], Some("laptop"));
+
+        assert!(matches!(
+            result,
+            Err(ConfigLoadError::InvalidHostPattern(pattern, _)) if pattern == "["
+        ));
+    }
+
+    #[test]
+    fn should_keep_entries_for_matching_host() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [repositories.repo]
+            url = "/srv/repo"
+            password = { env-var = "PASSWORD" }
+            hosts = ["laptop-*"]
+
+            [backups.home]
+            repository = "repo"
+            path = "/home"
+            hosts = ["laptop-work"]
+            "#,
+            Some("laptop-work"),
+        );
+
+        assert!(config.repository(&repo::Name("repo".to_string())).is_ok());
+        assert!(config.backup(&backup::Name("home".to_string())).is_ok());
+        assert_eq!(config.filtered, vec![]);
+    }
+
+    #[test]
+    fn should_drop_entries_for_other_hosts() {
+        let config = parse(
+            //language=TOML
+            r#"
+            [repositories.local]
+            url = "/srv/repo"
+            password = { env-var = "PASSWORD" }
+
+            [repositories.nas]
+            url = "/mnt/nas"
+            password = { env-var = "PASSWORD" }
+            hosts = ["server"]
+
+            [backups.home]
+            repository = "local"
+            path = "/home"
+            hosts = ["laptop-*"]
+
+            [backups.srv]
+            repository = "local"
+            path = "/srv"
+
+            [backups.media]
+            repository = "nas"
+            path = "/media"
+            "#,
+            Some("desktop"),
+        );
+
+        let backups = config
+            .backups
+            .iter()
+            .map(|(name, _)| name.0.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(backups, vec!["srv"]);
+        assert!(config.repository(&repo::Name("nas".to_string())).is_err());
+
+        assert_eq!(
+            config.filtered,
+            vec![
+                Filtered::Repository {
+                    name: repo::Name("nas".to_string()),
+                    hosts: vec!["server".to_string()],
+                    hostname: Some("desktop".to_string()),
+                },
+                Filtered::Backup {
+                    name: backup::Name("home".to_string()),
+                    hosts: vec!["laptop-*".to_string()],
+                    hostname: Some("desktop".to_string()),
+                },
+                Filtered::BackupOfFilteredRepository {
+                    name: backup::Name("media".to_string()),
+                    repository: repo::Name("nas".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_not_interpolate_entries_for_other_hosts() {
+        let mut config: Config = toml::from_str(
+            //language=TOML
+            r#"
+            [repositories.nas]
+            url = "${NAS_URL}"
+            password = { env-var = "PASSWORD" }
+            hosts = ["server"]
+
+            [backups.media]
+            repository = "nas"
+            path = "${MEDIA_DIR}"
+            "#,
+        )
+        .unwrap();
+        let context = interpolate::Context {
+            hostname: Some("desktop".to_string()),
+            ..Default::default()
+        };
+
+        config.filter_hosts_and_interpolate(&context).unwrap();
+
+        assert!(config.repositories.0.is_empty());
+        assert!(config.backups.0.is_empty());
+    }
+
+    #[test]
+    fn should_describe_filtered_entries() {
+        assert_eq!(
+            Filtered::Backup {
+                name: backup::Name("home".to_string()),
+                hosts: vec!["laptop-*".to_string(), "desktop".to_string()],
+                hostname: Some("server".to_string()),
+            }
+            .to_string(),
+            "backup 'home' only applies to hosts laptop-*, desktop and this host is 'server'"
+        );
+        assert_eq!(
+            Filtered::BackupOfFilteredRepository {
+                name: backup::Name("media".to_string()),
+                repository: repo::Name("nas".to_string()),
+            }
+            .to_string(),
+            "backup 'media' uses repository 'nas', which doesn't apply to this host"
+        );
+    }
+}
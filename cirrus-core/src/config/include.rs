@@ -0,0 +1,319 @@
+use super::ConfigLoadError;
+use std::path::{Path, PathBuf};
+
+/// The drop-in directory for a configuration file, e.g. `backups.d` for `backups.toml`.
+pub(super) fn drop_in_dir(config_path: &Path) -> Option<PathBuf> {
+    let stem = config_path.file_stem()?;
+    let mut dir_name = stem.to_owned();
+    dir_name.push(".d");
+    Some(config_path.with_file_name(dir_name))
+}
+
+/// Resolves the include patterns and the drop-in directory of a configuration file into a list
+/// of files. Relative patterns are resolved against the directory of the configuration file.
+pub(super) fn included_files(
+    config_path: &Path,
+    patterns: &[String],
+) -> Result<Vec<PathBuf>, ConfigLoadError> {
+    let base_dir = base_dir(config_path);
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let pattern_path = base_dir.join(pattern);
+        let is_literal = glob::Pattern::escape(pattern) == *pattern;
+        let matches = glob_files(&resolve_pattern(base_dir, pattern), pattern)?;
+        if is_literal && matches.is_empty() {
+            return Err(ConfigLoadError::IoError(
+                pattern_path,
+                std::io::Error::from(std::io::ErrorKind::NotFound),
+            ));
+        }
+        push_unique(&mut files, matches);
+    }
+    if let Some(drop_in_dir) = drop_in_dir(config_path).filter(|dir| dir.is_dir()) {
+        let pattern = Path::new(&glob::Pattern::escape(&drop_in_dir.to_string_lossy()))
+            .join("*.toml")
+            .to_string_lossy()
+            .into_owned();
+        push_unique(&mut files, glob_files(&pattern, &pattern)?);
+    }
+    Ok(files)
+}
+
+/// Files matching `pattern` affect the configuration. They're created, changed and removed in
+/// `dir`, or in its subdirectories if `recursive` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchPattern {
+    dir: PathBuf,
+    recursive: bool,
+    pattern: glob::Pattern,
+}
+
+/// A directory to watch for changes to the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchedDir {
+    pub path: PathBuf,
+    pub recursive: bool,
+}
+
+/// The patterns of all files that make up a configuration: the configuration file itself, the
+/// include patterns and the `.toml` files in the drop-in directory. Files that don't exist yet
+/// are covered as well, so they can be picked up once they're created.
+pub(super) fn watch_patterns(
+    config_path: &Path,
+    patterns: &[String],
+) -> Result<Vec<WatchPattern>, ConfigLoadError> {
+    let base_dir = base_dir(config_path);
+    let mut watch_patterns = vec![WatchPattern {
+        dir: base_dir.to_owned(),
+        recursive: false,
+        pattern: literal_pattern(config_path),
+    }];
+    for pattern in patterns {
+        let components: Vec<_> = Path::new(pattern).components().collect();
+        let literal_components = components
+            .iter()
+            .take_while(|c| {
+                let c = c.as_os_str().to_string_lossy();
+                glob::Pattern::escape(&c) == c
+            })
+            .count();
+        let (dir, recursive) = match components.len() - literal_components {
+            0 => (base_dir.join(pattern).parent().map(Path::to_owned), false),
+            n => (
+                Some(base_dir.join(components[..literal_components].iter().collect::<PathBuf>())),
+                n > 1,
+            ),
+        };
+        let resolved = resolve_pattern(base_dir, pattern);
+        watch_patterns.push(WatchPattern {
+            dir: dir.unwrap_or_else(|| base_dir.to_owned()),
+            recursive,
+            pattern: glob::Pattern::new(&resolved)
+                .map_err(|e| ConfigLoadError::InvalidIncludePattern(pattern.clone(), e))?,
+        });
+    }
+    if let Some(drop_in_dir) = drop_in_dir(config_path) {
+        let pattern = literal_pattern(&drop_in_dir).as_str().to_owned() + "/*.toml";
+        watch_patterns.push(WatchPattern {
+            dir: drop_in_dir,
+            recursive: false,
+            pattern: glob::Pattern::new(&pattern)
+                .map_err(|e| ConfigLoadError::InvalidIncludePattern(pattern.clone(), e))?,
+        });
+    }
+    Ok(watch_patterns)
+}
+
+/// The directories to watch for the given patterns. Directories that don't exist yet are
+/// replaced by their closest existing parent so their creation is noticed.
+pub(super) fn watched_dirs(patterns: &[WatchPattern]) -> Vec<WatchedDir> {
+    let mut dirs: Vec<WatchedDir> = Vec::new();
+    for pattern in patterns {
+        let path = match pattern.dir.ancestors().find(|dir| dir.is_dir()) {
+            Some(path) => path,
+            None => continue,
+        };
+        let recursive = pattern.recursive && path == pattern.dir;
+        match dirs.iter_mut().find(|dir| dir.path == path) {
+            Some(dir) => dir.recursive |= recursive,
+            None => dirs.push(WatchedDir {
+                path: path.to_owned(),
+                recursive,
+            }),
+        }
+    }
+    dirs
+}
+
+/// Whether a change to `path` affects the configuration, either because it matches one of the
+/// patterns or because it's a directory that is needed to watch one of them.
+pub(super) fn affects(patterns: &[WatchPattern], path: &Path) -> bool {
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    patterns
+        .iter()
+        .any(|p| p.pattern.matches_path_with(path, options) || p.dir.starts_with(path))
+}
+
+fn base_dir(config_path: &Path) -> &Path {
+    config_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+/// Resolves a pattern against the directory of the configuration file, which may itself
+/// contain characters that have a special meaning in patterns.
+fn resolve_pattern(base_dir: &Path, pattern: &str) -> String {
+    if Path::new(pattern).is_absolute() {
+        pattern.to_owned()
+    } else {
+        Path::new(&glob::Pattern::escape(&base_dir.to_string_lossy()))
+            .join(pattern)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+fn literal_pattern(path: &Path) -> glob::Pattern {
+    glob::Pattern::new(&glob::Pattern::escape(&path.to_string_lossy()))
+        .expect("escaped pattern should be valid")
+}
+
+fn glob_files(pattern: &str, original: &str) -> Result<Vec<PathBuf>, ConfigLoadError> {
+    glob::glob(pattern)
+        .map_err(|e| ConfigLoadError::InvalidIncludePattern(original.to_owned(), e))?
+        .map(|entry| {
+            entry.map_err(|e| {
+                let path = e.path().to_owned();
+                ConfigLoadError::IoError(path, std::io::Error::new(e.error().kind(), e))
+            })
+        })
+        .filter(|entry| !matches!(entry, Ok(path) if path.is_dir()))
+        .collect()
+}
+
+fn push_unique(files: &mut Vec<PathBuf>, new_files: Vec<PathBuf>) {
+    for file in new_files {
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_get_drop_in_dir_for_config_file() {
+        let result = drop_in_dir(Path::new("/home/user/.config/cirrus/backups.toml"));
+
+        assert_eq!(
+            result,
+            Some(PathBuf::from("/home/user/.config/cirrus/backups.d"))
+        );
+    }
+
+    #[test]
+    fn should_resolve_patterns_relative_to_config_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("shared")).unwrap();
+        std::fs::write(tmp.path().join("shared/b.toml"), "").unwrap();
+        std::fs::write(tmp.path().join("shared/a.toml"), "").unwrap();
+        std::fs::write(tmp.path().join("shared/c.txt"), "").unwrap();
+        std::fs::write(tmp.path().join("extra.toml"), "").unwrap();
+
+        let result = included_files(
+            &tmp.path().join("backups.toml"),
+            &["shared/*.toml".to_string(), "extra.toml".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            result,
+            vec![
+                tmp.path().join("shared/a.toml"),
+                tmp.path().join("shared/b.toml"),
+                tmp.path().join("extra.toml"),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_include_drop_in_dir_files_after_patterns() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("backups.d")).unwrap();
+        std::fs::write(tmp.path().join("backups.d/20-personal.toml"), "").unwrap();
+        std::fs::write(tmp.path().join("backups.d/10-shared.toml"), "").unwrap();
+        std::fs::write(tmp.path().join("backups.d/README"), "").unwrap();
+
+        let result = included_files(
+            &tmp.path().join("backups.toml"),
+            &["backups.d/20-personal.toml".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            result,
+            vec![
+                tmp.path().join("backups.d/20-personal.toml"),
+                tmp.path().join("backups.d/10-shared.toml"),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_not_resolve_missing_literal_include() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let result = included_files(
+            &tmp.path().join("backups.toml"),
+            &["missing.toml".to_string()],
+        );
+
+        assert!(matches!(result, Err(ConfigLoadError::IoError(_, _))));
+    }
+
+    #[test]
+    fn should_allow_glob_pattern_without_matches() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let result = included_files(
+            &tmp.path().join("backups.toml"),
+            &["missing/*.toml".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(result, Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn should_watch_dirs_of_patterns() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("hosts/laptop")).unwrap();
+
+        let patterns = watch_patterns(
+            &tmp.path().join("backups.toml"),
+            &[
+                "hosts/*/backups.toml".to_string(),
+                "missing/*.toml".to_string(),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            watched_dirs(&patterns),
+            vec![
+                WatchedDir {
+                    path: tmp.path().to_owned(),
+                    recursive: false,
+                },
+                WatchedDir {
+                    path: tmp.path().join("hosts"),
+                    recursive: true,
+                },
+            ]
+        );
+        assert!(affects(
+            &patterns,
+            &tmp.path().join("hosts/desktop/backups.toml")
+        ));
+        assert!(affects(&patterns, &tmp.path().join("missing")));
+        assert!(!affects(&patterns, &tmp.path().join("hosts/backups.toml")));
+    }
+
+    #[test]
+    fn should_not_resolve_invalid_pattern() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let result = included_files(&tmp.path().join("backups.toml"), &["[".to_string()]);
+
+        assert!(matches!(
+            result,
+            Err(ConfigLoadError::InvalidIncludePattern(_, _))
+        ));
+    }
+}
@@ -0,0 +1,466 @@
+//! Interpolation of `~`, environment variables and placeholders in configuration values.
+//!
+//! * a leading `~` followed by a path separator (or nothing) is replaced by the home directory
+//! * `$VAR` and `${VAR}` are replaced by the value of the environment variable `VAR`
+//! * `{{hostname}}` is replaced by the hostname
+//!
+//! `$$` produces a literal `$`, e.g. `C:\$$Recycle.Bin` for `C:\$Recycle.Bin`. A `$` that isn't
+//! followed by a variable name or `{` is kept as it is.
+
+use crate::config::{backup, repo, Config, ConfigLoadError};
+use dirs_next as dirs;
+use std::{collections::HashMap, path::PathBuf};
+
+#[derive(Debug, PartialEq, Eq, Clone, thiserror::Error)]
+pub enum Error {
+    #[error("environment variable '{0}' is not defined")]
+    UndefinedVariable(String),
+    #[error("invalid variable name '{0}'")]
+    InvalidVariableName(String),
+    #[error("missing closing '{0}'")]
+    Unterminated(&'static str),
+    #[error("unknown placeholder '{{{{{0}}}}}'")]
+    UnknownPlaceholder(String),
+    #[error("couldn't determine the home directory")]
+    NoHomeDirectory,
+    #[error("couldn't determine the hostname")]
+    NoHostname,
+}
+
+/// The values available for interpolation.
+#[derive(Debug, Default, Clone)]
+pub struct Context {
+    pub home_dir: Option<String>,
+    pub hostname: Option<String>,
+    pub vars: HashMap<String, String>,
+}
+
+impl Context {
+    pub fn from_environment() -> Self {
+        Context {
+            home_dir: dirs::home_dir().and_then(|dir| dir.into_os_string().into_string().ok()),
+            hostname: hostname::get()
+                .ok()
+                .map(|hostname| hostname.to_string_lossy().into_owned()),
+            vars: std::env::vars_os()
+                .filter_map(|(name, value)| {
+                    Some((name.into_string().ok()?, value.into_string().ok()?))
+                })
+                .collect(),
+        }
+    }
+
+    fn var(&self, name: &str) -> Result<&str, Error> {
+        if !is_valid_variable_name(name) {
+            return Err(Error::InvalidVariableName(name.to_owned()));
+        }
+        self.vars
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| Error::UndefinedVariable(name.to_owned()))
+    }
+
+    fn placeholder(&self, name: &str) -> Result<&str, Error> {
+        match name {
+            "hostname" => self.hostname.as_deref().ok_or(Error::NoHostname),
+            _ => Err(Error::UnknownPlaceholder(name.to_owned())),
+        }
+    }
+}
+
+fn is_valid_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn interpolate(value: &str, context: &Context) -> Result<String, Error> {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    if let Some(after) = rest.strip_prefix('~') {
+        if after.is_empty() || after.starts_with(std::path::is_separator) {
+            result.push_str(context.home_dir.as_deref().ok_or(Error::NoHomeDirectory)?);
+            rest = after;
+        }
+    }
+
+    while let Some(idx) = rest.find(['$', '{']) {
+        result.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            result.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}').ok_or(Error::Unterminated("}"))?;
+            result.push_str(context.var(&after[..end])?);
+            rest = &after[end + 1..];
+        } else if let Some(after) = rest.strip_prefix('$') {
+            let end = after
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(after.len());
+            if is_valid_variable_name(&after[..end]) {
+                result.push_str(context.var(&after[..end])?);
+                rest = &after[end..];
+            } else {
+                result.push('$');
+                rest = after;
+            }
+        } else if let Some(after) = rest.strip_prefix("{{") {
+            let end = after.find("}}").ok_or(Error::Unterminated("}}"))?;
+            result.push_str(context.placeholder(after[..end].trim())?);
+            rest = &after[end + 2..];
+        } else {
+            result.push('{');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+fn interpolate_string(
+    value: &mut String,
+    context: &Context,
+    setting: impl FnOnce() -> String,
+) -> Result<(), ConfigLoadError> {
+    *value = interpolate(value, context)
+        .map_err(|error| ConfigLoadError::InterpolationError(setting(), error))?;
+    Ok(())
+}
+
+fn interpolate_path(
+    value: &mut PathBuf,
+    context: &Context,
+    setting: impl FnOnce() -> String,
+) -> Result<(), ConfigLoadError> {
+    // paths read from the configuration file are always valid UTF-8
+    if let Some(s) = value.to_str() {
+        *value = interpolate(s, context)
+            .map_err(|error| ConfigLoadError::InterpolationError(setting(), error))?
+            .into();
+    }
+    Ok(())
+}
+
+fn interpolate_defaults(
+    defaults: &mut backup::Defaults,
+    context: &Context,
+    prefix: &str,
+) -> Result<(), ConfigLoadError> {
+    for exclude in &mut defaults.excludes {
+        interpolate_string(&mut exclude.0, context, || format!("{prefix}.excludes"))?;
+    }
+    for exclude in &mut defaults.iexcludes {
+        interpolate_string(&mut exclude.0, context, || format!("{prefix}.iexcludes"))?;
+    }
+    for exclude_file in &mut defaults.exclude_files {
+        interpolate_path(exclude_file, context, || format!("{prefix}.exclude_files"))?;
+    }
+    Ok(())
+}
+
+fn interpolate_secret(
+    secret: &mut repo::Secret,
+    context: &Context,
+    setting: String,
+) -> Result<(), ConfigLoadError> {
+    match secret {
+        repo::Secret::FromToml { toml, .. } => interpolate_string(toml, context, || setting),
+        repo::Secret::FromAgeToml { file, identity, .. } => {
+            interpolate_string(file, context, || setting.clone())?;
+            interpolate_secret(identity, context, format!("{setting}.identity"))
+        }
+        repo::Secret::FromFile { file } => interpolate_string(file, context, || setting),
+        repo::Secret::FromCommand { command } => {
+            for arg in command {
+                interpolate_string(arg, context, || setting.clone())?;
+            }
+            Ok(())
+        }
+        repo::Secret::FromEnvVar { .. }
+        | repo::Secret::FromOsKeyring { .. }
+        | repo::Secret::FromSystemdCredential { .. } => Ok(()),
+    }
+}
+
+impl Config {
+    /// Interpolates the home directory, environment variables and placeholders in all settings
+    /// that contain paths, patterns or URLs.
+    pub(crate) fn interpolate(&mut self, context: &Context) -> Result<(), ConfigLoadError> {
+        interpolate_defaults(&mut self.defaults, context, "defaults")?;
+
+        for (name, definition) in &mut self.repositories.0 {
+            let prefix = format!("repositories.{}", name.0);
+            interpolate_string(&mut definition.url.0, context, || format!("{prefix}.url"))?;
+            interpolate_secret(
+                &mut definition.password,
+                context,
+                format!("{prefix}.password"),
+            )?;
+            for (secret_name, secret) in &mut definition.secrets {
+                interpolate_secret(
+                    secret,
+                    context,
+                    format!("{prefix}.secrets.{}", secret_name.0),
+                )?;
+            }
+            if let Some(cache_dir) = &mut definition.options.cache_dir {
+                interpolate_path(cache_dir, context, || format!("{prefix}.options.cache_dir"))?;
+            }
+            interpolate_defaults(
+                &mut definition.backup_defaults,
+                context,
+                &format!("{prefix}.backup_defaults"),
+            )?;
+        }
+
+        for (name, definition) in &mut self.backups.0 {
+            let prefix = format!("backups.{}", name.0);
+            for path in &mut definition.paths {
+                interpolate_string(&mut path.0, context, || format!("{prefix}.paths"))?;
+            }
+            for exclude in &mut definition.excludes {
+                interpolate_string(&mut exclude.0, context, || format!("{prefix}.excludes"))?;
+            }
+            for exclude in &mut definition.iexcludes {
+                interpolate_string(&mut exclude.0, context, || format!("{prefix}.iexcludes"))?;
+            }
+            for exclude_file in &mut definition.exclude_files {
+                interpolate_path(exclude_file, context, || format!("{prefix}.exclude_files"))?;
+            }
+            for files_from in &mut definition.files_from {
+                interpolate_path(files_from, context, || format!("{prefix}.files_from"))?;
+            }
+            for files_from_raw in &mut definition.files_from_raw {
+                interpolate_path(files_from_raw, context, || {
+                    format!("{prefix}.files_from_raw")
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashmap;
+
+    fn context() -> Context {
+        Context {
+            home_dir: Some("/home/user".to_string()),
+            hostname: Some("laptop".to_string()),
+            vars: hashmap! {
+                "XDG_DATA_HOME".to_string() => "/home/user/.local/share".to_string(),
+                "USER".to_string() => "user".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn should_leave_plain_value_unchanged() {
+        assert_eq!(
+            interpolate("/srv/backup/{a,b}", &context()),
+            Ok("/srv/backup/{a,b}".to_string())
+        );
+    }
+
+    #[test]
+    fn should_replace_leading_tilde_with_home_directory() {
+        assert_eq!(
+            interpolate("~/Documents", &context()),
+            Ok("/home/user/Documents".to_string())
+        );
+        assert_eq!(interpolate("~", &context()), Ok("/home/user".to_string()));
+    }
+
+    #[test]
+    fn should_not_replace_tilde_elsewhere() {
+        assert_eq!(interpolate("~user", &context()), Ok("~user".to_string()));
+        assert_eq!(interpolate("/tmp/~", &context()), Ok("/tmp/~".to_string()));
+    }
+
+    #[test]
+    fn should_replace_environment_variables() {
+        assert_eq!(
+            interpolate("${XDG_DATA_HOME}/restic", &context()),
+            Ok("/home/user/.local/share/restic".to_string())
+        );
+        assert_eq!(
+            interpolate("/srv/${USER}_backup", &context()),
+            Ok("/srv/user_backup".to_string())
+        );
+    }
+
+    #[test]
+    fn should_replace_unbraced_environment_variables() {
+        assert_eq!(
+            interpolate("$XDG_DATA_HOME/restic", &context()),
+            Ok("/home/user/.local/share/restic".to_string())
+        );
+        assert_eq!(
+            interpolate("/home/$USER/Documents", &context()),
+            Ok("/home/user/Documents".to_string())
+        );
+    }
+
+    #[test]
+    fn should_keep_dollar_sign_without_variable_name() {
+        assert_eq!(
+            interpolate("/srv/cost: 5$", &context()),
+            Ok("/srv/cost: 5$".to_string())
+        );
+        assert_eq!(interpolate("$1 $-", &context()), Ok("$1 $-".to_string()));
+    }
+
+    #[test]
+    fn should_replace_hostname_placeholder() {
+        assert_eq!(
+            interpolate("sftp:host:/srv/{{hostname}}", &context()),
+            Ok("sftp:host:/srv/laptop".to_string())
+        );
+        assert_eq!(
+            interpolate("/srv/{{ hostname }}", &context()),
+            Ok("/srv/laptop".to_string())
+        );
+    }
+
+    #[test]
+    fn should_produce_escaped_dollar_sign_literally() {
+        assert_eq!(
+            interpolate("C:\\$$Recycle.Bin", &context()),
+            Ok("C:\\$Recycle.Bin".to_string())
+        );
+        assert_eq!(
+            interpolate("/a$${USER}/$$USER", &context()),
+            Ok("/a${USER}/$USER".to_string())
+        );
+        assert_eq!(interpolate("*.$$$$$$", &context()), Ok("*.$$$".to_string()));
+    }
+
+    #[test]
+    fn should_fail_for_undefined_variable() {
+        assert_eq!(
+            interpolate("${NOPE}", &context()),
+            Err(Error::UndefinedVariable("NOPE".to_string()))
+        );
+        assert_eq!(
+            interpolate("$Recycle.Bin", &context()),
+            Err(Error::UndefinedVariable("Recycle".to_string()))
+        );
+    }
+
+    #[test]
+    fn should_fail_for_invalid_syntax() {
+        assert_eq!(
+            interpolate("${USER", &context()),
+            Err(Error::Unterminated("}"))
+        );
+        assert_eq!(
+            interpolate("${1abc}", &context()),
+            Err(Error::InvalidVariableName("1abc".to_string()))
+        );
+        assert_eq!(
+            interpolate("{{hostname", &context()),
+            Err(Error::Unterminated("}}"))
+        );
+        assert_eq!(
+            interpolate("{{user}}", &context()),
+            Err(Error::UnknownPlaceholder("user".to_string()))
+        );
+    }
+
+    #[test]
+    fn should_fail_without_home_directory() {
+        let context = Context {
+            home_dir: None,
+            ..context()
+        };
+
+        assert_eq!(
+            interpolate("~/Documents", &context),
+            Err(Error::NoHomeDirectory)
+        );
+    }
+
+    #[test]
+    fn should_interpolate_config_settings() {
+        let mut config: Config = toml::from_str(
+            //language=TOML
+            r#"
+            [defaults]
+            exclude-files = ["~/.config/cirrus/excludes.txt"]
+
+            [repositories.data]
+            url = "${XDG_DATA_HOME}/restic"
+            password = { toml = "~/secrets.toml", key = "{{hostname}}" }
+
+            [backups.documents]
+            repository = "data"
+            path = "~/Documents"
+            excludes = ["/home/${USER}/Documents/tmp", "/home/$USER/*.$$$$"]
+            files-from = ["~/files.txt"]
+            "#,
+        )
+        .unwrap();
+
+        config.interpolate(&context()).unwrap();
+
+        assert_eq!(
+            config.defaults.exclude_files,
+            vec![PathBuf::from("/home/user/.config/cirrus/excludes.txt")]
+        );
+        let repo = config.repository(&repo::Name("data".to_string())).unwrap();
+        assert_eq!(repo.url.0, "/home/user/.local/share/restic");
+        assert_eq!(
+            repo.password,
+            repo::Secret::FromToml {
+                toml: "/home/user/secrets.toml".to_string(),
+                key: "{{hostname}}".to_string(),
+            }
+        );
+        let backup = config
+            .backup(&backup::Name("documents".to_string()))
+            .unwrap();
+        assert_eq!(
+            backup.paths,
+            vec![backup::Path("/home/user/Documents".to_string())]
+        );
+        assert_eq!(
+            backup.excludes,
+            vec![
+                backup::Exclude("/home/user/Documents/tmp".to_string()),
+                backup::Exclude("/home/user/*.$$".to_string()),
+            ]
+        );
+        assert_eq!(
+            backup.files_from,
+            vec![PathBuf::from("/home/user/files.txt")]
+        );
+    }
+
+    #[test]
+    fn should_name_setting_in_interpolation_error() {
+        let mut config: Config = toml::from_str(
+            //language=TOML
+            r#"
+            [backups.documents]
+            repository = "data"
+            path = "${NOPE}/Documents"
+            "#,
+        )
+        .unwrap();
+
+        let error = config.interpolate(&context()).unwrap_err();
+
+        assert!(matches!(
+            error,
+            ConfigLoadError::InterpolationError(ref setting, Error::UndefinedVariable(ref name))
+                if setting == "backups.documents.paths" && name == "NOPE"
+        ));
+    }
+}
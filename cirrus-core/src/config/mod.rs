@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
@@ -5,9 +6,16 @@ use std::{
 };
 
 pub mod backup;
+pub mod check;
+pub mod daemon;
+pub mod hosts;
+pub mod include;
+pub mod interpolate;
 pub mod repo;
+pub mod retry;
+pub mod schema;
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(transparent)]
 pub struct Repositories(pub HashMap<repo::Name, repo::Definition>);
 
@@ -21,7 +29,7 @@ impl Repositories {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(transparent)]
 pub struct Backups(pub HashMap<backup::Name, backup::Definition>);
 
@@ -35,16 +43,74 @@ impl Backups {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
+    /// glob patterns of additional configuration files, relative to the configuration file
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// defaults for all backups
+    #[serde(skip_serializing_if = "backup::Defaults::is_empty")]
+    pub defaults: backup::Defaults,
     pub repositories: Repositories,
     pub backups: Backups,
+    #[serde(skip_serializing_if = "daemon::Settings::is_default")]
+    pub daemon: daemon::Settings,
 
     /// path of the configuration file, if the configuration was loaded from a file
     #[serde(skip)]
     pub source: Option<PathBuf>,
+    /// paths of all additional configuration files that were merged into this configuration
+    #[serde(skip)]
+    pub included_files: Vec<PathBuf>,
+    /// patterns of all files that make up this configuration, including ones that don't exist yet
+    #[serde(skip)]
+    watch_patterns: Vec<include::WatchPattern>,
+    /// repositories and backups that were dropped because they don't apply to this host
+    #[serde(skip)]
+    pub filtered: Vec<hosts::Filtered>,
+}
+
+/// Names of settings that aren't known. They're ignored when loading the configuration so newer
+/// settings don't break older versions, but `config check` warns about them.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct UnknownSettings(pub Vec<String>);
+
+impl<'de> Deserialize<'de> for UnknownSettings {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = UnknownSettings;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a table of settings")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut names = Vec::new();
+                while let Some((name, _)) = map.next_entry::<String, serde::de::IgnoredAny>()? {
+                    names.push(name);
+                }
+                names.sort();
+                Ok(UnknownSettings(names))
+            }
+        }
+
+        d.deserialize_map(Visitor)
+    }
+}
+
+impl Serialize for UnknownSettings {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        // unknown settings have no effect, so they're not part of the effective configuration
+        use serde::ser::SerializeMap;
+        s.serialize_map(Some(0))?.end()
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -55,6 +121,29 @@ pub enum ConfigLoadError {
     InvalidConfigFile(PathBuf, #[source] eyre::Report),
     #[error("i/o error reading configuration file {}", .0.display())]
     IoError(PathBuf, std::io::Error),
+    #[error("invalid include pattern '{0}'")]
+    InvalidIncludePattern(String, #[source] glob::PatternError),
+    #[error("includes are not supported in included configuration file {}", .0.display())]
+    NestedInclude(PathBuf),
+    #[error("includes are only supported in configuration files")]
+    IncludeInConfigString,
+    #[error("repository '{}' in {} is already defined", (.0).0, .1.display())]
+    DuplicateRepository(repo::Name, PathBuf),
+    #[error("backup '{}' in {} is already defined", (.0).0, .1.display())]
+    DuplicateBackup(backup::Name, PathBuf),
+    #[error("defaults in {} are already defined", .0.display())]
+    DuplicateDefaults(PathBuf),
+    #[error("daemon settings in {} are already defined", .0.display())]
+    DuplicateDaemonSettings(PathBuf),
+    #[error("can't interpolate setting '{0}'")]
+    InterpolationError(String, #[source] interpolate::Error),
+    #[error("invalid host pattern '{0}'")]
+    InvalidHostPattern(String, #[source] glob::PatternError),
+    #[error(
+        "backup '{}' has nothing to back up, set `path`, `paths`, `files-from`, `files-from-raw` or `stdin-command`",
+        .0.0
+    )]
+    NoBackupSources(backup::Name),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -67,19 +156,156 @@ pub struct UnknownBackup(backup::Name);
 
 impl Config {
     pub fn parse(s: &str) -> Result<Config, ConfigLoadError> {
-        toml::from_str(s).map_err(|e| ConfigLoadError::InvalidConfigString(s.to_owned(), e.into()))
+        let mut config: Config = toml::from_str(s)
+            .map_err(|e| ConfigLoadError::InvalidConfigString(s.to_owned(), e.into()))?;
+        if !config.include.is_empty() {
+            return Err(ConfigLoadError::IncludeInConfigString);
+        }
+        config.filter_hosts_and_interpolate(&interpolate::Context::from_environment())?;
+        config.check_backup_sources()?;
+        config.apply_defaults();
+        Ok(config)
     }
 
+    /// Loads a configuration file and merges all included files and all `.toml` files in its
+    /// drop-in directory (e.g. `backups.d` for `backups.toml`) into it. Repositories and backups
+    /// that don't apply to this host are dropped from each file before merging.
     pub async fn parse_file(p: &Path) -> Result<Config, ConfigLoadError> {
+        let mut config = Self::parse_single_file(p).await?;
+        for included_file in include::included_files(p, &config.include)? {
+            let included = Self::parse_single_file(&included_file).await?;
+            if !included.include.is_empty() {
+                return Err(ConfigLoadError::NestedInclude(included_file));
+            }
+            config.merge(included, &included_file)?;
+            config.included_files.push(included_file);
+        }
+        config.watch_patterns = include::watch_patterns(p, &config.include)?;
+        config.include.clear();
+        config.filter_backups_of_filtered_repositories();
+        config.apply_defaults();
+        config.source = Some(p.to_owned());
+        Ok(config)
+    }
+
+    async fn parse_single_file(p: &Path) -> Result<Config, ConfigLoadError> {
         let config_string = tokio::fs::read_to_string(p)
             .await
             .map_err(|e| ConfigLoadError::IoError(p.to_owned(), e))?;
         let mut config: Config = toml::from_str(&config_string)
             .map_err(|e| ConfigLoadError::InvalidConfigFile(p.to_owned(), e.into()))?;
-        config.source = Some(p.to_owned());
+        config.filter_hosts_and_interpolate(&interpolate::Context::from_environment())?;
+        config.check_backup_sources()?;
+        if let Some(base_dir) = p.parent() {
+            config.resolve_relative_paths(base_dir);
+        }
         Ok(config)
     }
 
+    /// Drops the entries that don't apply to this host before interpolating the rest, so
+    /// settings meant for other hosts, e.g. with environment variables that only exist there,
+    /// can't make loading the configuration fail.
+    fn filter_hosts_and_interpolate(
+        &mut self,
+        context: &interpolate::Context,
+    ) -> Result<(), ConfigLoadError> {
+        self.filter_hosts(context.hostname.as_deref())?;
+        self.filter_backups_of_filtered_repositories();
+        self.interpolate(context)
+    }
+
+    fn check_backup_sources(&self) -> Result<(), ConfigLoadError> {
+        match self
+            .backups
+            .0
+            .iter()
+            .find(|(_, backup)| !backup.has_sources())
+        {
+            Some((name, _)) => Err(ConfigLoadError::NoBackupSources(name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolves relative paths to files referenced by the configuration against the directory
+    /// of the file they're defined in.
+    fn resolve_relative_paths(&mut self, base_dir: &Path) {
+        self.defaults.resolve_relative_paths(base_dir);
+        for repo in self.repositories.0.values_mut() {
+            repo.backup_defaults.resolve_relative_paths(base_dir);
+        }
+        for definition in self.backups.0.values_mut() {
+            definition.resolve_relative_paths(base_dir);
+        }
+    }
+
+    fn merge(&mut self, other: Config, other_path: &Path) -> Result<(), ConfigLoadError> {
+        if !other.defaults.is_empty() {
+            if !self.defaults.is_empty() {
+                return Err(ConfigLoadError::DuplicateDefaults(other_path.to_owned()));
+            }
+            self.defaults = other.defaults;
+        }
+        if !other.daemon.is_default() {
+            if !self.daemon.is_default() {
+                return Err(ConfigLoadError::DuplicateDaemonSettings(
+                    other_path.to_owned(),
+                ));
+            }
+            self.daemon = other.daemon;
+        }
+        for (name, definition) in other.repositories.0 {
+            if self.repositories.0.contains_key(&name) {
+                return Err(ConfigLoadError::DuplicateRepository(
+                    name,
+                    other_path.to_owned(),
+                ));
+            }
+            self.repositories.0.insert(name, definition);
+        }
+        for (name, definition) in other.backups.0 {
+            if self.backups.0.contains_key(&name) {
+                return Err(ConfigLoadError::DuplicateBackup(
+                    name,
+                    other_path.to_owned(),
+                ));
+            }
+            self.backups.0.insert(name, definition);
+        }
+        self.filtered.extend(other.filtered);
+        Ok(())
+    }
+
+    /// Merges the global and per-repository defaults into the backup definitions. The defaults
+    /// are removed afterwards so the configuration only contains the effective settings.
+    fn apply_defaults(&mut self) {
+        let defaults = std::mem::take(&mut self.defaults);
+        for definition in self.backups.0.values_mut() {
+            if definition.ignore_defaults {
+                continue;
+            }
+            match self.repositories.0.get(&definition.repository) {
+                Some(repo) => definition.apply_defaults(&defaults.merge(&repo.backup_defaults)),
+                None => definition.apply_defaults(&defaults),
+            }
+        }
+        for repo in self.repositories.0.values_mut() {
+            repo.backup_defaults = Default::default();
+        }
+    }
+
+    /// The directories to watch for changes to this configuration: the directory of the
+    /// configuration file, the directories of the include patterns and the drop-in directory.
+    /// Directories are watched instead of files so new files matching a pattern are noticed.
+    pub fn watched_dirs(&self) -> Vec<include::WatchedDir> {
+        include::watched_dirs(&self.watch_patterns)
+    }
+
+    /// Whether a change to a path in one of the [watched directories](Config::watched_dirs)
+    /// affects this configuration.
+    pub fn is_affected_by(&self, path: &Path) -> bool {
+        include::affects(&self.watch_patterns, path)
+    }
+
     pub fn repository(&self, name: &repo::Name) -> Result<&repo::Definition, UnknownRepository> {
         self.repositories
             .0
@@ -105,7 +331,8 @@ impl Config {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use maplit::hashmap;
+    use crate::restic::FailureKind;
+    use maplit::{btreemap, hashmap};
     use std::time::Duration;
 
     #[test]
@@ -122,10 +349,23 @@ mod tests {
             parallel-jobs = 6
             build-index = "6 months"
             password = { env-var = "SSH_PASSWORD" }
+            timeout = "2h"
             
             [repositories.sftp.secrets.UNUSED_SECRET]
             env-var = "SECRET_ENV"
 
+            [repositories.sftp.options]
+            limit-upload = 2048
+            compression = "max"
+            cache-dir = "/var/cache/restic"
+            insecure-tls = true
+            extended = { "sftp.command" = "ssh -p 2222 user@host -s sftp" }
+
+            [repositories.sftp.retry]
+            max-attempts = 5
+            initial-delay = "30s"
+            retryable = ["other"]
+
             [backups.home]
             repository = "local"
             path = "/home/user"
@@ -133,10 +373,26 @@ mod tests {
                 "/.local/share/Trash",
                 "/.cache"
             ]
+            iexcludes = ["*.tmp"]
+            exclude-files = ["/etc/cirrus/excludes.txt"]
+            files-from = ["/etc/cirrus/files.txt"]
+            files-from-raw = ["/etc/cirrus/files.bin"]
             exclude-caches = true
             exclude-larger-than = "1G"
             ignore-unreadable-source-files = true
+            read-concurrency = 4
+            skip-if-unchanged = true
             extra-args = ["--one-file-system"]
+            timeout = "6h"
+
+            [backups.home.retention]
+            keep-daily = 7
+            keep-weekly = 4
+            keep-within = "2days"
+            prune = true
+
+            [backups.home.retry]
+            backoff-factor = 3
 
             [[backups.home.triggers]]
             at = "16:00"
@@ -149,6 +405,13 @@ mod tests {
             path = "/srv"
             disable-triggers = true
             triggers = []
+
+            [backups.srv.hooks]
+            before = ["systemctl stop app"]
+            after = ["systemctl start app"]
+            on-failure = ["notify-send 'backup failed'"]
+            timeout = "1m"
+            abort-on-before-failure = false
             "#,
         )
         .unwrap();
@@ -158,6 +421,8 @@ mod tests {
         assert_eq!(
             config,
             Config {
+                include: vec![],
+                defaults: Default::default(),
                 repositories: Repositories(hashmap! {
                     repo::Name("local".to_string()) => repo::Definition {
                         url: repo::Url("/srv/restic-repo".to_string()),
@@ -165,6 +430,12 @@ mod tests {
                         build_index: None,
                         password: repo::Secret::FromEnvVar { env_var: "LOCAL_PASSWORD".to_string() },
                         secrets: HashMap::new(),
+                        backup_defaults: Default::default(),
+                        options: Default::default(),
+                        timeout: None,
+                        retry: None,
+                        hosts: vec![],
+                        unknown_settings: Default::default(),
                     },
                     repo::Name("sftp".to_string()) => repo::Definition {
                         url: repo::Url("sftp:user@host:repo/path".to_string()),
@@ -175,20 +446,48 @@ mod tests {
                             repo::SecretName("UNUSED_SECRET".to_string()) => repo::Secret::FromEnvVar {
                                 env_var: "SECRET_ENV".to_string()
                             }
-                        }
+                        },
+                        backup_defaults: Default::default(),
+                        options: repo::Options {
+                            limit_upload: Some(2048),
+                            compression: Some(repo::Compression::Max),
+                            cache_dir: Some(PathBuf::from("/var/cache/restic")),
+                            insecure_tls: true,
+                            extended: btreemap! {
+                                "sftp.command".to_string() => "ssh -p 2222 user@host -s sftp".to_string(),
+                            },
+                            ..Default::default()
+                        },
+                        timeout: Some(Duration::from_secs(2 * 60 * 60)),
+                        retry: Some(retry::Policy {
+                            max_attempts: 5,
+                            initial_delay: Duration::from_secs(30),
+                            retryable: vec![FailureKind::Other],
+                            ..Default::default()
+                        }),
+                        hosts: vec![],
+                        unknown_settings: Default::default(),
                     },
                 }),
                 backups: Backups(hashmap! {
                     backup::Name("home".to_string()) => backup::Definition {
                         repository: repo::Name("local".to_string()),
-                        path: backup::Path("/home/user".to_string()),
+                        paths: vec![backup::Path("/home/user".to_string())],
                         excludes: vec![
                             backup::Exclude("/.local/share/Trash".to_string()),
                             backup::Exclude("/.cache".to_string()),
                         ],
+                        iexcludes: vec![backup::Exclude("*.tmp".to_string())],
+                        exclude_files: vec![PathBuf::from("/etc/cirrus/excludes.txt")],
+                        files_from: vec![PathBuf::from("/etc/cirrus/files.txt")],
+                        files_from_raw: vec![PathBuf::from("/etc/cirrus/files.bin")],
+                        stdin_command: vec![],
+                        stdin_filename: None,
                         exclude_caches: true,
                         exclude_larger_than: Some("1G".to_string()),
                         ignore_unreadable_source_files: true,
+                        read_concurrency: Some(4),
+                        skip_if_unchanged: true,
                         disable_triggers: false,
                         extra_args: vec!["--one-file-system".to_string()],
                         triggers: vec![
@@ -196,21 +495,63 @@ mod tests {
                                 schedule_dsl::Schedule::from_time_and_days("16:00", "weekday").unwrap()
                             ),
                             backup::Trigger(schedule_dsl::Schedule::from_time("4am").unwrap()),
-                        ]
+                        ],
+                        retention: Some(backup::Retention {
+                            keep_daily: Some(7),
+                            keep_weekly: Some(4),
+                            keep_within: Some(Duration::from_secs(2 * 24 * 60 * 60)),
+                            prune: true,
+                            ..Default::default()
+                        }),
+                        hooks: Default::default(),
+                        ignore_defaults: false,
+                        timeout: Some(Duration::from_secs(6 * 60 * 60)),
+                        retry: Some(retry::Policy {
+                            backoff_factor: 3,
+                            ..Default::default()
+                        }),
+                        hosts: vec![],
+                        unknown_settings: Default::default(),
                     },
                     backup::Name("srv".to_string()) => backup::Definition {
                         repository: repo::Name("sftp".to_string()),
-                        path: backup::Path("/srv".to_string()),
+                        paths: vec![backup::Path("/srv".to_string())],
                         excludes: vec![],
+                        iexcludes: vec![],
+                        exclude_files: vec![],
+                        files_from: vec![],
+                        files_from_raw: vec![],
+                        stdin_command: vec![],
+                        stdin_filename: None,
                         exclude_caches: false,
                         exclude_larger_than: None,
                         ignore_unreadable_source_files: false,
+                        read_concurrency: None,
+                        skip_if_unchanged: false,
                         disable_triggers: true,
                         extra_args: vec![],
-                        triggers: vec![]
+                        triggers: vec![],
+                        retention: None,
+                        hooks: backup::Hooks {
+                            before: vec!["systemctl stop app".to_string()],
+                            after: vec!["systemctl start app".to_string()],
+                            on_success: vec![],
+                            on_failure: vec!["notify-send 'backup failed'".to_string()],
+                            timeout: Duration::from_secs(60),
+                            abort_on_before_failure: false,
+                        },
+                        ignore_defaults: false,
+                        timeout: None,
+                        retry: None,
+                        hosts: vec![],
+                        unknown_settings: Default::default(),
                     },
                 }),
                 source: None,
+                included_files: vec![],
+                watch_patterns: vec![],
+                daemon: Default::default(),
+                filtered: vec![],
             }
         );
     }
@@ -225,13 +566,20 @@ mod tests {
             parallel_jobs = 8
             build_index = "1s"
             password = { env_var = "var" }
+            options = { limit_download = 512, pack_size = 32, cache_dir = "/cache", insecure_tls = true }
+            retry = { max_attempts = 2, initial_delay = "10s", backoff_factor = 1 }
 
             [backups.test]
             repository = "test"
             path = "/"
+            exclude_files = ["/excludes"]
+            files_from = ["/files"]
+            files_from_raw = ["/files-raw"]
             exclude_caches = true
             exclude_larger_than = "1G"
             ignore_unreadable_source_files = true
+            read_concurrency = 2
+            skip_if_unchanged = true
             extra_args = [""]
             disable_triggers = true
             "#,
@@ -243,6 +591,8 @@ mod tests {
         assert_eq!(
             config,
             Config {
+                include: vec![],
+                defaults: Default::default(),
                 repositories: Repositories(hashmap! {
                     repo::Name("test".to_string()) => repo::Definition {
                         url: repo::Url("/url".to_string()),
@@ -250,23 +600,775 @@ mod tests {
                         build_index: Some(Duration::from_secs(1)),
                         password: repo::Secret::FromEnvVar { env_var: "var".to_string() },
                         secrets: HashMap::new(),
+                        backup_defaults: Default::default(),
+                        options: repo::Options {
+                            limit_download: Some(512),
+                            pack_size: Some(32),
+                            cache_dir: Some(PathBuf::from("/cache")),
+                            insecure_tls: true,
+                            ..Default::default()
+                        },
+                        timeout: None,
+                        retry: Some(retry::Policy {
+                            max_attempts: 2,
+                            initial_delay: Duration::from_secs(10),
+                            backoff_factor: 1,
+                            ..Default::default()
+                        }),
+                        hosts: vec![],
+                        unknown_settings: Default::default(),
                     },
                 }),
                 backups: Backups(hashmap! {
                     backup::Name("test".to_string()) => backup::Definition {
                         repository: repo::Name("test".to_string()),
-                        path: backup::Path("/".to_string()),
+                        paths: vec![backup::Path("/".to_string())],
                         excludes: vec![],
+                        iexcludes: vec![],
+                        exclude_files: vec![PathBuf::from("/excludes")],
+                        files_from: vec![PathBuf::from("/files")],
+                        files_from_raw: vec![PathBuf::from("/files-raw")],
+                        stdin_command: vec![],
+                        stdin_filename: None,
                         exclude_caches: true,
                         exclude_larger_than: Some("1G".to_string()),
                         ignore_unreadable_source_files: true,
+                        read_concurrency: Some(2),
+                        skip_if_unchanged: true,
                         disable_triggers: true,
                         extra_args: vec!["".to_string()],
-                        triggers: vec![]
+                        triggers: vec![],
+                        retention: None,
+                        hooks: Default::default(),
+                        ignore_defaults: false,
+                        timeout: None,
+                        retry: None,
+                        hosts: vec![],
+                        unknown_settings: Default::default(),
                     },
                 }),
                 source: None,
+                included_files: vec![],
+                watch_patterns: vec![],
+                daemon: Default::default(),
+                filtered: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn should_parse_multiple_backup_paths() {
+        let input: toml::Value = toml::from_str(
+            //language=TOML
+            r#"
+            [backups.multiple]
+            repository = "test"
+            paths = ["/home/user/Documents", "/home/user/Projects", "/etc"]
+
+            [backups.single]
+            repository = "test"
+            paths = ["/srv"]
+            "#,
+        )
+        .unwrap();
+
+        let config: Config = input.try_into().unwrap();
+
+        let multiple = config
+            .backup(&backup::Name("multiple".to_string()))
+            .unwrap();
+        let single = config.backup(&backup::Name("single".to_string())).unwrap();
+        assert_eq!(
+            multiple.paths,
+            vec![
+                backup::Path("/home/user/Documents".to_string()),
+                backup::Path("/home/user/Projects".to_string()),
+                backup::Path("/etc".to_string()),
+            ]
+        );
+        assert_eq!(single.paths, vec![backup::Path("/srv".to_string())]);
+    }
+
+    #[test]
+    fn should_parse_daemon_settings() {
+        let config = Config::parse(
+            //language=TOML
+            r#"
+            [daemon]
+            secrets-cache-ttl = "15m"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.daemon.secrets_cache_ttl,
+            Duration::from_secs(15 * 60)
+        );
+    }
+
+    #[test]
+    fn should_default_daemon_settings() {
+        let config = Config::parse("").unwrap();
+
+        assert_eq!(config.daemon, daemon::Settings::default());
+        assert_eq!(
+            config.daemon.secrets_cache_ttl,
+            Duration::from_secs(60 * 60)
+        );
+    }
+
+    #[test]
+    fn should_parse_stdin_command_backup() {
+        let config = Config::parse(
+            //language=TOML
+            r#"
+            [backups.db]
+            repository = "test"
+            stdin-command = ["pg_dump", "mydb"]
+            stdin-filename = "mydb.sql"
+            "#,
+        )
+        .unwrap();
+
+        let backup = config.backup(&backup::Name("db".to_string())).unwrap();
+        assert_eq!(backup.paths, vec![]);
+        assert_eq!(
+            backup.stdin_command,
+            vec!["pg_dump".to_string(), "mydb".to_string()]
+        );
+        assert_eq!(backup.stdin_filename, Some("mydb.sql".to_string()));
+    }
+
+    #[test]
+    fn should_parse_command_secrets() {
+        let config = Config::parse(
+            //language=TOML
+            r#"
+            [repositories.test]
+            url = "/srv/repo"
+            password = { command = ["pass", "show", "restic/test"] }
+            secrets.AWS_SECRET_ACCESS_KEY = { command = ["vault", "read", "-field=key", "aws"] }
+            "#,
+        )
+        .unwrap();
+
+        let repo = config.repository(&repo::Name("test".to_string())).unwrap();
+        assert_eq!(
+            repo.password,
+            repo::Secret::FromCommand {
+                command: vec![
+                    "pass".to_string(),
+                    "show".to_string(),
+                    "restic/test".to_string()
+                ]
+            }
+        );
+        assert_eq!(
+            repo.secrets[&repo::SecretName("AWS_SECRET_ACCESS_KEY".to_string())].label(),
+            "command"
+        );
+    }
+
+    #[test]
+    fn should_parse_file_and_systemd_credential_secrets() {
+        let config = Config::parse(
+            //language=TOML
+            r#"
+            [repositories.test]
+            url = "/srv/repo"
+            password = { file = "/etc/cirrus/password" }
+            secrets.B2_ACCOUNT_KEY = { systemd-credential = "b2-key" }
+            secrets.B2_ACCOUNT_ID = { systemd_credential = "b2-id" }
+            "#,
+        )
+        .unwrap();
+
+        let repo = config.repository(&repo::Name("test".to_string())).unwrap();
+        assert_eq!(
+            repo.password,
+            repo::Secret::FromFile {
+                file: "/etc/cirrus/password".to_string()
+            }
+        );
+        assert_eq!(
+            repo.secrets[&repo::SecretName("B2_ACCOUNT_KEY".to_string())],
+            repo::Secret::FromSystemdCredential {
+                name: "b2-key".to_string()
+            }
+        );
+        assert_eq!(
+            repo.secrets[&repo::SecretName("B2_ACCOUNT_ID".to_string())],
+            repo::Secret::FromSystemdCredential {
+                name: "b2-id".to_string()
             }
         );
     }
+
+    #[test]
+    fn should_parse_age_toml_secret() {
+        let config = Config::parse(
+            //language=TOML
+            r#"
+            [repositories.test]
+            url = "/srv/repo"
+            password = { file = "/etc/cirrus/secrets.toml.age", key = "password", identity = { keyring = "age" } }
+            "#,
+        )
+        .unwrap();
+
+        let repo = config.repository(&repo::Name("test".to_string())).unwrap();
+        assert_eq!(
+            repo.password,
+            repo::Secret::FromAgeToml {
+                file: "/etc/cirrus/secrets.toml.age".to_string(),
+                key: "password".to_string(),
+                identity: Box::new(repo::Secret::FromOsKeyring {
+                    keyring: "age".to_string()
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn should_not_parse_age_toml_secret_without_identity() {
+        let result: Result<repo::Secret, _> = toml::from_str(
+            //language=TOML
+            r#"
+            file = "/etc/cirrus/secrets.toml.age"
+            key = "password"
+            "#,
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_not_parse_backup_without_sources() {
+        let result = Config::parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            excludes = ["*.tmp"]
+            "#,
+        );
+
+        assert!(matches!(
+            result,
+            Err(ConfigLoadError::NoBackupSources(backup::Name(name))) if name == "test"
+        ));
+    }
+
+    #[test]
+    fn should_name_path_settings_in_invalid_paths_error() {
+        let result = Config::parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            paths = 42
+            "#,
+        );
+
+        let message = match result {
+            Err(ConfigLoadError::InvalidConfigString(_, e)) => e.to_string(),
+            other => panic!("unexpected result {other:?}"),
+        };
+        assert!(message.contains("`path`"), "{message}");
+        assert!(message.contains("`paths`"), "{message}");
+    }
+
+    #[test]
+    fn should_not_parse_both_path_and_paths() {
+        let input: toml::Value = toml::from_str(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            path = "/srv"
+            paths = ["/etc"]
+            "#,
+        )
+        .unwrap();
+
+        let result: Result<Config, _> = input.try_into();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_collect_unknown_backup_and_repository_settings() {
+        let config = Config::parse(
+            //language=TOML
+            r#"
+            [backups.test]
+            repository = "test"
+            path = "/srv"
+            exclude-cache = true
+
+            [repositories.test]
+            url = "/srv/repo"
+            password = { env-var = "PASSWORD" }
+            parallel-job = 2
+            "#,
+        )
+        .unwrap();
+
+        let backup = config.backup(&backup::Name("test".to_string())).unwrap();
+        let repo = config.repository(&repo::Name("test".to_string())).unwrap();
+        assert_eq!(
+            backup.unknown_settings,
+            UnknownSettings(vec!["exclude-cache".to_string()])
+        );
+        assert_eq!(
+            repo.unknown_settings,
+            UnknownSettings(vec!["parallel-job".to_string()])
+        );
+        assert!(!toml::to_string(&config).unwrap().contains("exclude-cache"));
+    }
+
+    mod includes {
+        use super::*;
+
+        fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+            path
+        }
+
+        #[tokio::test]
+        async fn should_resolve_relative_file_paths_against_defining_file() {
+            let tmp = tempfile::tempdir().unwrap();
+            let config_path = write(
+                tmp.path(),
+                "backups.toml",
+                //language=TOML
+                r#"
+                include = ["shared/*.toml"]
+
+                [backups.documents]
+                repository = "shared"
+                path = "/home/user/Documents"
+                exclude-files = ["excludes.txt", "/etc/excludes.txt"]
+                files-from = ["files.txt"]
+                "#,
+            );
+            write(
+                tmp.path(),
+                "shared/defaults.toml",
+                //language=TOML
+                r#"
+                [defaults]
+                exclude-files = ["common-excludes.txt"]
+                "#,
+            );
+
+            let config = Config::parse_file(&config_path).await.unwrap();
+
+            let backup = config
+                .backup(&backup::Name("documents".to_string()))
+                .unwrap();
+            assert_eq!(
+                backup.exclude_files,
+                vec![
+                    tmp.path().join("shared/common-excludes.txt"),
+                    tmp.path().join("excludes.txt"),
+                    PathBuf::from("/etc/excludes.txt"),
+                ]
+            );
+            assert_eq!(backup.files_from, vec![tmp.path().join("files.txt")]);
+        }
+
+        #[tokio::test]
+        async fn should_filter_hosts_before_merging() {
+            let hostname = hostname::get().unwrap().to_string_lossy().into_owned();
+            let tmp = tempfile::tempdir().unwrap();
+            let config_path = write(
+                tmp.path(),
+                "backups.toml",
+                &format!(
+                    //language=TOML
+                    r#"
+                    [repositories.main]
+                    url = "/srv/this-host"
+                    password = {{ env-var = "PASSWORD" }}
+                    hosts = ["{hostname}"]
+
+                    [backups.home]
+                    repository = "main"
+                    path = "/home"
+                    "#
+                ),
+            );
+            write(
+                tmp.path(),
+                "backups.d/other.toml",
+                &format!(
+                    //language=TOML
+                    r#"
+                    [repositories.main]
+                    url = "/srv/other-host"
+                    password = {{ env-var = "PASSWORD" }}
+                    hosts = ["not-{hostname}"]
+                    "#
+                ),
+            );
+
+            let config = Config::parse_file(&config_path).await.unwrap();
+
+            let repo = config.repository(&repo::Name("main".to_string())).unwrap();
+            assert_eq!(repo.url.0, "/srv/this-host");
+            assert!(config.backup(&backup::Name("home".to_string())).is_ok());
+            assert!(matches!(
+                config.filtered.as_slice(),
+                [hosts::Filtered::Repository { name, .. }] if name.0 == "main"
+            ));
+        }
+
+        #[tokio::test]
+        async fn should_merge_included_files_and_drop_in_dir() {
+            let tmp = tempfile::tempdir().unwrap();
+            let config_path = write(
+                tmp.path(),
+                "backups.toml",
+                //language=TOML
+                r#"
+                include = ["shared/*.toml"]
+
+                [backups.documents]
+                repository = "shared"
+                path = "/home/user/Documents"
+                "#,
+            );
+            let shared = write(
+                tmp.path(),
+                "shared/repos.toml",
+                //language=TOML
+                r#"
+                [repositories.shared]
+                url = "sftp:backup@host:/srv/restic"
+                password = { env-var = "SHARED_PASSWORD" }
+                "#,
+            );
+            let personal = write(
+                tmp.path(),
+                "backups.d/personal.toml",
+                //language=TOML
+                r#"
+                [backups.projects]
+                repository = "shared"
+                path = "/home/user/Projects"
+                "#,
+            );
+
+            let config = Config::parse_file(&config_path).await.unwrap();
+
+            assert!(config.repository(&repo::Name("shared".to_string())).is_ok());
+            assert!(config
+                .backup(&backup::Name("documents".to_string()))
+                .is_ok());
+            assert!(config.backup(&backup::Name("projects".to_string())).is_ok());
+            assert_eq!(config.include, Vec::<String>::new());
+            assert_eq!(config.source, Some(config_path.clone()));
+            assert_eq!(config.included_files, vec![shared.clone(), personal]);
+            assert_eq!(
+                config.watched_dirs(),
+                vec![
+                    include::WatchedDir {
+                        path: tmp.path().to_owned(),
+                        recursive: false,
+                    },
+                    include::WatchedDir {
+                        path: tmp.path().join("shared"),
+                        recursive: false,
+                    },
+                    include::WatchedDir {
+                        path: tmp.path().join("backups.d"),
+                        recursive: false,
+                    },
+                ]
+            );
+        }
+
+        #[tokio::test]
+        async fn should_be_affected_by_new_files_matching_includes() {
+            let tmp = tempfile::tempdir().unwrap();
+            let config_path = write(
+                tmp.path(),
+                "backups.toml",
+                //language=TOML
+                r#"
+                include = ["shared/*.toml"]
+                "#,
+            );
+
+            let config = Config::parse_file(&config_path).await.unwrap();
+
+            assert_eq!(
+                config.watched_dirs(),
+                vec![include::WatchedDir {
+                    path: tmp.path().to_owned(),
+                    recursive: false,
+                }]
+            );
+            assert!(config.is_affected_by(&config_path));
+            assert!(config.is_affected_by(&tmp.path().join("shared")));
+            assert!(config.is_affected_by(&tmp.path().join("shared/repos.toml")));
+            assert!(config.is_affected_by(&tmp.path().join("backups.d")));
+            assert!(config.is_affected_by(&tmp.path().join("backups.d/personal.toml")));
+            assert!(!config.is_affected_by(&tmp.path().join("shared/nested/repos.toml")));
+            assert!(!config.is_affected_by(&tmp.path().join("backups.d/README")));
+            assert!(!config.is_affected_by(&tmp.path().join("other.toml")));
+        }
+
+        #[tokio::test]
+        async fn should_not_merge_duplicate_repository() {
+            let tmp = tempfile::tempdir().unwrap();
+            let config_path = write(
+                tmp.path(),
+                "backups.toml",
+                //language=TOML
+                r#"
+                [repositories.test]
+                url = "/srv/repo"
+                password = { env-var = "PASSWORD" }
+                "#,
+            );
+            write(
+                tmp.path(),
+                "backups.d/duplicate.toml",
+                //language=TOML
+                r#"
+                [repositories.test]
+                url = "/srv/other-repo"
+                password = { env-var = "PASSWORD" }
+                "#,
+            );
+
+            let result = Config::parse_file(&config_path).await;
+
+            assert!(matches!(
+                result,
+                Err(ConfigLoadError::DuplicateRepository(name, _)) if name.0 == "test"
+            ));
+        }
+
+        #[tokio::test]
+        async fn should_not_merge_duplicate_backup() {
+            let tmp = tempfile::tempdir().unwrap();
+            let config_path = write(
+                tmp.path(),
+                "backups.toml",
+                //language=TOML
+                r#"
+                include = ["other.toml"]
+
+                [backups.test]
+                repository = "test"
+                path = "/"
+                "#,
+            );
+            write(
+                tmp.path(),
+                "other.toml",
+                //language=TOML
+                r#"
+                [backups.test]
+                repository = "test"
+                path = "/srv"
+                "#,
+            );
+
+            let result = Config::parse_file(&config_path).await;
+
+            assert!(matches!(
+                result,
+                Err(ConfigLoadError::DuplicateBackup(name, _)) if name.0 == "test"
+            ));
+        }
+
+        #[tokio::test]
+        async fn should_not_allow_nested_includes() {
+            let tmp = tempfile::tempdir().unwrap();
+            let config_path = write(tmp.path(), "backups.toml", r#"include = ["other.toml"]"#);
+            write(tmp.path(), "other.toml", r#"include = ["third.toml"]"#);
+            write(tmp.path(), "third.toml", "");
+
+            let result = Config::parse_file(&config_path).await;
+
+            assert!(matches!(result, Err(ConfigLoadError::NestedInclude(_))));
+        }
+
+        #[test]
+        fn should_not_allow_includes_in_config_string() {
+            let result = Config::parse(r#"include = ["other.toml"]"#);
+
+            assert!(matches!(
+                result,
+                Err(ConfigLoadError::IncludeInConfigString)
+            ));
+        }
+    }
+
+    mod defaults {
+        use super::*;
+
+        #[test]
+        fn should_merge_global_and_repository_defaults_into_backups() {
+            let config = Config::parse(
+                //language=TOML
+                r#"
+                [defaults]
+                excludes = ["/.cache"]
+                exclude-caches = true
+                exclude-larger-than = "1G"
+                extra-args = ["--one-file-system"]
+                retention = { keep-last = 10 }
+
+                [repositories.remote]
+                url = "sftp:host:/srv/repo"
+                password = { env-var = "PASSWORD" }
+                backup-defaults = { excludes = ["/.local/share/Trash"], exclude-larger-than = "100M" }
+
+                [repositories.local]
+                url = "/srv/repo"
+                password = { env-var = "PASSWORD" }
+
+                [backups.remote]
+                repository = "remote"
+                path = "/home/user"
+                excludes = ["/Downloads"]
+
+                [backups.local]
+                repository = "local"
+                path = "/home/user"
+                exclude-larger-than = "10G"
+                ignore-unreadable-source-files = true
+                "#,
+            )
+            .unwrap();
+
+            let remote = config.backup(&backup::Name("remote".to_string())).unwrap();
+            assert_eq!(
+                remote.excludes,
+                vec![
+                    backup::Exclude("/.cache".to_string()),
+                    backup::Exclude("/.local/share/Trash".to_string()),
+                    backup::Exclude("/Downloads".to_string()),
+                ]
+            );
+            assert!(remote.exclude_caches);
+            assert_eq!(remote.exclude_larger_than, Some("100M".to_string()));
+            assert!(!remote.ignore_unreadable_source_files);
+            assert_eq!(remote.extra_args, vec!["--one-file-system".to_string()]);
+
+            let local = config.backup(&backup::Name("local".to_string())).unwrap();
+            assert_eq!(local.excludes, vec![backup::Exclude("/.cache".to_string())]);
+            assert!(local.exclude_caches);
+            assert_eq!(local.exclude_larger_than, Some("10G".to_string()));
+            assert!(local.ignore_unreadable_source_files);
+            assert_eq!(
+                local.retention,
+                Some(backup::Retention {
+                    keep_last: Some(10),
+                    ..Default::default()
+                })
+            );
+        }
+
+        #[test]
+        fn should_prefer_explicit_false_in_repository_defaults() {
+            let config = Config::parse(
+                //language=TOML
+                r#"
+                [defaults]
+                exclude-caches = true
+                ignore-unreadable-source-files = true
+
+                [repositories.test]
+                url = "/srv/repo"
+                password = { env-var = "PASSWORD" }
+                backup-defaults = { exclude-caches = false }
+
+                [backups.test]
+                repository = "test"
+                path = "/"
+                "#,
+            )
+            .unwrap();
+
+            let backup = config.backup(&backup::Name("test".to_string())).unwrap();
+            assert!(!backup.exclude_caches);
+            assert!(backup.ignore_unreadable_source_files);
+        }
+
+        #[test]
+        fn should_enable_settings_in_backup_without_defaults() {
+            let config = Config::parse(
+                //language=TOML
+                r#"
+                [backups.test]
+                repository = "test"
+                path = "/"
+                exclude-caches = true
+                "#,
+            )
+            .unwrap();
+
+            let backup = config.backup(&backup::Name("test".to_string())).unwrap();
+            assert!(backup.exclude_caches);
+            assert!(!backup.ignore_unreadable_source_files);
+        }
+
+        #[test]
+        fn should_not_merge_defaults_into_backup_that_ignores_them() {
+            let config = Config::parse(
+                //language=TOML
+                r#"
+                [defaults]
+                excludes = ["/.cache"]
+                exclude-caches = true
+
+                [backups.test]
+                repository = "test"
+                path = "/"
+                ignore-defaults = true
+                "#,
+            )
+            .unwrap();
+
+            let backup = config.backup(&backup::Name("test".to_string())).unwrap();
+            assert_eq!(backup.excludes, vec![]);
+            assert!(!backup.exclude_caches);
+        }
+
+        #[test]
+        fn should_only_contain_effective_settings_after_merging() {
+            let config = Config::parse(
+                //language=TOML
+                r#"
+                [defaults]
+                excludes = ["/.cache"]
+
+                [repositories.test]
+                url = "/srv/repo"
+                password = { env-var = "PASSWORD" }
+                backup-defaults = { exclude-caches = true }
+
+                [backups.test]
+                repository = "test"
+                path = "/"
+                "#,
+            )
+            .unwrap();
+
+            let printed = toml::to_string_pretty(&config).unwrap();
+            let reparsed = Config::parse(&printed).unwrap();
+
+            assert!(config.defaults.is_empty());
+            assert!(!printed.contains("defaults]"));
+            assert_eq!(reparsed, config);
+        }
+    }
 }
@@ -1,8 +1,14 @@
+use crate::config::{backup, retry, UnknownSettings};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    time::Duration,
+};
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged, deny_unknown_fields)]
 pub enum Secret {
     FromEnvVar {
         #[serde(rename = "env-var", alias = "env_var")]
@@ -15,6 +21,27 @@ pub enum Secret {
         toml: String,
         key: String,
     },
+    /// the trimmed output of a command, e.g. `["pass", "show", "restic"]`
+    FromCommand {
+        command: Vec<String>,
+    },
+    /// a value in an age-encrypted TOML file, decrypted with an identity from another secret;
+    /// the file is encrypted to the recipients listed in a `.recipients` file next to it, if
+    /// there is one, and to the identity's own recipients
+    FromAgeToml {
+        file: String,
+        key: String,
+        identity: Box<Secret>,
+    },
+    /// the whole contents of a file
+    FromFile {
+        file: String,
+    },
+    /// a credential passed by systemd with `LoadCredential=` or `SetCredential=`
+    FromSystemdCredential {
+        #[serde(rename = "systemd-credential", alias = "systemd_credential")]
+        name: String,
+    },
 }
 
 impl Default for Secret {
@@ -31,30 +58,109 @@ impl Secret {
             Secret::FromEnvVar { .. } => "environment variable",
             Secret::FromOsKeyring { .. } => "OS keyring",
             Secret::FromToml { .. } => "TOML value",
+            Secret::FromCommand { .. } => "command",
+            Secret::FromAgeToml { .. } => "age-encrypted TOML value",
+            Secret::FromFile { .. } => "file",
+            Secret::FromSystemdCredential { .. } => "systemd credential",
         }
     }
 }
 
-#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
+#[derive(
+    Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize, JsonSchema,
+)]
 #[serde(transparent)]
 pub struct Name(pub String);
 
-#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(transparent)]
 pub struct Url(pub String);
 
-#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(transparent)]
 pub struct SecretName(pub String);
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Auto,
+    Off,
+    Max,
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Auto => "auto",
+            Compression::Off => "off",
+            Compression::Max => "max",
+        }
+    }
+}
+
+/// restic options that apply to every command run against a repository.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Options {
+    /// upload rate limit in KiB/s
+    #[serde(alias = "limit-upload")]
+    pub limit_upload: Option<u32>,
+    /// download rate limit in KiB/s
+    #[serde(alias = "limit-download")]
+    pub limit_download: Option<u32>,
+    pub compression: Option<Compression>,
+    /// target pack size in MiB
+    #[serde(alias = "pack-size")]
+    pub pack_size: Option<u32>,
+    #[serde(alias = "cache-dir")]
+    pub cache_dir: Option<PathBuf>,
+    /// skip TLS certificate verification, e.g. for a REST server with a self-signed certificate
+    #[serde(default, alias = "insecure-tls")]
+    pub insecure_tls: bool,
+    /// extended options passed as `--option key=value`, e.g. `"sftp.command" = "ssh ..."`
+    #[serde(default)]
+    pub extended: BTreeMap<String, String>,
+}
+
+impl Options {
+    pub fn is_empty(&self) -> bool {
+        self == &Options::default()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "Repository")]
+#[schemars(deny_unknown_fields)]
 pub struct Definition {
     pub url: Url,
     #[serde(alias = "parallel-jobs")]
     pub parallel_jobs: Option<u32>,
     #[serde(default, with = "humantime_serde", alias = "build-index")]
+    #[schemars(with = "Option<String>")]
     pub build_index: Option<Duration>,
     pub password: Secret,
     #[serde(default)]
     pub secrets: HashMap<SecretName, Secret>,
+    /// defaults for all backups to this repository, taking precedence over the global defaults
+    #[serde(
+        default,
+        alias = "backup-defaults",
+        skip_serializing_if = "backup::Defaults::is_empty"
+    )]
+    pub backup_defaults: backup::Defaults,
+    #[serde(default, skip_serializing_if = "Options::is_empty")]
+    pub options: Options,
+    /// stop jobs for this repository that are still running after this long; backups can
+    /// override it
+    #[serde(default, with = "humantime_serde")]
+    #[schemars(with = "Option<String>")]
+    pub timeout: Option<Duration>,
+    /// retry failed jobs for this repository; backups can override it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<retry::Policy>,
+    /// glob patterns of the hostnames this repository applies to, all hosts if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    #[serde(flatten)]
+    #[schemars(skip)]
+    pub unknown_settings: UnknownSettings,
 }
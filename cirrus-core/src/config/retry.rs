@@ -0,0 +1,119 @@
+use crate::restic::FailureKind;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_INITIAL_DELAY: Duration = Duration::from_secs(60);
+const DEFAULT_BACKOFF_FACTOR: u32 = 2;
+
+/// When the daemon runs a failed job again.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "Retry")]
+pub struct Policy {
+    /// how often a job runs at most, including the first attempt
+    #[serde(default = "default_max_attempts", alias = "max-attempts")]
+    pub max_attempts: u32,
+    /// how long to wait before the first retry
+    #[serde(
+        default = "default_initial_delay",
+        with = "humantime_serde",
+        alias = "initial-delay"
+    )]
+    #[schemars(with = "String")]
+    pub initial_delay: Duration,
+    /// the delay is multiplied by this after every retry
+    #[serde(default = "default_backoff_factor", alias = "backoff-factor")]
+    pub backoff_factor: u32,
+    /// the kinds of restic failures that are retried; other errors, e.g. failing hooks, never are
+    #[serde(default = "default_retryable")]
+    pub retryable: Vec<FailureKind>,
+}
+
+fn default_max_attempts() -> u32 {
+    DEFAULT_MAX_ATTEMPTS
+}
+
+fn default_initial_delay() -> Duration {
+    DEFAULT_INITIAL_DELAY
+}
+
+fn default_backoff_factor() -> u32 {
+    DEFAULT_BACKOFF_FACTOR
+}
+
+fn default_retryable() -> Vec<FailureKind> {
+    vec![FailureKind::RepositoryLocked, FailureKind::Other]
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_delay: DEFAULT_INITIAL_DELAY,
+            backoff_factor: DEFAULT_BACKOFF_FACTOR,
+            retryable: default_retryable(),
+        }
+    }
+}
+
+impl Policy {
+    /// How long to wait before running a job again after the given attempt failed, or `None` if
+    /// it shouldn't run again. Attempts are counted from 1.
+    pub fn delay_after(&self, attempt: u32, failure_kind: Option<FailureKind>) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        if !self.retryable.contains(&failure_kind?) {
+            return None;
+        }
+        let factor = self
+            .backoff_factor
+            .saturating_pow(attempt.saturating_sub(1));
+        Some(self.initial_delay.saturating_mul(factor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_back_off_exponentially() {
+        let policy = Policy {
+            max_attempts: 4,
+            ..Default::default()
+        };
+
+        let delays: Vec<_> = (1..=4)
+            .map(|attempt| policy.delay_after(attempt, Some(FailureKind::Other)))
+            .collect();
+
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_secs(60)),
+                Some(Duration::from_secs(120)),
+                Some(Duration::from_secs(240)),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn should_not_retry_failure_that_isnt_retryable() {
+        let policy = Policy::default();
+
+        assert_eq!(
+            policy.delay_after(1, Some(FailureKind::WrongPassword)),
+            None
+        );
+    }
+
+    #[test]
+    fn should_not_retry_errors_other_than_restic_failures() {
+        let policy = Policy::default();
+
+        assert_eq!(policy.delay_after(1, None), None);
+    }
+}
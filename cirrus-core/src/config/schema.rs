@@ -0,0 +1,173 @@
+use crate::config::Config;
+use schemars::{
+    gen::SchemaSettings,
+    schema::{ObjectValidation, RootSchema, Schema, SchemaObject, SubschemaValidation},
+    visit::{visit_schema_object, Visitor},
+};
+
+/// Adds the alternative spelling of every setting, e.g. `exclude-caches` for `exclude_caches`,
+/// since the configuration accepts both dashes and underscores. Settings with a different alias,
+/// e.g. `path` for `paths`, get that alias as well.
+#[derive(Debug, Clone)]
+struct SeparatorAliases;
+
+/// Aliases that aren't just a different separator, by the setting they stand for.
+const ALIASES: &[(&str, &str)] = &[("paths", "path")];
+
+fn separator_alias(name: &str) -> Option<String> {
+    if name.contains('_') {
+        Some(name.replace('_', "-"))
+    } else if name.contains('-') {
+        Some(name.replace('-', "_"))
+    } else {
+        None
+    }
+}
+
+fn requiring(name: &str) -> Schema {
+    Schema::Object(SchemaObject {
+        object: Some(Box::new(ObjectValidation {
+            required: [name.to_owned()].into_iter().collect(),
+            ..Default::default()
+        })),
+        ..Default::default()
+    })
+}
+
+impl Visitor for SeparatorAliases {
+    fn visit_schema_object(&mut self, schema: &mut SchemaObject) {
+        let mut required_aliases = Vec::new();
+        if let Some(object) = &mut schema.object {
+            let aliases = object
+                .properties
+                .keys()
+                .filter_map(|name| separator_alias(name).map(|alias| (name.clone(), alias)))
+                .chain(
+                    ALIASES
+                        .iter()
+                        .filter(|(name, _)| object.properties.contains_key(*name))
+                        .map(|(name, alias)| (name.to_string(), alias.to_string())),
+                )
+                .filter(|(_, alias)| !object.properties.contains_key(alias))
+                .collect::<Vec<_>>();
+            for (name, alias) in aliases {
+                let property = object.properties[&name].clone();
+                object.properties.insert(alias.clone(), property);
+                // either spelling satisfies a required setting
+                if object.required.remove(&name) {
+                    required_aliases.push((name, alias));
+                }
+            }
+        }
+        for (name, alias) in required_aliases {
+            schema
+                .subschemas()
+                .all_of
+                .get_or_insert_with(Vec::new)
+                .push(Schema::Object(SchemaObject {
+                    subschemas: Some(Box::new(SubschemaValidation {
+                        any_of: Some(vec![requiring(&name), requiring(&alias)]),
+                        ..Default::default()
+                    })),
+                    ..Default::default()
+                }));
+        }
+
+        visit_schema_object(self, schema);
+    }
+}
+
+/// Generates a JSON Schema describing the configuration file format.
+pub fn config_schema() -> RootSchema {
+    let generator = SchemaSettings::draft07()
+        .with(|settings| {
+            // TOML has no null values
+            settings.option_add_null_type = false;
+        })
+        .into_generator();
+    let mut schema = generator.into_root_schema_for::<Config>();
+    SeparatorAliases.visit_root_schema(&mut schema);
+    schema
+}
+
+/// The configuration JSON Schema as pretty-printed JSON.
+pub fn config_schema_json() -> String {
+    let mut json =
+        serde_json::to_string_pretty(&config_schema()).expect("schema should be serializable");
+    json.push('\n');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn should_match_checked_in_schema() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("config.schema.json");
+        let schema = config_schema_json();
+        if std::env::var_os("UPDATE_CONFIG_SCHEMA").is_some() {
+            std::fs::write(&path, &schema).unwrap();
+        }
+
+        let checked_in = std::fs::read_to_string(&path).unwrap_or_default();
+
+        assert!(
+            checked_in == schema,
+            "{} is out of date, regenerate it by running the tests with UPDATE_CONFIG_SCHEMA=1",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn should_accept_both_separators_in_settings() {
+        let schema = config_schema();
+
+        let backup = schema.definitions["Backup"].clone().into_object();
+        let properties = &backup.object.unwrap().properties;
+        assert!(properties.contains_key("exclude_caches"));
+        assert!(properties.contains_key("exclude-caches"));
+    }
+
+    #[test]
+    fn should_accept_other_aliases() {
+        let schema = config_schema();
+
+        let backup = schema.definitions["Backup"].clone().into_object();
+        let properties = &backup.object.unwrap().properties;
+        assert_eq!(properties["path"], properties["paths"]);
+    }
+
+    #[test]
+    fn should_deny_unknown_backup_and_repository_settings() {
+        let schema = serde_json::to_value(config_schema()).unwrap();
+
+        assert_eq!(
+            schema["definitions"]["Backup"]["additionalProperties"],
+            false
+        );
+        assert_eq!(
+            schema["definitions"]["Repository"]["additionalProperties"],
+            false
+        );
+    }
+
+    #[test]
+    fn should_require_either_spelling_of_required_setting() {
+        let schema = config_schema();
+
+        let secret = serde_json::to_value(&schema.definitions["Secret"]).unwrap();
+        let env_var_variant = secret["anyOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|variant| variant["properties"].get("env-var").is_some())
+            .unwrap();
+        assert_eq!(env_var_variant.get("required"), None);
+        assert_eq!(
+            env_var_variant["allOf"][0]["anyOf"],
+            serde_json::json!([{"required": ["env-var"]}, {"required": ["env_var"]}])
+        );
+    }
+}
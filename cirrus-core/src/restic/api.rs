@@ -0,0 +1,596 @@
+use super::{Error, Options, Output, Restic, TERMINATE_GRACE_PERIOD};
+use crate::{
+    config::backup,
+    secrets::{self, RepoWithSecrets},
+    tag::Tag,
+};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use std::{ffi::OsString, future::Future, path::Path};
+use time::OffsetDateTime;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub short_id: String,
+    #[serde(with = "time::serde::iso8601")]
+    pub time: OffsetDateTime,
+    pub parent: Option<String>,
+    pub tree: String,
+    pub paths: Vec<backup::Path>,
+    pub hostname: String,
+    #[serde(default)]
+    pub username: String,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    #[serde(default)]
+    pub excludes: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+}
+
+/// The snapshots restic kept and removed for one group of snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ForgetGroup {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub tags: Vec<Tag>,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub paths: Vec<backup::Path>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub keep: Vec<Snapshot>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub remove: Vec<Snapshot>,
+}
+
+impl ForgetGroup {
+    /// Parses the JSON output of `restic forget --json`.
+    pub fn parse_all(json: &str) -> Result<Vec<ForgetGroup>, Error> {
+        serde_json::from_str(json).map_err(Error::InvalidOutput)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StatsMode {
+    RestoreSize,
+    FilesByContents,
+    RawData,
+    BlobsPerFile,
+}
+
+impl StatsMode {
+    fn arg(&self) -> &'static str {
+        match self {
+            StatsMode::RestoreSize => "restore-size",
+            StatsMode::FilesByContents => "files-by-contents",
+            StatsMode::RawData => "raw-data",
+            StatsMode::BlobsPerFile => "blobs-per-file",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Stats {
+    pub total_size: u64,
+    #[serde(default)]
+    pub total_file_count: u64,
+    #[serde(default)]
+    pub total_blob_count: u64,
+    #[serde(default)]
+    pub snapshots_count: u64,
+    /// only reported in `raw-data` mode by restic versions with compression support
+    #[serde(default)]
+    pub total_uncompressed_size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DiffChange {
+    pub path: String,
+    /// `+` for added, `-` for removed, `M` for modified content, `U` for updated metadata and
+    /// `T` for a changed type
+    pub modifier: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct DiffStat {
+    #[serde(default)]
+    pub files: u64,
+    #[serde(default)]
+    pub dirs: u64,
+    #[serde(default)]
+    pub others: u64,
+    #[serde(default)]
+    pub data_blobs: u64,
+    #[serde(default)]
+    pub tree_blobs: u64,
+    #[serde(default)]
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct DiffStatistics {
+    pub source_snapshot: String,
+    pub target_snapshot: String,
+    #[serde(default)]
+    pub changed_files: u64,
+    #[serde(default)]
+    pub added: DiffStat,
+    #[serde(default)]
+    pub removed: DiffStat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "message_type", rename_all = "snake_case")]
+enum DiffMessage {
+    Change(DiffChange),
+    Statistics(DiffStatistics),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    pub changes: Vec<DiffChange>,
+    pub statistics: DiffStatistics,
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Parses the groups from the JSON line of `restic forget --json`. Pruning afterwards prints
+/// more output that isn't JSON, which is logged. Some restic versions don't print the JSON line
+/// at all if there are no snapshots to forget, so that means there are no groups.
+fn parse_forget_output(output: &str) -> Result<Vec<ForgetGroup>, Error> {
+    let mut groups = None;
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        if groups.is_none() && line.starts_with('[') {
+            groups = Some(ForgetGroup::parse_all(line)?);
+        } else {
+            tracing::info!("{}", line);
+        }
+    }
+    Ok(groups.unwrap_or_default())
+}
+
+fn parse_diff(output: &str) -> Result<Diff, Error> {
+    let mut changes = Vec::new();
+    let mut statistics = None;
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str(line).map_err(Error::InvalidOutput)? {
+            DiffMessage::Change(change) => changes.push(change),
+            DiffMessage::Statistics(s) => statistics = Some(s),
+        }
+    }
+    Ok(Diff {
+        changes,
+        statistics: statistics.ok_or(Error::IncompleteOutput)?,
+    })
+}
+
+impl Restic {
+    /// Runs restic to completion and returns its standard output. Standard error is logged.
+    /// Once `cancellation` completes, restic is terminated and [Error::Cancelled] is returned.
+    pub(super) async fn output(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        args: &[OsString],
+        json: bool,
+        cancellation: impl Future,
+    ) -> Result<Vec<u8>, Error> {
+        let mut process = self.run(
+            Some(repo_with_secrets),
+            args,
+            &Options {
+                stdout: Output::Capture,
+                stderr: Output::Capture,
+                json,
+                ..Default::default()
+            },
+        )?;
+        let mut stdout = process
+            .stdout()
+            .take()
+            .expect("should be present based on params");
+        let stderr = process
+            .stderr()
+            .take()
+            .expect("should be present based on params");
+
+        let mut buf = Vec::new();
+        let read = {
+            let read_output = async {
+                let log_stderr = async {
+                    let mut lines = BufReader::new(stderr).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        tracing::warn!("{}", secrets::redact(&line));
+                        process.inspect_stderr(&line);
+                    }
+                };
+                let (read, _) = tokio::join!(stdout.read_to_end(&mut buf), log_stderr);
+                read
+            };
+            tokio::select! {
+                read = read_output => Some(read),
+                _ = cancellation => None,
+            }
+        };
+        match read {
+            Some(read) => {
+                read.map_err(Error::SubprocessIoError)?;
+                process.check_wait().await?;
+                Ok(buf)
+            }
+            None => {
+                process.terminate(TERMINATE_GRACE_PERIOD).await?;
+                Err(Error::Cancelled)
+            }
+        }
+    }
+
+    pub(super) async fn json<T: DeserializeOwned>(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        args: &[OsString],
+        cancellation: impl Future,
+    ) -> Result<T, Error> {
+        let output = self
+            .output(repo_with_secrets, args, true, cancellation)
+            .await?;
+        serde_json::from_slice(&output).map_err(Error::InvalidOutput)
+    }
+
+    /// All snapshots in the repository, or only those of one backup. Restic is terminated once
+    /// `cancellation` completes.
+    pub async fn snapshots(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        backup: Option<&backup::Name>,
+        cancellation: impl Future,
+    ) -> Result<Vec<Snapshot>, Error> {
+        let mut args = vec![OsString::from("snapshots")];
+        if let Some(backup) = backup {
+            args.push("--tag".into());
+            args.push(Tag::for_backup(backup).0.into());
+        }
+        self.json(repo_with_secrets, &args, cancellation).await
+    }
+
+    /// Forgets old snapshots of the backup and returns what restic kept and removed. Restic is
+    /// terminated once `cancellation` completes.
+    pub async fn forget_snapshots(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        name: &backup::Name,
+        retention: &backup::Retention,
+        cancellation: impl Future,
+    ) -> Result<Vec<ForgetGroup>, Error> {
+        let args = Self::forget_args(name, retention)
+            .into_iter()
+            .map(OsString::from)
+            .collect::<Vec<_>>();
+        let output = self
+            .output(repo_with_secrets, &args, true, cancellation)
+            .await?;
+        parse_forget_output(&String::from_utf8_lossy(&output))
+    }
+
+    /// Checks the repository, optionally also reading a subset of the data, e.g. `10%`. Restic is
+    /// terminated once `cancellation` completes.
+    pub async fn check(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        read_data_subset: Option<&str>,
+        cancellation: impl Future,
+    ) -> Result<(), Error> {
+        let mut args = vec![OsString::from("check")];
+        if let Some(subset) = read_data_subset {
+            args.push("--read-data-subset".into());
+            args.push(subset.into());
+        }
+        self.output(repo_with_secrets, &args, false, cancellation)
+            .await?;
+        Ok(())
+    }
+
+    /// Removes unreferenced data from the repository. Restic is terminated once `cancellation`
+    /// completes.
+    pub async fn prune(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        cancellation: impl Future,
+    ) -> Result<(), Error> {
+        self.output(repo_with_secrets, &["prune".into()], false, cancellation)
+            .await?;
+        Ok(())
+    }
+
+    /// Size statistics of the repository. Restic is terminated once `cancellation` completes.
+    pub async fn stats(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        mode: StatsMode,
+        cancellation: impl Future,
+    ) -> Result<Stats, Error> {
+        self.json(
+            repo_with_secrets,
+            &["stats".into(), "--mode".into(), mode.arg().into()],
+            cancellation,
+        )
+        .await
+    }
+
+    /// Restores the snapshot into the target directory, optionally only the included paths.
+    /// Restic is terminated once `cancellation` completes.
+    pub async fn restore(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        snapshot_id: &str,
+        target: &Path,
+        includes: &[String],
+        cancellation: impl Future,
+    ) -> Result<(), Error> {
+        let mut args = vec![
+            OsString::from("restore"),
+            snapshot_id.into(),
+            "--target".into(),
+            target.into(),
+        ];
+        for include in includes {
+            args.push("--include".into());
+            args.push(include.into());
+        }
+        self.output(repo_with_secrets, &args, false, cancellation)
+            .await?;
+        Ok(())
+    }
+
+    /// The changes between two snapshots. Restic is terminated once `cancellation` completes.
+    pub async fn diff(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        from_snapshot_id: &str,
+        to_snapshot_id: &str,
+        cancellation: impl Future,
+    ) -> Result<Diff, Error> {
+        let output = self
+            .output(
+                repo_with_secrets,
+                &[
+                    "diff".into(),
+                    from_snapshot_id.into(),
+                    to_snapshot_id.into(),
+                ],
+                true,
+                cancellation,
+            )
+            .await?;
+        parse_diff(&String::from_utf8_lossy(&output))
+    }
+
+    /// Initializes a new repository. Restic is terminated once `cancellation` completes.
+    pub async fn init(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        cancellation: impl Future,
+    ) -> Result<(), Error> {
+        self.output(repo_with_secrets, &["init".into()], false, cancellation)
+            .await?;
+        Ok(())
+    }
+
+    /// Removes stale locks, or all locks if `remove_all` is set. Restic is terminated once
+    /// `cancellation` completes.
+    pub async fn unlock(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        remove_all: bool,
+        cancellation: impl Future,
+    ) -> Result<(), Error> {
+        let mut args = vec![OsString::from("unlock")];
+        if remove_all {
+            args.push("--remove-all".into());
+        }
+        self.output(repo_with_secrets, &args, false, cancellation)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn should_parse_minimal_snapshot() {
+        // language=JSON
+        let json = r#"
+          {
+            "time": "2020-08-03T23:05:57.5629523+02:00",
+            "tree": "86fb8a32a6ac5c10fa2e21dbf140d8c40e5373dd891cc7926e067f125d6ad750",
+            "paths": [
+              "/root"
+            ],
+            "hostname": "testhost",
+            "username": "root",
+            "id": "3cc47d6ab8569b5bf8287d2b665b99f5279b2854a4c2a558676bae9e2741371d",
+            "short_id": "3cc47d6a"
+        }"#;
+
+        let result: Snapshot = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            result,
+            Snapshot {
+                id: "3cc47d6ab8569b5bf8287d2b665b99f5279b2854a4c2a558676bae9e2741371d".to_string(),
+                short_id: "3cc47d6a".to_string(),
+                time: datetime!(2020-08-03 23:05:57.5629523 +02:00),
+                parent: None,
+                tree: "86fb8a32a6ac5c10fa2e21dbf140d8c40e5373dd891cc7926e067f125d6ad750"
+                    .to_string(),
+                paths: vec![backup::Path("/root".to_string())],
+                hostname: "testhost".to_string(),
+                username: "root".to_string(),
+                uid: None,
+                gid: None,
+                excludes: vec![],
+                tags: vec![],
+            }
+        )
+    }
+
+    #[test]
+    fn should_parse_complete_snapshot() {
+        // language=JSON
+        let json = r#"
+          {
+            "time": "2020-08-03T23:05:57.5629523+02:00",
+            "tree": "86fb8a32a6ac5c10fa2e21dbf140d8c40e5373dd891cc7926e067f125d6ad750",
+            "parent": "2e8ad31a949d004194b97031427161b5b9c5a846359629b4c0671e2bbb26e6c4",
+            "paths": [
+              "/"
+            ],
+            "hostname": "host",
+            "username": "testuser",
+            "uid": 1001,
+            "gid": 1002,
+            "excludes": [
+              ".cache"
+            ],
+            "tags": [
+              "tag1",
+              "tag2.tag"
+            ],
+            "id": "3cc47d6ab8569b5bf8287d2b665b99f5279b2854a4c2a558676bae9e2741371d",
+            "short_id": "3cc47d6a"
+        }"#;
+
+        let result: Snapshot = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            result,
+            Snapshot {
+                id: "3cc47d6ab8569b5bf8287d2b665b99f5279b2854a4c2a558676bae9e2741371d".to_string(),
+                short_id: "3cc47d6a".to_string(),
+                time: datetime!(2020-08-03 23:05:57.5629523 +02:00),
+                parent: Some(
+                    "2e8ad31a949d004194b97031427161b5b9c5a846359629b4c0671e2bbb26e6c4".to_string()
+                ),
+                tree: "86fb8a32a6ac5c10fa2e21dbf140d8c40e5373dd891cc7926e067f125d6ad750"
+                    .to_string(),
+                paths: vec![backup::Path("/".to_string())],
+                hostname: "host".to_string(),
+                username: "testuser".to_string(),
+                uid: Some(1001),
+                gid: Some(1002),
+                excludes: vec![".cache".to_string()],
+                tags: vec![Tag("tag1".to_string()), Tag("tag2.tag".to_string())],
+            }
+        )
+    }
+
+    #[test]
+    fn should_parse_forget_output() {
+        // language=JSON
+        let json = r#"[{"tags":["cirrus.test"],"host":"host","paths":["/"],"keep":[{"time":"2023-01-14T12:00:00+01:00","tree":"tree2","paths":["/"],"hostname":"host","username":"user","id":"id2","short_id":"id2"}],"remove":null,"reasons":[]}]"#;
+
+        let groups = ForgetGroup::parse_all(json).unwrap();
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].tags, vec![Tag("cirrus.test".to_string())]);
+        assert_eq!(groups[0].keep.len(), 1);
+        assert_eq!(groups[0].keep[0].id, "id2");
+        assert!(groups[0].remove.is_empty());
+    }
+
+    #[test]
+    fn should_parse_forget_output_followed_by_prune_output() {
+        let output = concat!(
+            r#"[{"tags":["cirrus.test"],"host":"host","paths":["/"],"keep":null,"remove":null}]"#,
+            "\n",
+            "loading indexes...\n",
+            "done\n",
+        );
+
+        let groups = parse_forget_output(output).unwrap();
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].host, "host");
+    }
+
+    #[test]
+    fn should_parse_forget_output_without_json_as_no_groups() {
+        let groups = parse_forget_output("loading indexes...\n").unwrap();
+
+        assert_eq!(groups, vec![]);
+    }
+
+    #[test]
+    fn should_parse_empty_forget_output_as_no_groups() {
+        let groups = parse_forget_output("").unwrap();
+
+        assert_eq!(groups, vec![]);
+    }
+
+    #[test]
+    fn should_parse_stats() {
+        // language=JSON
+        let json = r#"{"total_size":4096,"total_file_count":12,"snapshots_count":3}"#;
+
+        let stats: Stats = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            stats,
+            Stats {
+                total_size: 4096,
+                total_file_count: 12,
+                total_blob_count: 0,
+                snapshots_count: 3,
+                total_uncompressed_size: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn should_parse_diff() {
+        let output = concat!(
+            r#"{"message_type":"change","path":"/home/user/new","modifier":"+"}"#,
+            "\n",
+            r#"{"message_type":"change","path":"/home/user/old","modifier":"-"}"#,
+            "\n",
+            r#"{"message_type":"statistics","source_snapshot":"a","target_snapshot":"b","changed_files":0,"added":{"files":1,"dirs":0,"others":0,"data_blobs":1,"tree_blobs":1,"bytes":10},"removed":{"files":1,"dirs":0,"others":0,"data_blobs":1,"tree_blobs":1,"bytes":20}}"#,
+            "\n",
+        );
+
+        let diff = parse_diff(output).unwrap();
+
+        assert_eq!(
+            diff.changes,
+            vec![
+                DiffChange {
+                    path: "/home/user/new".to_string(),
+                    modifier: "+".to_string()
+                },
+                DiffChange {
+                    path: "/home/user/old".to_string(),
+                    modifier: "-".to_string()
+                },
+            ]
+        );
+        assert_eq!(diff.statistics.added.bytes, 10);
+        assert_eq!(diff.statistics.removed.bytes, 20);
+    }
+
+    #[test]
+    fn should_not_parse_diff_without_statistics() {
+        let output = r#"{"message_type":"change","path":"/home/user/new","modifier":"+"}"#;
+
+        let result = parse_diff(output);
+
+        assert!(matches!(result, Err(Error::IncompleteOutput)));
+    }
+}
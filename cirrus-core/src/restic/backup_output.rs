@@ -0,0 +1,231 @@
+use serde::{Deserialize, Deserializer};
+use std::time::Duration;
+
+/// A message printed by `restic backup --json`, one per line.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "message_type", rename_all = "snake_case")]
+pub enum BackupMessage {
+    Status(BackupStatus),
+    VerboseStatus(BackupVerboseStatus),
+    Error(BackupError),
+    Summary(BackupSummary),
+}
+
+impl BackupMessage {
+    pub fn parse(line: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(line)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BackupStatus {
+    #[serde(default, rename = "seconds_elapsed", deserialize_with = "seconds")]
+    pub elapsed: Duration,
+    /// only present once restic can estimate it
+    #[serde(
+        default,
+        rename = "seconds_remaining",
+        deserialize_with = "optional_seconds"
+    )]
+    pub remaining: Option<Duration>,
+    #[serde(default)]
+    pub percent_done: f64,
+    #[serde(default)]
+    pub total_files: u64,
+    #[serde(default)]
+    pub files_done: u64,
+    #[serde(default)]
+    pub total_bytes: u64,
+    #[serde(default)]
+    pub bytes_done: u64,
+    #[serde(default)]
+    pub error_count: u64,
+    #[serde(default)]
+    pub current_files: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BackupVerboseStatus {
+    pub action: String,
+    #[serde(default)]
+    pub item: String,
+    #[serde(default, deserialize_with = "seconds")]
+    pub duration: Duration,
+    #[serde(default)]
+    pub data_size: u64,
+    #[serde(default)]
+    pub metadata_size: u64,
+    #[serde(default)]
+    pub total_files: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BackupError {
+    #[serde(default)]
+    pub error: BackupErrorDetails,
+    /// the backup stage the error happened in, e.g. `scan` or `archival`
+    #[serde(default)]
+    pub during: String,
+    #[serde(default)]
+    pub item: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct BackupErrorDetails {
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BackupSummary {
+    #[serde(default)]
+    pub files_new: u64,
+    #[serde(default)]
+    pub files_changed: u64,
+    #[serde(default)]
+    pub files_unmodified: u64,
+    #[serde(default)]
+    pub dirs_new: u64,
+    #[serde(default)]
+    pub dirs_changed: u64,
+    #[serde(default)]
+    pub dirs_unmodified: u64,
+    #[serde(default)]
+    pub data_added: u64,
+    #[serde(default)]
+    pub total_files_processed: u64,
+    #[serde(default)]
+    pub total_bytes_processed: u64,
+    #[serde(default, rename = "total_duration", deserialize_with = "seconds")]
+    pub duration: Duration,
+    /// not set for dry runs
+    #[serde(default)]
+    pub snapshot_id: Option<String>,
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let seconds = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom)
+}
+
+fn optional_seconds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    seconds(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_status() {
+        // language=JSON
+        let json = r#"{"message_type":"status","seconds_elapsed":3,"seconds_remaining":12,"percent_done":0.2,"total_files":100,"files_done":20,"total_bytes":1000,"bytes_done":200,"current_files":["/home/user/file"]}"#;
+
+        let message = BackupMessage::parse(json).unwrap();
+
+        assert_eq!(
+            message,
+            BackupMessage::Status(BackupStatus {
+                elapsed: Duration::from_secs(3),
+                remaining: Some(Duration::from_secs(12)),
+                percent_done: 0.2,
+                total_files: 100,
+                files_done: 20,
+                total_bytes: 1000,
+                bytes_done: 200,
+                error_count: 0,
+                current_files: vec!["/home/user/file".to_owned()],
+            })
+        );
+    }
+
+    #[test]
+    fn should_parse_status_without_optional_fields() {
+        // language=JSON
+        let json = r#"{"message_type":"status","percent_done":0,"total_files":1,"total_bytes":5}"#;
+
+        let message = BackupMessage::parse(json).unwrap();
+
+        assert!(matches!(
+            message,
+            BackupMessage::Status(BackupStatus {
+                remaining: None,
+                total_files: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn should_parse_verbose_status() {
+        // language=JSON
+        let json = r#"{"message_type":"verbose_status","action":"new","item":"/home/user/file","duration":0.5,"data_size":42,"metadata_size":0,"total_files":1}"#;
+
+        let message = BackupMessage::parse(json).unwrap();
+
+        assert_eq!(
+            message,
+            BackupMessage::VerboseStatus(BackupVerboseStatus {
+                action: "new".to_owned(),
+                item: "/home/user/file".to_owned(),
+                duration: Duration::from_millis(500),
+                data_size: 42,
+                metadata_size: 0,
+                total_files: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn should_parse_error() {
+        // language=JSON
+        let json = r#"{"message_type":"error","error":{"message":"open /root/secret: permission denied"},"during":"archival","item":"/root/secret"}"#;
+
+        let message = BackupMessage::parse(json).unwrap();
+
+        assert_eq!(
+            message,
+            BackupMessage::Error(BackupError {
+                error: BackupErrorDetails {
+                    message: "open /root/secret: permission denied".to_owned()
+                },
+                during: "archival".to_owned(),
+                item: "/root/secret".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn should_parse_summary() {
+        // language=JSON
+        let json = r#"{"message_type":"summary","files_new":2,"files_changed":1,"files_unmodified":10,"dirs_new":1,"dirs_changed":0,"dirs_unmodified":3,"data_blobs":3,"tree_blobs":2,"data_added":2048,"total_files_processed":13,"total_bytes_processed":4096,"total_duration":1.25,"snapshot_id":"8f2a3b4c"}"#;
+
+        let message = BackupMessage::parse(json).unwrap();
+
+        assert_eq!(
+            message,
+            BackupMessage::Summary(BackupSummary {
+                files_new: 2,
+                files_changed: 1,
+                files_unmodified: 10,
+                dirs_new: 1,
+                dirs_changed: 0,
+                dirs_unmodified: 3,
+                data_added: 2048,
+                total_files_processed: 13,
+                total_bytes_processed: 4096,
+                duration: Duration::from_millis(1250),
+                snapshot_id: Some("8f2a3b4c".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn should_not_parse_plain_text() {
+        let result = BackupMessage::parse("using parent snapshot 8f2a3b4c");
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// Why restic failed, as far as it can be told from its exit status and error output.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    RepositoryNotFound,
+    RepositoryLocked,
+    WrongPassword,
+    UnreadableSourceFiles,
+    Interrupted,
+    Other,
+}
+
+impl FailureKind {
+    /// Classifies the exit codes restic uses for specific failures. Restic before 0.17 exits
+    /// with 1 for most of these, so they have to be told apart by [FailureKind::from_stderr].
+    pub fn from_exit_code(code: Option<i32>) -> Option<Self> {
+        match code? {
+            3 => Some(FailureKind::UnreadableSourceFiles),
+            10 => Some(FailureKind::RepositoryNotFound),
+            11 => Some(FailureKind::RepositoryLocked),
+            12 => Some(FailureKind::WrongPassword),
+            130 => Some(FailureKind::Interrupted),
+            _ => None,
+        }
+    }
+
+    /// Classifies a line of restic's error output.
+    ///
+    /// Restic before 0.17 reports any backend error while opening the repository as "unable to
+    /// open config file", e.g. a network outage, so that line only means a missing repository if
+    /// the config file itself doesn't exist.
+    pub fn from_stderr(line: &str) -> Option<Self> {
+        const MISSING_FILE_PATTERNS: &[&str] = &["no such file or directory", "does not exist"];
+        const PATTERNS: &[(&str, FailureKind)] = &[
+            ("wrong password or no key found", FailureKind::WrongPassword),
+            (
+                "repository is already locked",
+                FailureKind::RepositoryLocked,
+            ),
+            (
+                "unable to create lock in backend",
+                FailureKind::RepositoryLocked,
+            ),
+            (
+                "at least one source file could not be read",
+                FailureKind::UnreadableSourceFiles,
+            ),
+        ];
+        if line.contains("unable to open config file") {
+            let is_missing = MISSING_FILE_PATTERNS
+                .iter()
+                .any(|pattern| line.contains(pattern));
+            return is_missing.then_some(FailureKind::RepositoryNotFound);
+        }
+        PATTERNS
+            .iter()
+            .find(|(pattern, _)| line.contains(pattern))
+            .map(|(_, kind)| *kind)
+    }
+
+    fn description(&self) -> Option<&'static str> {
+        match self {
+            FailureKind::RepositoryNotFound => Some("the repository does not exist"),
+            FailureKind::RepositoryLocked => Some("the repository is locked"),
+            FailureKind::WrongPassword => Some("wrong password or no key found"),
+            FailureKind::UnreadableSourceFiles => Some("some source files could not be read"),
+            FailureKind::Interrupted => Some("restic was interrupted"),
+            FailureKind::Other => None,
+        }
+    }
+
+    /// What the user can do about the failure.
+    pub fn suggestion(&self) -> Option<&'static str> {
+        match self {
+            FailureKind::RepositoryNotFound => Some(
+                "check the repository URL, or initialize a new repository with `cirrus restic -r REPOSITORY init`",
+            ),
+            FailureKind::RepositoryLocked => Some(
+                "if no other restic process uses the repository, remove stale locks with `cirrus restic -r REPOSITORY unlock`",
+            ),
+            FailureKind::WrongPassword => Some(
+                "set the correct repository password with `cirrus secret set REPOSITORY`",
+            ),
+            FailureKind::UnreadableSourceFiles => Some(
+                "check the permissions of the source files, or set `ignore_unreadable_source_files = true` for the backup",
+            ),
+            FailureKind::Interrupted | FailureKind::Other => None,
+        }
+    }
+}
+
+/// A failed restic run.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct Failure {
+    pub code: Option<i32>,
+    pub kind: FailureKind,
+}
+
+impl Failure {
+    /// The exit code takes precedence over the error output since it's more specific.
+    pub(crate) fn new(code: Option<i32>, stderr_kind: Option<FailureKind>) -> Self {
+        let kind = FailureKind::from_exit_code(code)
+            .or(stderr_kind)
+            .unwrap_or(FailureKind::Other);
+        Failure { code, kind }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.kind.description(), self.code) {
+            (Some(description), Some(code)) => {
+                write!(f, "restic failed: {} (exit status {})", description, code)
+            }
+            (Some(description), None) => write!(f, "restic failed: {}", description),
+            (None, Some(code)) => write!(f, "restic exited with error status {}", code),
+            (None, None) => write!(f, "restic exited with unknown error status"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_classify_structured_exit_codes() {
+        assert_eq!(
+            Failure::new(Some(12), None).kind,
+            FailureKind::WrongPassword
+        );
+        assert_eq!(
+            Failure::new(Some(11), None).kind,
+            FailureKind::RepositoryLocked
+        );
+        assert_eq!(
+            Failure::new(Some(10), None).kind,
+            FailureKind::RepositoryNotFound
+        );
+    }
+
+    #[test]
+    fn should_classify_generic_exit_code_by_stderr() {
+        let kind = FailureKind::from_stderr("Fatal: wrong password or no key found");
+
+        assert_eq!(Failure::new(Some(1), kind).kind, FailureKind::WrongPassword);
+    }
+
+    #[test]
+    fn should_classify_lock_failure_from_stderr() {
+        assert_eq!(
+            FailureKind::from_stderr(
+                "unable to create lock in backend: repository is already locked by PID 1234 on host by user (UID 1000, GID 1000)"
+            ),
+            Some(FailureKind::RepositoryLocked)
+        );
+    }
+
+    #[test]
+    fn should_classify_missing_repository_from_stderr() {
+        assert_eq!(
+            FailureKind::from_stderr(
+                "Fatal: unable to open config file: stat /srv/repo/config: no such file or directory"
+            ),
+            Some(FailureKind::RepositoryNotFound)
+        );
+    }
+
+    #[test]
+    fn should_classify_missing_repository_in_cloud_storage_from_stderr() {
+        assert_eq!(
+            FailureKind::from_stderr(
+                "Fatal: unable to open config file: Stat: The specified key does not exist."
+            ),
+            Some(FailureKind::RepositoryNotFound)
+        );
+    }
+
+    #[test]
+    fn should_not_classify_backend_error_as_missing_repository() {
+        assert_eq!(
+            FailureKind::from_stderr(
+                "Fatal: unable to open config file: Stat: dial tcp: lookup backup.example.com: no such host"
+            ),
+            None
+        );
+        assert_eq!(
+            FailureKind::from_stderr("Is there a repository at the following location?"),
+            None
+        );
+    }
+
+    #[test]
+    fn should_prefer_exit_code_over_stderr() {
+        let kind = FailureKind::from_stderr("Fatal: wrong password or no key found");
+
+        assert_eq!(
+            Failure::new(Some(11), kind).kind,
+            FailureKind::RepositoryLocked
+        );
+    }
+
+    #[test]
+    fn should_not_classify_unknown_failure() {
+        assert_eq!(FailureKind::from_stderr("Fatal: something else"), None);
+        assert_eq!(Failure::new(Some(1), None).kind, FailureKind::Other);
+    }
+
+    #[test]
+    fn should_describe_failure() {
+        assert_eq!(
+            Failure::new(Some(12), None).to_string(),
+            "restic failed: wrong password or no key found (exit status 12)"
+        );
+        assert_eq!(
+            Failure::new(Some(1), None).to_string(),
+            "restic exited with error status 1"
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use super::{Options, Restic};
+use crate::secrets::{RepoWithSecrets, SecretValue, Secrets};
+use eyre::{eyre, WrapErr};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Key {
+    pub id: String,
+    /// whether this key was used to open the repository
+    pub current: bool,
+    #[serde(rename = "userName")]
+    pub user_name: String,
+    #[serde(rename = "hostName")]
+    pub host_name: String,
+    pub created: String,
+}
+
+impl Restic {
+    pub async fn list_keys(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+    ) -> eyre::Result<Vec<Key>> {
+        Ok(self
+            .json(
+                repo_with_secrets,
+                &["key".into(), "list".into()],
+                std::future::pending::<()>(),
+            )
+            .await?)
+    }
+
+    /// Adds a key with the new password to the repository. The password is passed on standard
+    /// input so it never ends up in a file or the process arguments.
+    pub async fn add_key(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        new_password: &SecretValue,
+    ) -> eyre::Result<()> {
+        let mut process = self.run_internal(
+            Some(repo_with_secrets),
+            &["key", "add"],
+            &Options::default(),
+            true,
+        )?;
+        let mut stdin = process
+            .child
+            .stdin
+            .take()
+            .expect("should be present based on params");
+        let input = SecretValue::new(format!("{}\n", new_password.0));
+        let written = stdin.write_all(input.0.as_bytes()).await;
+        drop(stdin);
+        process.check_wait().await?;
+        written.wrap_err("failed to pass new password to restic")
+    }
+
+    pub async fn remove_key(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        id: &str,
+    ) -> eyre::Result<()> {
+        self.run(
+            Some(repo_with_secrets),
+            &["key", "remove", id],
+            &Options::default(),
+        )?
+        .check_wait()
+        .await?;
+        Ok(())
+    }
+
+    /// Replaces the key of the old password with a key for `new_password`: adds the new key,
+    /// checks that the new password opens the repository with it, stores the new password and
+    /// removes the old key. If any step fails, the old password is kept working. `keys_before`
+    /// are the keys listed with the old password. Returns the id of the new key.
+    pub async fn rotate_key(
+        &self,
+        secrets: &Secrets,
+        old: &RepoWithSecrets<'_>,
+        keys_before: &[Key],
+        new_password: SecretValue,
+    ) -> eyre::Result<String> {
+        let old_key_id = current_key(keys_before)?.id.clone();
+        self.add_key(old, &new_password)
+            .await
+            .wrap_err("failed to add restic key for the new password")?;
+        let keys_after = self.list_keys(old).await?;
+        let new_key_id = added_key(keys_before, &keys_after)
+            .wrap_err("couldn't identify the new restic key, check `restic key list`")?
+            .id
+            .clone();
+        let new = RepoWithSecrets {
+            repo: old.repo,
+            repo_password: new_password.clone(),
+            secrets: old.secrets.clone(),
+        };
+
+        if let Err(error) = self.verify_new_key(&new, &new_key_id).await {
+            return Err(self
+                .roll_back(secrets, old, &new_key_id, false, error)
+                .await);
+        }
+        if let Err(error) = secrets.set_secret(&old.repo.password, new_password) {
+            return Err(self
+                .roll_back(secrets, old, &new_key_id, false, error)
+                .await);
+        }
+        if let Err(error) = self.remove_key(&new, &old_key_id).await {
+            let error = error.wrap_err(format!("failed to remove old restic key {}", old_key_id));
+            return Err(self.roll_back(secrets, old, &new_key_id, true, error).await);
+        }
+        Ok(new_key_id)
+    }
+
+    /// Checks that the new password opens the repository with the key that was just added.
+    async fn verify_new_key(
+        &self,
+        new: &RepoWithSecrets<'_>,
+        new_key_id: &str,
+    ) -> eyre::Result<()> {
+        let keys = self
+            .list_keys(new)
+            .await
+            .wrap_err("failed to open repository with the new password")?;
+        let current_key = current_key(&keys)?;
+        if current_key.id != new_key_id {
+            return Err(eyre!(
+                "the new password opened the repository with key {} instead of {}",
+                current_key.id,
+                new_key_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Undoes a partial rotation so the old password keeps working. Returns the original error
+    /// with the outcome of the rollback attached.
+    async fn roll_back(
+        &self,
+        secrets: &Secrets,
+        old: &RepoWithSecrets<'_>,
+        new_key_id: &str,
+        restore_secret: bool,
+        error: eyre::Report,
+    ) -> eyre::Report {
+        if restore_secret {
+            if let Err(restore_error) =
+                secrets.set_secret(&old.repo.password, old.repo_password.clone())
+            {
+                return error.wrap_err(format!(
+                    "failed to rotate password and couldn't restore the old password ({}); \
+                     both restic keys are still valid, the new key is {}",
+                    restore_error, new_key_id
+                ));
+            }
+        }
+        match self.remove_key(old, new_key_id).await {
+            Ok(()) => error.wrap_err("failed to rotate password, kept the old password"),
+            Err(remove_error) => error.wrap_err(format!(
+                "failed to rotate password and couldn't remove the new restic key {} ({}); \
+                 the old password is still valid",
+                new_key_id, remove_error
+            )),
+        }
+    }
+}
+
+/// The key that was used to open the repository.
+pub fn current_key(keys: &[Key]) -> eyre::Result<&Key> {
+    keys.iter()
+        .find(|key| key.current)
+        .ok_or_else(|| eyre!("restic didn't report the key used to open the repository"))
+}
+
+/// The single key in `after` that isn't in `before`.
+pub fn added_key<'a>(before: &[Key], after: &'a [Key]) -> eyre::Result<&'a Key> {
+    let mut added = after
+        .iter()
+        .filter(|key| !before.iter().any(|k| k.id == key.id));
+    match (added.next(), added.next()) {
+        (Some(key), None) => Ok(key),
+        (None, _) => Err(eyre!("no new key was added to the repository")),
+        (Some(_), Some(_)) => Err(eyre!(
+            "more than one key was added to the repository at the same time"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: &str, current: bool) -> Key {
+        Key {
+            id: id.to_owned(),
+            current,
+            user_name: "user".to_owned(),
+            host_name: "host".to_owned(),
+            created: "2023-01-14 12:00:00".to_owned(),
+        }
+    }
+
+    #[test]
+    fn should_parse_key_list() {
+        // language=JSON
+        let json = r#"[
+          {"current": true, "id": "3ac6c4d2", "userName": "user", "hostName": "host", "created": "2023-01-14 12:00:00"},
+          {"current": false, "id": "9e1b02f7", "userName": "user", "hostName": "host", "created": "2023-01-14 12:00:00"}
+        ]"#;
+
+        let keys: Vec<Key> = serde_json::from_str(json).unwrap();
+
+        assert_eq!(keys, vec![key("3ac6c4d2", true), key("9e1b02f7", false)]);
+    }
+
+    #[test]
+    fn should_find_current_key() {
+        let keys = vec![key("a", false), key("b", true)];
+
+        assert_eq!(current_key(&keys).unwrap().id, "b");
+    }
+
+    #[test]
+    fn should_find_added_key() {
+        let before = vec![key("a", true)];
+        let after = vec![key("a", true), key("b", false)];
+
+        assert_eq!(added_key(&before, &after).unwrap().id, "b");
+    }
+
+    #[test]
+    fn should_not_find_added_key_if_none_was_added() {
+        let before = vec![key("a", true)];
+        let after = vec![key("a", true)];
+
+        assert!(added_key(&before, &after).is_err());
+    }
+
+    #[test]
+    fn should_not_find_added_key_if_several_were_added() {
+        let before = vec![key("a", true)];
+        let after = vec![key("a", true), key("b", false), key("c", false)];
+
+        assert!(added_key(&before, &after).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use super::{Error, Restic};
+use crate::secrets::RepoWithSecrets;
+use serde::Deserialize;
+use std::{ffi::OsString, future::Future, time::Duration};
+use time::OffsetDateTime;
+
+/// restic refreshes the locks of running processes every few minutes and considers locks that
+/// haven't been refreshed for this long as stale.
+pub const STALE_LOCK_AGE: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Lock {
+    /// not part of restic's output, set from the lock's file name
+    #[serde(default)]
+    pub id: String,
+    #[serde(with = "time::serde::iso8601")]
+    pub time: OffsetDateTime,
+    #[serde(default)]
+    pub exclusive: bool,
+    pub hostname: String,
+    #[serde(default)]
+    pub username: String,
+    pub pid: u32,
+}
+
+impl Lock {
+    /// Whether the lock was left behind by a process on the given host, e.g. because the machine
+    /// suspended or crashed during a backup. Locks of other hosts are never considered stale
+    /// because their processes can't be checked.
+    pub fn is_stale(&self, hostname: &str, now: OffsetDateTime) -> bool {
+        self.is_stale_with(hostname, now, process_exists)
+    }
+
+    fn is_stale_with(
+        &self,
+        hostname: &str,
+        now: OffsetDateTime,
+        process_exists: impl Fn(u32) -> bool,
+    ) -> bool {
+        // restic itself compares hostnames exactly when deciding whether a lock is stale
+        if self.hostname != hostname {
+            return false;
+        }
+        now - self.time > STALE_LOCK_AGE || !process_exists(self.pid)
+    }
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    // signal 0 only checks whether the process exists and may be signalled
+    let result = unsafe { libc::kill(pid as i32, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    // without a way to check, only the age of a lock can make it stale
+    true
+}
+
+impl Restic {
+    /// All locks in the repository. `restic list locks` only prints the lock IDs, so every lock
+    /// is read with `restic cat lock` afterwards. Neither command locks the repository, which
+    /// would fail while it's exclusively locked. Restic is terminated once `cancellation`
+    /// completes.
+    pub async fn locks(
+        &self,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        cancellation: impl Future,
+    ) -> Result<Vec<Lock>, Error> {
+        let mut cancellation = std::pin::pin!(cancellation);
+        let output = self
+            .output(
+                repo_with_secrets,
+                &[
+                    OsString::from("list"),
+                    OsString::from("locks"),
+                    OsString::from("--no-lock"),
+                ],
+                false,
+                cancellation.as_mut(),
+            )
+            .await?;
+        let output = String::from_utf8_lossy(&output);
+        let mut locks = Vec::new();
+        for id in output.lines().map(str::trim).filter(|id| !id.is_empty()) {
+            let mut lock: Lock = self
+                .json(
+                    repo_with_secrets,
+                    &[
+                        OsString::from("cat"),
+                        OsString::from("lock"),
+                        id.into(),
+                        OsString::from("--no-lock"),
+                    ],
+                    cancellation.as_mut(),
+                )
+                .await?;
+            lock.id = id.to_owned();
+            locks.push(lock);
+        }
+        Ok(locks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn lock(hostname: &str, time: OffsetDateTime) -> Lock {
+        Lock {
+            id: "0e4a5ff1".to_owned(),
+            time,
+            exclusive: false,
+            hostname: hostname.to_owned(),
+            username: "user".to_owned(),
+            pid: 1234,
+        }
+    }
+
+    #[test]
+    fn should_parse_lock() {
+        // language=JSON
+        let json = r#"{"time":"2023-01-14T12:00:00.123456789+01:00","exclusive":false,"hostname":"laptop","username":"user","pid":1234,"uid":1000,"gid":1000}"#;
+
+        let lock: Lock = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            lock,
+            Lock {
+                id: "".to_owned(),
+                time: datetime!(2023-01-14 12:00:00.123456789 +01:00),
+                exclusive: false,
+                hostname: "laptop".to_owned(),
+                username: "user".to_owned(),
+                pid: 1234,
+            }
+        );
+    }
+
+    #[test]
+    fn should_consider_lock_of_dead_process_on_this_host_stale() {
+        let now = datetime!(2023-01-14 12:05:00 UTC);
+        let lock = lock("laptop", datetime!(2023-01-14 12:00:00 UTC));
+
+        assert!(lock.is_stale_with("laptop", now, |_| false));
+    }
+
+    #[test]
+    fn should_consider_old_lock_on_this_host_stale() {
+        let now = datetime!(2023-01-14 13:00:00 UTC);
+        let lock = lock("laptop", datetime!(2023-01-14 12:00:00 UTC));
+
+        assert!(lock.is_stale_with("laptop", now, |_| true));
+    }
+
+    #[test]
+    fn should_not_consider_recent_lock_of_running_process_stale() {
+        let now = datetime!(2023-01-14 12:05:00 UTC);
+        let lock = lock("laptop", datetime!(2023-01-14 12:00:00 UTC));
+
+        assert!(!lock.is_stale_with("laptop", now, |_| true));
+    }
+
+    #[test]
+    fn should_not_consider_lock_of_other_host_stale() {
+        let now = datetime!(2023-01-14 13:00:00 UTC);
+        let lock = lock("desktop", datetime!(2023-01-14 12:00:00 UTC));
+
+        assert!(!lock.is_stale_with("laptop", now, |_| false));
+    }
+
+    #[test]
+    fn should_compare_hostnames_exactly() {
+        let now = datetime!(2023-01-14 13:00:00 UTC);
+        let lock = lock("Laptop", datetime!(2023-01-14 12:00:00 UTC));
+
+        assert!(!lock.is_stale_with("laptop", now, |_| false));
+    }
+}
@@ -1,12 +1,29 @@
-use crate::{config::backup, secrets::RepoWithSecrets};
-use std::{ffi::OsStr, path::PathBuf, process::Stdio};
-use tokio::process::Command;
+use crate::{
+    config::{backup, repo},
+    secrets::RepoWithSecrets,
+};
+use std::{
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+    process::Stdio,
+};
+use tokio::{process::Command, sync::OnceCell};
 
 use crate::tag::Tag;
+pub use api::*;
+pub use backup_output::*;
+pub use failure::*;
 pub use process::*;
+pub use version::*;
 
+mod api;
+mod backup_output;
+mod failure;
+pub mod keys;
+pub mod locks;
 mod process;
 mod util;
+mod version;
 
 #[derive(Debug, Copy, Clone)]
 pub enum Verbosity {
@@ -115,15 +132,46 @@ pub enum Error {
     SubprocessStatusError(#[source] std::io::Error),
     #[error("error killing process")]
     SubprocessTerminateError(#[source] std::io::Error),
-    #[error("{}", .0.message())]
-    ResticError(ExitStatus),
+    #[error("{0}")]
+    ResticError(Failure),
     #[error("couldn't determine restic version from output")]
     FailedToGetResticVersion,
+    #[error("restic {} is not supported, cirrus requires restic {} or newer", .0, ResticVersion::MINIMUM)]
+    UnsupportedResticVersion(ResticVersion),
+    #[error("the setting '{}' requires restic {} or newer, but restic {} is installed", .capability.setting(), .capability.min_version(), .installed)]
+    UnsupportedByResticVersion {
+        capability: Capability,
+        installed: ResticVersion,
+    },
+    #[error("failed to parse restic output")]
+    InvalidOutput(#[source] serde_json::Error),
+    #[error("restic output is incomplete")]
+    IncompleteOutput,
+    #[error("restic was cancelled")]
+    Cancelled,
+    #[error("failed to start stdin command")]
+    FailedToStartStdinCommand(#[source] std::io::Error),
+    #[error("stdin command exited with {}", match .0 {
+        Some(code) => format!("error status {}", code),
+        None => "unknown error status".to_owned(),
+    })]
+    StdinCommandFailed(Option<i32>),
+}
+
+impl Error {
+    /// Why restic failed, if this is a failure of restic itself.
+    pub fn failure_kind(&self) -> Option<FailureKind> {
+        match self {
+            Error::ResticError(failure) => Some(failure.kind),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Restic {
     config: Config,
+    version: OnceCell<ResticVersion>,
 }
 
 impl Restic {
@@ -131,9 +179,16 @@ impl Restic {
     const EXCLUDE_PARAM: &'static str = "--iexclude";
     #[cfg(not(windows))]
     const EXCLUDE_PARAM: &'static str = "--exclude";
+    #[cfg(windows)]
+    const EXCLUDE_FILE_PARAM: &'static str = "--iexclude-file";
+    #[cfg(not(windows))]
+    const EXCLUDE_FILE_PARAM: &'static str = "--exclude-file";
 
     pub fn new(config: Config) -> Self {
-        Restic { config }
+        Restic {
+            config,
+            version: OnceCell::new(),
+        }
     }
 
     pub fn new_with_path(path: impl Into<PathBuf>) -> Self {
@@ -149,13 +204,33 @@ impl Restic {
         extra_args: &[impl AsRef<OsStr>],
         options: &Options,
     ) -> Result<ResticProcess, Error> {
-        self.run_with_config(&self.config.primary, repo_with_secrets, extra_args, options)
-            .or_else(|e| match &self.config.fallback {
-                Some(fallback) => {
-                    self.run_with_config(fallback, repo_with_secrets, extra_args, options)
-                }
-                None => Err(e),
-            })
+        self.run_internal(repo_with_secrets, extra_args, options, false)
+    }
+
+    fn run_internal(
+        &self,
+        repo_with_secrets: Option<&RepoWithSecrets>,
+        extra_args: &[impl AsRef<OsStr>],
+        options: &Options,
+        piped_stdin: bool,
+    ) -> Result<ResticProcess, Error> {
+        self.run_with_config(
+            &self.config.primary,
+            repo_with_secrets,
+            extra_args,
+            options,
+            piped_stdin,
+        )
+        .or_else(|e| match &self.config.fallback {
+            Some(fallback) => self.run_with_config(
+                fallback,
+                repo_with_secrets,
+                extra_args,
+                options,
+                piped_stdin,
+            ),
+            None => Err(e),
+        })
     }
 
     pub fn backup(
@@ -165,47 +240,153 @@ impl Restic {
         definition: &backup::Definition,
         options: &Options,
     ) -> Result<ResticProcess, Error> {
-        let mut args = vec![
-            "backup".to_owned(),
-            definition.path.0.clone(),
-            "--tag".to_owned(),
-            Tag::for_backup(name).0,
-        ];
+        let mut args = vec![OsString::from("backup")];
+        for path in &definition.paths {
+            args.push(path.0.clone().into());
+        }
+        if !definition.stdin_command.is_empty() {
+            args.push("--stdin".into());
+            if let Some(stdin_filename) = &definition.stdin_filename {
+                args.push("--stdin-filename".into());
+                args.push(stdin_filename.into());
+            }
+        }
+        args.push("--tag".into());
+        args.push(Tag::for_backup(name).0.into());
         for exclude in &definition.excludes {
-            args.push(Self::EXCLUDE_PARAM.to_owned());
-            args.push(exclude.0.clone());
+            args.push(Self::EXCLUDE_PARAM.into());
+            args.push(exclude.0.clone().into());
+        }
+        for exclude in &definition.iexcludes {
+            args.push("--iexclude".into());
+            args.push(exclude.0.clone().into());
+        }
+        for exclude_file in &definition.exclude_files {
+            args.push(Self::EXCLUDE_FILE_PARAM.into());
+            args.push(exclude_file.into());
+        }
+        for files_from in &definition.files_from {
+            args.push("--files-from".into());
+            args.push(files_from.into());
+        }
+        for files_from_raw in &definition.files_from_raw {
+            args.push("--files-from-raw".into());
+            args.push(files_from_raw.into());
         }
         if definition.exclude_caches {
-            args.push("--exclude-caches".to_owned());
+            args.push("--exclude-caches".into());
         }
         if let Some(exclude_larger_than) = &definition.exclude_larger_than {
-            args.push("--exclude-larger-than".to_owned());
-            args.push(exclude_larger_than.clone());
+            args.push("--exclude-larger-than".into());
+            args.push(exclude_larger_than.into());
+        }
+        if let Some(read_concurrency) = definition.read_concurrency {
+            args.push("--read-concurrency".into());
+            args.push(read_concurrency.to_string().into());
+        }
+        if definition.skip_if_unchanged {
+            args.push("--skip-if-unchanged".into());
         }
         for arg in &definition.extra_args {
-            args.push(arg.clone());
+            args.push(arg.into());
         }
 
-        let mut process = self.run(Some(repo_with_secrets), &args, options)?;
+        let mut process = match definition.stdin_command.split_first() {
+            None => self.run(Some(repo_with_secrets), &args, options)?,
+            Some((program, program_args)) => {
+                let mut process =
+                    self.run_internal(Some(repo_with_secrets), &args, options, true)?;
+                process.pipe_from_command(program, program_args)?;
+                process
+            }
+        };
         if definition.ignore_unreadable_source_files {
             process.extra_success_status = Some(3);
         }
         Ok(process)
     }
 
+    fn forget_args(name: &backup::Name, retention: &backup::Retention) -> Vec<String> {
+        let mut args = vec![
+            "forget".to_owned(),
+            "--tag".to_owned(),
+            Tag::for_backup(name).0,
+        ];
+        let keep_counts = [
+            ("--keep-last", retention.keep_last),
+            ("--keep-hourly", retention.keep_hourly),
+            ("--keep-daily", retention.keep_daily),
+            ("--keep-weekly", retention.keep_weekly),
+            ("--keep-monthly", retention.keep_monthly),
+            ("--keep-yearly", retention.keep_yearly),
+        ];
+        for (param, count) in keep_counts {
+            if let Some(count) = count {
+                args.push(param.to_owned());
+                args.push(count.to_string());
+            }
+        }
+        if let Some(keep_within) = retention.keep_within {
+            // restic only supports whole hours as the smallest unit
+            args.push("--keep-within".to_owned());
+            args.push(format!("{}h", keep_within.as_secs().div_ceil(3600)));
+        }
+        if retention.prune {
+            args.push("--prune".to_owned());
+        }
+        args
+    }
+
+    fn repo_options_args(options: &repo::Options) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if let Some(limit_upload) = options.limit_upload {
+            args.push("--limit-upload".into());
+            args.push(limit_upload.to_string().into());
+        }
+        if let Some(limit_download) = options.limit_download {
+            args.push("--limit-download".into());
+            args.push(limit_download.to_string().into());
+        }
+        if let Some(compression) = options.compression {
+            args.push("--compression".into());
+            args.push(compression.name().into());
+        }
+        if let Some(pack_size) = options.pack_size {
+            args.push("--pack-size".into());
+            args.push(pack_size.to_string().into());
+        }
+        if let Some(cache_dir) = &options.cache_dir {
+            args.push("--cache-dir".into());
+            args.push(cache_dir.into());
+        }
+        if options.insecure_tls {
+            args.push("--insecure-tls".into());
+        }
+        for (key, value) in &options.extended {
+            args.push("--option".into());
+            args.push(format!("{}={}", key, value).into());
+        }
+        args
+    }
+
     fn run_with_config(
         &self,
         config: &CommandConfig,
         repo_with_secrets: Option<&RepoWithSecrets>,
         extra_args: &[impl AsRef<OsStr>],
         options: &Options,
+        piped_stdin: bool,
     ) -> Result<ResticProcess, Error> {
         let mut cmd = config.to_command();
-        cmd.stdin(Stdio::null())
-            .stdout(options.stdout)
-            .stderr(options.stderr)
-            // kill-on-drop is a final fallback, normally the process gets terminated gracefully
-            .kill_on_drop(true);
+        cmd.stdin(if piped_stdin {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(options.stdout)
+        .stderr(options.stderr)
+        // kill-on-drop is a final fallback, normally the process gets terminated gracefully
+        .kill_on_drop(true);
 
         if let Some(repo_with_secrets) = repo_with_secrets {
             cmd.env("RESTIC_PASSWORD", &repo_with_secrets.repo_password.0);
@@ -213,6 +394,7 @@ impl Restic {
                 cmd.env(&name.0, &value.0);
             }
             cmd.arg("--repo").arg(&repo_with_secrets.repo.url.0);
+            cmd.args(Self::repo_options_args(&repo_with_secrets.repo.options));
         }
 
         for arg in extra_args {
@@ -234,6 +416,8 @@ impl Restic {
         Ok(ResticProcess {
             child,
             extra_success_status: None,
+            stdin_task: None,
+            stderr_failure_kind: None,
         })
     }
 }
@@ -1,11 +1,18 @@
-use super::Error;
-use std::time::Duration;
-use tokio::process::{Child, ChildStderr, ChildStdout};
+use super::{Error, Failure, FailureKind};
+use std::{process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{Child, ChildStderr, ChildStdout, Command},
+    task::JoinHandle,
+};
+
+/// How long restic gets to exit after being asked to terminate before it's killed.
+pub const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
 #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 pub enum ExitStatus {
     Successful,
-    Failed(Option<i32>),
+    Failed(Failure),
 }
 
 impl ExitStatus {
@@ -16,17 +23,14 @@ impl ExitStatus {
     pub fn check_status(&self) -> Result<(), Error> {
         match self {
             ExitStatus::Successful => Ok(()),
-            ExitStatus::Failed(_) => Err(Error::ResticError(*self)),
+            ExitStatus::Failed(failure) => Err(Error::ResticError(*failure)),
         }
     }
 
     pub fn message(&self) -> String {
         match self {
             ExitStatus::Successful => "restic exited successfully".to_owned(),
-            ExitStatus::Failed(Some(code)) => {
-                format!("restic exited with error status {}", code)
-            }
-            ExitStatus::Failed(None) => "restic exited with unknown error status".to_owned(),
+            ExitStatus::Failed(failure) => failure.to_string(),
         }
     }
 }
@@ -35,10 +39,32 @@ impl ExitStatus {
 fn ask_to_terminate(child: &mut Child) -> Result<(), Error> {
     // TODO maybe not expect?
     let pid = child.id().expect("child should have a PID");
-    unsafe { libc::kill(pid as i32, libc::SIGTERM) };
+    ask_to_terminate_pid(pid);
     Ok(())
 }
 
+#[cfg(unix)]
+fn ask_to_terminate_pid(pid: u32) {
+    unsafe { libc::kill(pid as i32, libc::SIGTERM) };
+}
+
+/// There's no way to ask a console process on Windows to stop, so it's terminated right away.
+#[cfg(windows)]
+fn ask_to_terminate_pid(pid: u32) {
+    use winapi::um::{
+        handleapi::CloseHandle,
+        processthreadsapi::{OpenProcess, TerminateProcess},
+        winnt::PROCESS_TERMINATE,
+    };
+    unsafe {
+        let handle = OpenProcess(PROCESS_TERMINATE, 0, pid);
+        if !handle.is_null() {
+            TerminateProcess(handle, 1);
+            CloseHandle(handle);
+        }
+    }
+}
+
 #[cfg(not(unix))]
 fn ask_to_terminate(child: &mut Child) -> Result<(), Error> {
     child
@@ -51,6 +77,19 @@ fn ask_to_terminate(child: &mut Child) -> Result<(), Error> {
 pub struct ResticProcess {
     pub(crate) child: Child,
     pub(crate) extra_success_status: Option<i32>,
+    /// copies the output of the stdin command to restic and waits for the command to finish
+    pub(crate) stdin_task: Option<JoinHandle<Result<(), Error>>>,
+    /// the first failure recognized in restic's error output
+    pub(crate) stderr_failure_kind: Option<FailureKind>,
+}
+
+impl Drop for ResticProcess {
+    fn drop(&mut self) {
+        // dropping the task also kills the stdin command
+        if let Some(stdin_task) = &self.stdin_task {
+            stdin_task.abort();
+        }
+    }
 }
 
 impl ResticProcess {
@@ -62,17 +101,96 @@ impl ResticProcess {
         &mut self.child.stderr
     }
 
+    /// Lets the process classify a failure by restic's error output. Whoever reads the captured
+    /// standard error should pass every line here.
+    pub fn inspect_stderr(&mut self, line: &str) {
+        if self.stderr_failure_kind.is_none() {
+            self.stderr_failure_kind = FailureKind::from_stderr(line);
+        }
+    }
+
+    /// Runs a command and pipes its standard output into restic. If the command fails, restic is
+    /// terminated before it reaches the end of its input so it doesn't create a partial snapshot.
+    pub(crate) fn pipe_from_command(
+        &mut self,
+        program: &str,
+        args: &[String],
+    ) -> Result<(), Error> {
+        let mut restic_stdin = self
+            .child
+            .stdin
+            .take()
+            .expect("should be present based on params");
+        let restic_pid = self.child.id();
+        let mut command = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(Error::FailedToStartStdinCommand)?;
+
+        self.stdin_task = Some(tokio::spawn(async move {
+            let mut stdout = command
+                .stdout
+                .take()
+                .expect("should be present based on params");
+            let stderr = command
+                .stderr
+                .take()
+                .expect("should be present based on params");
+            let log_stderr = async {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    tracing::warn!("{}", line);
+                }
+            };
+            let (copied, _) =
+                tokio::join!(tokio::io::copy(&mut stdout, &mut restic_stdin), log_stderr);
+            // stop reading so the command can't block on a full pipe if restic went away
+            drop(stdout);
+
+            let status = command.wait().await.map_err(Error::SubprocessStatusError)?;
+            if !status.success() {
+                // restic has to be stopped before its input is closed, otherwise it would see
+                // the end of the input and create a snapshot
+                if let Some(pid) = restic_pid {
+                    ask_to_terminate_pid(pid);
+                }
+                return Err(Error::StdinCommandFailed(status.code()));
+            }
+            copied.map_err(Error::SubprocessIoError)?;
+            Ok(())
+        }));
+        Ok(())
+    }
+
     pub async fn wait(&mut self) -> Result<ExitStatus, Error> {
         let proc_status = self
             .child
             .wait()
             .await
             .map_err(Error::SubprocessStatusError)?;
-        if proc_status.success() || proc_status.code() == self.extra_success_status {
-            Ok(ExitStatus::Successful)
+        let status = if proc_status.success() || proc_status.code() == self.extra_success_status {
+            ExitStatus::Successful
         } else {
-            Ok(ExitStatus::Failed(proc_status.code()))
+            ExitStatus::Failed(Failure::new(proc_status.code(), self.stderr_failure_kind))
+        };
+
+        if let Some(stdin_task) = self.stdin_task.take() {
+            let stdin_result = stdin_task
+                .await
+                .map_err(|e| Error::SubprocessIoError(e.into()))?;
+            match stdin_result {
+                // a failing stdin command is the more useful error if restic failed as a result
+                Err(error @ Error::StdinCommandFailed(_)) => return Err(error),
+                Err(error) if status.success() => return Err(error),
+                _ => {}
+            }
         }
+
+        Ok(status)
     }
 
     pub async fn check_wait(&mut self) -> Result<(), Error> {
@@ -82,6 +200,9 @@ impl ResticProcess {
     #[tracing::instrument(level = "debug", skip_all, fields(pid = self.child.id(), grace_period_secs = grace_period.as_secs_f64()))]
     pub async fn terminate(&mut self, grace_period: Duration) -> Result<(), Error> {
         tracing::debug!("trying to terminate gracefully");
+        if let Some(stdin_task) = self.stdin_task.take() {
+            stdin_task.abort();
+        }
         ask_to_terminate(&mut self.child)?;
         match tokio::time::timeout(grace_period, self.wait()).await {
             Ok(result) => {
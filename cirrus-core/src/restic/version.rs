@@ -0,0 +1,221 @@
+use super::{Error, Restic};
+use crate::config::{backup, repo};
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ResticVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ResticVersion {
+    /// The oldest restic version cirrus is tested with.
+    pub const MINIMUM: ResticVersion = ResticVersion::new(0, 12, 0);
+
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        ResticVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses the output of `restic version`, e.g.
+    /// `restic 0.16.4 compiled with go1.21.6 on linux/amd64`. Suffixes such as `-dev` are
+    /// ignored.
+    pub fn parse(version_string: &str) -> Option<Self> {
+        let mut words = version_string.split_whitespace();
+        if words.next()? != "restic" {
+            return None;
+        }
+        let version = words.next()?;
+        let version = version.split(['-', '+']).next()?;
+        let mut parts = version.split('.').map(|part| part.parse::<u32>().ok());
+        let major = parts.next()??;
+        let minor = parts.next()??;
+        let patch = match parts.next() {
+            Some(patch) => patch?,
+            None => 0,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(ResticVersion::new(major, minor, patch))
+    }
+
+    pub fn is_supported(&self) -> bool {
+        *self >= Self::MINIMUM
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        *self >= capability.min_version()
+    }
+}
+
+impl Display for ResticVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// A restic feature that's only available from a certain version on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Capability {
+    Compression,
+    PackSize,
+    ReadConcurrency,
+    SkipIfUnchanged,
+}
+
+impl Capability {
+    pub fn min_version(&self) -> ResticVersion {
+        match self {
+            Capability::Compression | Capability::PackSize => ResticVersion::new(0, 14, 0),
+            Capability::ReadConcurrency => ResticVersion::new(0, 15, 0),
+            Capability::SkipIfUnchanged => ResticVersion::new(0, 17, 0),
+        }
+    }
+
+    /// The configuration setting that needs this capability.
+    pub fn setting(&self) -> &'static str {
+        match self {
+            Capability::Compression => "options.compression",
+            Capability::PackSize => "options.pack_size",
+            Capability::ReadConcurrency => "read_concurrency",
+            Capability::SkipIfUnchanged => "skip_if_unchanged",
+        }
+    }
+
+    /// The capabilities needed by the options of a repository.
+    pub fn required_by_repo(repo: &repo::Definition) -> Vec<Capability> {
+        let mut capabilities = Vec::new();
+        if repo.options.compression.is_some() {
+            capabilities.push(Capability::Compression);
+        }
+        if repo.options.pack_size.is_some() {
+            capabilities.push(Capability::PackSize);
+        }
+        capabilities
+    }
+
+    /// The capabilities needed to run a backup, not including those of its repository.
+    pub fn required_by_backup(backup: &backup::Definition) -> Vec<Capability> {
+        let mut capabilities = Vec::new();
+        if backup.read_concurrency.is_some() {
+            capabilities.push(Capability::ReadConcurrency);
+        }
+        if backup.skip_if_unchanged {
+            capabilities.push(Capability::SkipIfUnchanged);
+        }
+        capabilities
+    }
+}
+
+impl Restic {
+    /// The version of the installed restic, queried once and then cached.
+    pub async fn version(&self) -> Result<ResticVersion, Error> {
+        self.version
+            .get_or_try_init(|| async {
+                let version_string = self
+                    .version_string()
+                    .await
+                    .map_err(|_| Error::FailedToGetResticVersion)?;
+                ResticVersion::parse(&version_string).ok_or(Error::FailedToGetResticVersion)
+            })
+            .await
+            .copied()
+    }
+
+    /// Fails if the installed restic is older than [ResticVersion::MINIMUM].
+    pub async fn check_version(&self) -> Result<ResticVersion, Error> {
+        let version = self.version().await?;
+        if !version.is_supported() {
+            return Err(Error::UnsupportedResticVersion(version));
+        }
+        Ok(version)
+    }
+
+    /// Fails if the installed restic is too old for any of the capabilities. If the version
+    /// can't be determined, the capabilities are assumed to be available and restic itself has
+    /// the final say.
+    pub async fn check_capabilities(
+        &self,
+        capabilities: impl IntoIterator<Item = Capability>,
+    ) -> Result<(), Error> {
+        let mut capabilities = capabilities.into_iter().peekable();
+        if capabilities.peek().is_none() {
+            return Ok(());
+        }
+        let version = match self.version().await {
+            Ok(version) => version,
+            Err(error) => {
+                tracing::warn!(%error, "can't check if restic supports the configured options");
+                return Ok(());
+            }
+        };
+        match capabilities.find(|capability| !version.supports(*capability)) {
+            Some(capability) => Err(Error::UnsupportedByResticVersion {
+                capability,
+                installed: version,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_release_version() {
+        assert_eq!(
+            ResticVersion::parse("restic 0.16.4 compiled with go1.21.6 on linux/amd64"),
+            Some(ResticVersion::new(0, 16, 4))
+        );
+    }
+
+    #[test]
+    fn should_parse_development_version() {
+        assert_eq!(
+            ResticVersion::parse("restic 0.17.0-dev (compiled manually) compiled with go1.22.1"),
+            Some(ResticVersion::new(0, 17, 0))
+        );
+    }
+
+    #[test]
+    fn should_parse_version_without_patch() {
+        assert_eq!(
+            ResticVersion::parse("restic 0.13"),
+            Some(ResticVersion::new(0, 13, 0))
+        );
+    }
+
+    #[test]
+    fn should_not_parse_other_output() {
+        assert_eq!(ResticVersion::parse("restic version line"), None);
+        assert_eq!(ResticVersion::parse("rustic 0.16.4"), None);
+        assert_eq!(ResticVersion::parse("restic 0.16.4.1"), None);
+    }
+
+    #[test]
+    fn should_compare_versions() {
+        assert!(ResticVersion::new(0, 9, 6) < ResticVersion::new(0, 12, 0));
+        assert!(ResticVersion::new(0, 14, 0) < ResticVersion::new(0, 14, 1));
+        assert!(ResticVersion::new(1, 0, 0) > ResticVersion::new(0, 17, 3));
+    }
+
+    #[test]
+    fn should_check_capability_against_version() {
+        let version = ResticVersion::new(0, 14, 0);
+
+        assert!(version.supports(Capability::Compression));
+        assert!(!version.supports(Capability::SkipIfUnchanged));
+    }
+
+    #[test]
+    fn should_display_version() {
+        assert_eq!(ResticVersion::new(0, 16, 4).to_string(), "0.16.4");
+    }
+}
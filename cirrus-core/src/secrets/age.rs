@@ -0,0 +1,333 @@
+use crate::secrets::SecretValue;
+use eyre::{eyre, WrapErr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+const AGE: &str = "age";
+const AGE_KEYGEN: &str = "age-keygen";
+
+/// Runs an `age` command with the given input and returns its output. The identity is always
+/// passed on standard input so it never has to be written to disk.
+fn run(program: &str, args: &[&str], input: &[u8]) -> eyre::Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("failed to start '{}'", program))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_owned();
+    // write from a separate thread so a large output can't block the input
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child
+        .wait_with_output()
+        .wrap_err_with(|| format!("error waiting for '{}'", program))?;
+    writer
+        .join()
+        .map_err(|_| eyre!("failed to write input of '{}'", program))?
+        .wrap_err_with(|| format!("failed to write input of '{}'", program))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = format!("'{}' failed with {}", program, output.status);
+        return Err(match stderr.trim() {
+            "" => eyre!(message),
+            stderr => eyre!(stderr.to_owned()).wrap_err(message),
+        });
+    }
+    Ok(output.stdout)
+}
+
+fn decrypt(file: &Path, identity: &SecretValue) -> eyre::Result<String> {
+    let file = file
+        .to_str()
+        .ok_or_else(|| eyre!("invalid secrets file path '{}'", file.display()))?;
+    let plaintext = run(
+        AGE,
+        &["--decrypt", "--identity", "-", file],
+        identity.0.as_bytes(),
+    )
+    .wrap_err_with(|| format!("failed to decrypt secrets file '{}'", file))?;
+    String::from_utf8(plaintext)
+        .wrap_err_with(|| format!("secrets file '{}' is not valid UTF-8", file))
+}
+
+/// The recipients for all identities in the identity file, so that every identity can still
+/// decrypt the file after it was re-encrypted.
+fn recipients(identity: &SecretValue) -> eyre::Result<Vec<String>> {
+    let output = run(AGE_KEYGEN, &["-y"], identity.0.as_bytes())
+        .wrap_err("failed to get recipients for age identity")?;
+    let recipients = String::from_utf8_lossy(&output)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    if recipients.is_empty() {
+        return Err(eyre!("age identity doesn't contain any keys"));
+    }
+    Ok(recipients)
+}
+
+/// The file listing everyone the secrets file is encrypted to, e.g. `secrets.toml.age.recipients`
+/// for `secrets.toml.age`. age files don't record their recipients, so without it re-encrypting
+/// would lock out everyone but the local identity.
+fn recipients_file(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".recipients");
+    PathBuf::from(name)
+}
+
+/// Parses a recipients file in the format `age --recipients-file` accepts: one recipient per
+/// line, with blank lines and `#` comments ignored.
+fn parse_recipients(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// All recipients to encrypt the secrets file to: those from its recipients file, and the
+/// identity's own ones so it can always decrypt the file again.
+fn all_recipients(file: &Path, identity: &SecretValue) -> eyre::Result<Vec<String>> {
+    let recipients_file = recipients_file(file);
+    let mut all = match std::fs::read_to_string(&recipients_file) {
+        Ok(contents) => parse_recipients(&contents),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            if file.exists() {
+                tracing::warn!(
+                    file = %file.display(),
+                    recipients_file = %recipients_file.display(),
+                    "re-encrypting secrets file only to the recipients of the identity, list all recipients in the recipients file to keep them"
+                );
+            }
+            Vec::new()
+        }
+        Err(error) => {
+            return Err(error).wrap_err_with(|| {
+                format!(
+                    "failed to read recipients file '{}'",
+                    recipients_file.display()
+                )
+            })
+        }
+    };
+    for recipient in recipients(identity)? {
+        if !all.contains(&recipient) {
+            all.push(recipient);
+        }
+    }
+    Ok(all)
+}
+
+fn encrypt(plaintext: &str, recipients: &[String]) -> eyre::Result<Vec<u8>> {
+    let mut args = vec!["--encrypt"];
+    for recipient in recipients {
+        args.push("--recipient");
+        args.push(recipient);
+    }
+    run(AGE, &args, plaintext.as_bytes()).wrap_err("failed to encrypt secrets file")
+}
+
+fn lookup(secrets: &str, key: &str, file: &Path) -> eyre::Result<SecretValue> {
+    let secrets: HashMap<&str, &str> = toml::from_str(secrets)
+        .wrap_err_with(|| format!("failed to parse secrets file '{}'", file.display()))?;
+    secrets
+        .get(key)
+        .map(|s| SecretValue::new(*s))
+        .ok_or_else(|| {
+            eyre!(
+                "key '{}' not found in secrets file '{}'",
+                key,
+                file.display()
+            )
+        })
+}
+
+fn update(secrets: &str, key: &str, value: SecretValue, file: &Path) -> eyre::Result<String> {
+    let mut secrets: BTreeMap<String, String> = toml::from_str(secrets)
+        .wrap_err_with(|| format!("failed to parse secrets file '{}'", file.display()))?;
+    secrets.insert(key.to_owned(), value.0.clone());
+    Ok(toml::to_string(&secrets)?)
+}
+
+pub(super) fn get_secret(
+    file: &str,
+    key: &str,
+    identity: &SecretValue,
+) -> eyre::Result<SecretValue> {
+    let file = Path::new(file);
+    lookup(&decrypt(file, identity)?, key, file)
+}
+
+/// Sets the key in the encrypted secrets file, creating the file if it doesn't exist yet. The
+/// file is replaced atomically so it can't end up partially written, and is encrypted to the
+/// recipients in its recipients file as well as those of the identity.
+pub(super) fn set_secret(
+    file: &str,
+    key: &str,
+    identity: &SecretValue,
+    value: SecretValue,
+) -> eyre::Result<()> {
+    let file = Path::new(file);
+    let secrets = if file.exists() {
+        decrypt(file, identity)?
+    } else {
+        String::new()
+    };
+    let recipients = all_recipients(file, identity)?;
+    let ciphertext = encrypt(&update(&secrets, key, value, file)?, &recipients)?;
+
+    let mut tmp_name = file
+        .file_name()
+        .ok_or_else(|| eyre!("invalid secrets file path '{}'", file.display()))?
+        .to_owned();
+    tmp_name.push(".tmp");
+    let tmp_file = file.with_file_name(tmp_name);
+    std::fs::write(&tmp_file, ciphertext)
+        .and_then(|_| std::fs::rename(&tmp_file, file))
+        .wrap_err_with(|| format!("failed to write secrets file '{}'", file.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_look_up_key() {
+        let value = lookup(
+            r#"password = "secret-value""#,
+            "password",
+            Path::new("secrets.toml.age"),
+        )
+        .unwrap();
+
+        assert_eq!(&value.0, "secret-value");
+    }
+
+    #[test]
+    fn should_not_look_up_missing_key() {
+        let result = lookup(
+            r#"password = "secret-value""#,
+            "other",
+            Path::new("secrets.toml.age"),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_update_existing_secrets() {
+        let updated = update(
+            "a = \"1\"\nb = \"2\"\n",
+            "b",
+            SecretValue::new("new"),
+            Path::new("secrets.toml.age"),
+        )
+        .unwrap();
+
+        assert_eq!(updated, "a = \"1\"\nb = \"new\"\n");
+    }
+
+    #[test]
+    fn should_add_secret_to_empty_file() {
+        let updated = update(
+            "",
+            "password",
+            SecretValue::new("value"),
+            Path::new("secrets.toml.age"),
+        )
+        .unwrap();
+
+        assert_eq!(updated, "password = \"value\"\n");
+    }
+
+    #[test]
+    fn should_parse_recipients_file() {
+        let recipients = parse_recipients(
+            "# laptop\nage1laptop\n\n  age1server  \nssh-ed25519 AAAAC3Nz user@host\n",
+        );
+
+        assert_eq!(
+            recipients,
+            vec!["age1laptop", "age1server", "ssh-ed25519 AAAAC3Nz user@host"]
+        );
+    }
+
+    #[test]
+    fn should_name_recipients_file_after_secrets_file() {
+        assert_eq!(
+            recipients_file(Path::new("/etc/cirrus/secrets.toml.age")),
+            PathBuf::from("/etc/cirrus/secrets.toml.age.recipients")
+        );
+    }
+
+    fn age_is_installed() -> bool {
+        [AGE, AGE_KEYGEN]
+            .iter()
+            .all(|program| Command::new(program).arg("--version").output().is_ok())
+    }
+
+    fn generate_identity() -> SecretValue {
+        let output = run(AGE_KEYGEN, &[], b"").unwrap();
+        SecretValue::new(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn should_encrypt_and_decrypt_secrets_file() {
+        if !age_is_installed() {
+            eprintln!("skipping test, age is not installed");
+            return;
+        }
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("secrets.toml.age");
+        let file = file.to_str().unwrap();
+        let identity = generate_identity();
+
+        set_secret(file, "password", &identity, SecretValue::new("one")).unwrap();
+        set_secret(file, "other", &identity, SecretValue::new("two")).unwrap();
+
+        assert_eq!(get_secret(file, "password", &identity).unwrap().0, "one");
+        assert_eq!(get_secret(file, "other", &identity).unwrap().0, "two");
+    }
+
+    #[test]
+    fn should_keep_recipients_from_recipients_file() {
+        if !age_is_installed() {
+            eprintln!("skipping test, age is not installed");
+            return;
+        }
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("secrets.toml.age");
+        let identity = generate_identity();
+        let other_identity = generate_identity();
+        std::fs::write(
+            recipients_file(&file),
+            recipients(&other_identity).unwrap().join("\n"),
+        )
+        .unwrap();
+        let file = file.to_str().unwrap();
+
+        set_secret(file, "password", &identity, SecretValue::new("one")).unwrap();
+
+        assert_eq!(get_secret(file, "password", &identity).unwrap().0, "one");
+        assert_eq!(
+            get_secret(file, "password", &other_identity).unwrap().0,
+            "one"
+        );
+    }
+
+    #[test]
+    fn should_fail_if_age_is_missing() {
+        let result = run("/nonexistent/age", &[], b"");
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::secrets::SecretValue;
+use eyre::{eyre, WrapErr};
+use humantime_serde::re::humantime;
+use std::{
+    io::Read,
+    process::{Command, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+fn read_to_end(mut output: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        output.read_to_end(&mut buf).ok();
+        buf
+    })
+}
+
+pub(super) fn get_secret(command: &[String]) -> eyre::Result<SecretValue> {
+    get_secret_with_timeout(command, TIMEOUT)
+}
+
+/// Runs the command and returns its trimmed standard output as the secret value. Standard error
+/// is only used for the error message if the command fails.
+fn get_secret_with_timeout(command: &[String], timeout: Duration) -> eyre::Result<SecretValue> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| eyre!("secret command is empty"))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("failed to start secret command '{}'", program))?;
+    let stdout = read_to_end(child.stdout.take().unwrap());
+    let stderr = read_to_end(child.stderr.take().unwrap());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .wrap_err("error getting secret command status")?
+        {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(eyre!(
+                "secret command '{}' timed out after {}",
+                program,
+                humantime::format_duration(timeout)
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        let message = format!("secret command '{}' failed with {}", program, status);
+        let stderr = String::from_utf8_lossy(&stderr);
+        return Err(match stderr.trim() {
+            "" => eyre!(message),
+            stderr => eyre!(stderr.to_owned()).wrap_err(message),
+        });
+    }
+
+    let value = String::from_utf8(stdout).wrap_err("secret command output is not valid UTF-8")?;
+    Ok(SecretValue::new(value.trim()))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Vec<String> {
+        vec!["sh".to_owned(), "-c".to_owned(), script.to_owned()]
+    }
+
+    #[test]
+    fn should_get_trimmed_output() {
+        let value = get_secret(&sh("printf '  secret-value\\n\\n'")).unwrap();
+
+        assert_eq!(&value.0, "secret-value");
+    }
+
+    #[test]
+    fn should_include_stderr_in_error() {
+        let result = get_secret(&sh("echo 'vault is sealed' >&2; exit 2"));
+
+        let error = format!("{:#}", result.err().unwrap());
+        assert!(error.contains("vault is sealed"), "{}", error);
+        assert!(error.contains("exit status: 2"), "{}", error);
+    }
+
+    #[test]
+    fn should_fail_on_timeout() {
+        let result = get_secret_with_timeout(&sh("sleep 10"), Duration::from_millis(100));
+
+        let error = format!("{:#}", result.err().unwrap());
+        assert!(error.contains("timed out"), "{}", error);
+    }
+
+    #[test]
+    fn should_fail_for_missing_program() {
+        let result = get_secret(&["/nonexistent/secret-command".to_owned()]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_fail_for_empty_command() {
+        let result = get_secret(&[]);
+
+        assert!(result.is_err());
+    }
+}
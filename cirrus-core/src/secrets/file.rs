@@ -0,0 +1,93 @@
+use crate::secrets::SecretValue;
+use eyre::{eyre, WrapErr};
+use std::path::{Path, PathBuf};
+
+const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+/// Whether other users can read the file. Always false on platforms without Unix permissions.
+pub(crate) fn is_world_readable(path: &Path) -> std::io::Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        Ok(std::fs::metadata(path)?.permissions().mode() & 0o004 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(false)
+    }
+}
+
+fn read_secret_file(path: &Path) -> eyre::Result<SecretValue> {
+    let mut value = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read secret file '{}'", path.display()))?;
+    // editors usually add a final line break that isn't part of the secret
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    Ok(SecretValue::new(value))
+}
+
+pub(super) fn get_secret(file: &str) -> eyre::Result<SecretValue> {
+    let path = Path::new(file);
+    if matches!(is_world_readable(path), Ok(true)) {
+        tracing::warn!(file, "secret file is readable by other users");
+    }
+    read_secret_file(path)
+}
+
+fn credential_path(name: &str) -> eyre::Result<PathBuf> {
+    let dir = std::env::var_os(CREDENTIALS_DIRECTORY).ok_or_else(|| {
+        eyre!(
+            "{} is not set, systemd credentials are only available to services",
+            CREDENTIALS_DIRECTORY
+        )
+    })?;
+    Ok(Path::new(&dir).join(name))
+}
+
+pub(super) fn get_systemd_credential(name: &str) -> eyre::Result<SecretValue> {
+    read_secret_file(&credential_path(name)?)
+        .wrap_err_with(|| format!("failed to read systemd credential '{}'", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_whole_file_without_final_line_break() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("password");
+        std::fs::write(&path, " secret value \n").unwrap();
+
+        let value = get_secret(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(&value.0, " secret value ");
+    }
+
+    #[test]
+    fn should_not_read_missing_file() {
+        let result = get_secret("/tmp/nopenopenope");
+
+        assert!(result.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_detect_world_readable_file() {
+        use std::os::unix::fs::PermissionsExt as _;
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("password");
+        std::fs::write(&path, "secret").unwrap();
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(!is_world_readable(&path).unwrap());
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(is_world_readable(&path).unwrap());
+    }
+}
@@ -1,25 +1,77 @@
 use crate::config::repo::{self, Secret, SecretName};
 use eyre::{eyre, WrapErr};
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
+mod age;
+mod command;
+mod file;
 #[cfg(not(feature = "keyring"))]
 mod keyring_disabled;
 #[cfg(feature = "keyring")]
 mod os_keyring;
+mod redact;
 
 #[cfg(not(feature = "keyring"))]
 use keyring_disabled::*;
 #[cfg(feature = "keyring")]
 use os_keyring::*;
 
-pub struct SecretValue(pub String);
+pub(crate) use file::is_world_readable;
+pub use redact::{redact, REDACTED};
+
+/// A secret can't be resolved right now but might be later, e.g. because the OS keyring is
+/// locked or its service isn't running.
+#[derive(Debug, thiserror::Error)]
+#[error("secret storage is unavailable")]
+pub struct Unavailable(#[source] pub(crate) Box<dyn std::error::Error + Send + Sync>);
+
+/// Whether the error only means that the secret isn't available yet, see [Unavailable].
+pub fn is_unavailable(error: &eyre::Report) -> bool {
+    error.chain().any(|cause| cause.is::<Unavailable>())
+}
+
+/// A secret value. Values returned by [Secrets::get_secret] hold a registration that keeps them
+/// redacted from output until the value and all of its clones are dropped.
+#[derive(Clone)]
+pub struct SecretValue(pub String, Option<Arc<redact::Registration>>);
 
 impl SecretValue {
     pub fn new(value: impl Into<String>) -> Self {
-        SecretValue(value.into())
+        SecretValue(value.into(), None)
     }
 }
 
+/// Overwrites the string's bytes with zeros. Volatile writes keep the compiler from optimizing
+/// the writes away since the string is never read again.
+fn zeroize(s: &mut String) {
+    // SAFETY: zero bytes are valid UTF-8
+    let bytes = unsafe { s.as_mut_vec() };
+    for byte in bytes.iter_mut() {
+        // SAFETY: the pointer comes from a valid mutable reference
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+impl Drop for SecretValue {
+    fn drop(&mut self) {
+        zeroize(&mut self.0);
+    }
+}
+
+impl std::fmt::Debug for SecretValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SecretValue").field(&REDACTED).finish()
+    }
+}
+
+impl std::fmt::Display for SecretValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[derive(Debug)]
 pub struct RepoWithSecrets<'a> {
     pub repo: &'a repo::Definition,
     pub repo_password: SecretValue,
@@ -30,12 +82,18 @@ pub struct RepoWithSecrets<'a> {
 pub struct Secrets;
 
 impl Secrets {
+    /// Resolves the secret. The value is remembered so it can be redacted from any output.
     pub fn get_secret(&self, secret: &Secret) -> eyre::Result<SecretValue> {
+        let value = self.resolve(secret)?;
+        Ok(redact::register(value))
+    }
+
+    fn resolve(&self, secret: &Secret) -> eyre::Result<SecretValue> {
         match secret {
             Secret::FromEnvVar { env_var } => {
                 let value = std::env::var(env_var)
                     .wrap_err_with(|| format!("environment variable '{}' not set", env_var))?;
-                Ok(SecretValue(value))
+                Ok(SecretValue::new(value))
             }
             Secret::FromOsKeyring { keyring } => get_secret(keyring),
             Secret::FromToml { toml, key } => {
@@ -49,6 +107,14 @@ impl Secrets {
                     .ok_or_else(|| eyre!("key '{}' not found in secrets file '{}'", key, toml))
                     .map(SecretValue::new)
             }
+            Secret::FromCommand { command } => command::get_secret(command),
+            Secret::FromAgeToml {
+                file,
+                key,
+                identity,
+            } => age::get_secret(file, key, &self.get_secret(identity)?),
+            Secret::FromFile { file } => file::get_secret(file),
+            Secret::FromSystemdCredential { name } => file::get_systemd_credential(name),
         }
     }
 
@@ -59,6 +125,11 @@ impl Secrets {
                 std::env::set_var(env_var, &value.0);
                 Ok(())
             }
+            Secret::FromAgeToml {
+                file,
+                key,
+                identity,
+            } => age::set_secret(file, key, &self.get_secret(identity)?, value),
             _ => Err(eyre!(
                 "{} secret must be configured externally",
                 secret.label()
@@ -66,6 +137,14 @@ impl Secrets {
         }
     }
 
+    /// Whether `set_secret` stores the value permanently rather than just for this process.
+    pub fn can_store(&self, secret: &Secret) -> bool {
+        matches!(
+            secret,
+            Secret::FromOsKeyring { .. } | Secret::FromAgeToml { .. }
+        )
+    }
+
     pub fn get_secrets<'a>(&self, repo: &'a repo::Definition) -> eyre::Result<RepoWithSecrets<'a>> {
         let repo_password = self.get_secret(&repo.password)?;
         let secrets = repo
@@ -102,6 +181,15 @@ mod tests {
         EnvGuard(key.to_owned())
     }
 
+    #[test]
+    fn should_recognize_unavailable_secret_storage() {
+        let unavailable = eyre::Report::new(Unavailable("locked".into())).wrap_err("no password");
+        let other = eyre::eyre!("environment variable 'X' not set");
+
+        assert!(is_unavailable(&unavailable));
+        assert!(!is_unavailable(&other));
+    }
+
     mod env_var {
         use super::*;
 
@@ -137,7 +225,7 @@ mod tests {
             };
 
             Secrets
-                .set_secret(&secret, SecretValue("secret-value".to_owned()))
+                .set_secret(&secret, SecretValue::new("secret-value"))
                 .unwrap();
 
             assert_eq!(&std::env::var("TEST_SECRET").unwrap(), "secret-value");
@@ -208,12 +296,66 @@ mod tests {
                 key: "key".to_string(),
             };
 
-            let result = Secrets.set_secret(&secret, SecretValue("nope".to_owned()));
+            let result = Secrets.set_secret(&secret, SecretValue::new("nope"));
 
             assert!(result.is_err());
         }
     }
 
+    mod systemd_credential {
+        use super::*;
+
+        #[test]
+        fn should_get_secret_from_credentials_directory() {
+            let secret = Secret::FromSystemdCredential {
+                name: "restic-password".to_owned(),
+            };
+            let tmp = tempfile::tempdir().unwrap();
+            std::fs::write(tmp.path().join("restic-password"), "secret-value\n").unwrap();
+
+            std::env::remove_var("CREDENTIALS_DIRECTORY");
+            assert!(Secrets.get_secret(&secret).is_err());
+
+            let _guard = set_env("CREDENTIALS_DIRECTORY", tmp.path().to_str().unwrap());
+            let value = Secrets.get_secret(&secret).unwrap();
+            assert_eq!(&value.0, "secret-value");
+        }
+
+        #[test]
+        fn should_not_set_secret() {
+            let secret = Secret::FromSystemdCredential {
+                name: "restic-password".to_owned(),
+            };
+
+            let result = Secrets.set_secret(&secret, SecretValue::new("nope"));
+
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn should_zeroize_string() {
+        let mut value = "secret".to_string();
+
+        zeroize(&mut value);
+
+        assert_eq!(value.as_bytes(), &[0; 6]);
+    }
+
+    #[test]
+    fn should_not_show_secret_value_in_debug_output() {
+        let value = SecretValue::new("secret");
+
+        assert_eq!(format!("{:?}", value), "SecretValue(\"***\")");
+    }
+
+    #[test]
+    fn should_not_show_secret_value_in_display_output() {
+        let value = SecretValue::new("secret");
+
+        assert_eq!(format!("{}", value), "***");
+    }
+
     #[test]
     fn should_load_all_secrets_for_repo() {
         let mut tmp = tempfile::NamedTempFile::new().unwrap();
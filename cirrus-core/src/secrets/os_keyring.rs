@@ -1,4 +1,4 @@
-use crate::secrets::SecretValue;
+use crate::secrets::{SecretValue, Unavailable};
 use eyre::WrapErr;
 use std::{
     error::Error,
@@ -21,9 +21,15 @@ const KEYRING_SERVICE: &str = "io.gitlab.fkrull.cirrus";
 pub(super) fn get_secret(name: &str) -> eyre::Result<SecretValue> {
     let value = keyring::Entry::new(KEYRING_SERVICE, name)
         .get_password()
-        .map_err(SyncError::new)
+        .map_err(|error| match error {
+            // a locked keyring or a keyring service that isn't running yet
+            keyring::Error::NoStorageAccess(_) | keyring::Error::PlatformFailure(_) => {
+                eyre::Report::new(Unavailable(Box::new(SyncError::new(error))))
+            }
+            error => eyre::Report::new(SyncError::new(error)),
+        })
         .wrap_err_with(|| format!("no stored password for key '{}'", name))?;
-    Ok(SecretValue(value))
+    Ok(SecretValue::new(value))
 }
 
 pub(super) fn set_secret(name: &str, value: SecretValue) -> eyre::Result<()> {
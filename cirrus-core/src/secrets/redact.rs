@@ -0,0 +1,138 @@
+use crate::secrets::{zeroize, SecretValue};
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex, Weak},
+};
+
+pub const REDACTED: &str = "***";
+
+/// Shorter values would garble unrelated output without protecting much.
+const MIN_REDACTED_LEN: usize = 4;
+
+/// A copy of a secret value that is redacted from output as long as any [SecretValue] holding it
+/// is alive. The copy is zeroized when the last one is dropped.
+pub(crate) struct Registration(String);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        zeroize(&mut self.0);
+    }
+}
+
+/// The secret values that are currently in use in this process, so they can be scrubbed from
+/// output.
+static KNOWN_SECRETS: Mutex<Vec<Weak<Registration>>> = Mutex::new(Vec::new());
+
+/// Registers the value for redaction until it and all of its clones are dropped.
+pub(crate) fn register(mut value: SecretValue) -> SecretValue {
+    if value.0.len() < MIN_REDACTED_LEN {
+        return value;
+    }
+    let mut known = KNOWN_SECRETS.lock().unwrap();
+    known.retain(|registration| registration.strong_count() > 0);
+    let existing = known
+        .iter()
+        .filter_map(Weak::upgrade)
+        .find(|registration| registration.0 == value.0);
+    let registration = existing.unwrap_or_else(|| {
+        let registration = Arc::new(Registration(value.0.clone()));
+        known.push(Arc::downgrade(&registration));
+        registration
+    });
+    value.1 = Some(registration);
+    value
+}
+
+/// Replaces all known secret values in the text.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let mut known = KNOWN_SECRETS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect::<Vec<_>>();
+    // replace longer secrets first in case one contains another
+    known.sort_by_key(|registration| std::cmp::Reverse(registration.0.len()));
+    let mut redacted = Cow::Borrowed(text);
+    for secret in &known {
+        if redacted.contains(secret.0.as_str()) {
+            redacted = Cow::Owned(redacted.replace(secret.0.as_str(), REDACTED));
+        }
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the registry is global, so every test uses its own values
+
+    #[test]
+    fn should_redact_registered_secret() {
+        let _value = register(SecretValue::new("redact-test-secret"));
+
+        let redacted = redact("Fatal: wrong password redact-test-secret for repository");
+
+        assert_eq!(redacted, "Fatal: wrong password *** for repository");
+    }
+
+    #[test]
+    fn should_not_change_text_without_secrets() {
+        let redacted = redact("nothing to see here");
+
+        assert!(matches!(redacted, Cow::Borrowed("nothing to see here")));
+    }
+
+    #[test]
+    fn should_redact_longer_secret_first() {
+        let _short = register(SecretValue::new("nested-test"));
+        let _long = register(SecretValue::new("nested-test-secret"));
+
+        let redacted = redact("nested-test-secret and nested-test");
+
+        assert_eq!(redacted, "*** and ***");
+    }
+
+    #[test]
+    fn should_not_redact_short_values() {
+        let _value = register(SecretValue::new("ab"));
+
+        let redacted = redact("abc");
+
+        assert_eq!(redacted, "abc");
+    }
+
+    #[test]
+    fn should_redact_secret_while_a_clone_is_alive() {
+        let value = register(SecretValue::new("cloned-test-secret"));
+        let clone = value.clone();
+
+        drop(value);
+
+        assert_eq!(redact("cloned-test-secret"), "***");
+        drop(clone);
+    }
+
+    #[test]
+    fn should_forget_secret_when_all_values_are_dropped() {
+        let value = register(SecretValue::new("dropped-test-secret"));
+        let clone = value.clone();
+
+        drop(value);
+        drop(clone);
+
+        assert_eq!(redact("dropped-test-secret"), "dropped-test-secret");
+    }
+
+    #[test]
+    fn should_share_registration_of_equal_values() {
+        let first = register(SecretValue::new("shared-test-secret"));
+        let second = register(SecretValue::new("shared-test-secret"));
+
+        drop(first);
+
+        assert_eq!(redact("shared-test-secret"), "***");
+        drop(second);
+    }
+}
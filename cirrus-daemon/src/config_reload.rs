@@ -1,8 +1,31 @@
 use crate::{shutdown::ShutdownAcknowledged, shutdown::ShutdownRequested};
-use cirrus_core::config::Config;
+use cirrus_core::{
+    config::{check::Severity, include::WatchedDir, Config},
+    restic::Restic,
+};
 use notify::Watcher;
 use std::sync::Arc;
 
+/// Logs all problems found by checking the configuration, including settings the installed restic
+/// doesn't support. Secrets aren't resolved here, the secrets cache takes care of that when a job
+/// needs them.
+pub async fn log_config_issues(config: &Config, restic: &Restic) {
+    for filtered in &config.filtered {
+        tracing::info!(%filtered, "skipping configuration entry");
+    }
+    let mut issues = config.check(None);
+    // the version is cached, and failing to get it was already logged at startup
+    if let Ok(version) = restic.version().await {
+        issues.extend(config.check_restic_version(version));
+    }
+    for issue in issues {
+        match issue.severity() {
+            Severity::Error => tracing::error!(%issue, "configuration problem"),
+            Severity::Warning => tracing::warn!(%issue, "configuration problem"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ConfigReload {
     pub new_config: Arc<Config>,
@@ -19,11 +42,17 @@ events::subscriptions! {
 pub struct ConfigReloadService {
     events: Subscriptions,
     config: Arc<Config>,
+    restic: Arc<Restic>,
     watcher: notify::RecommendedWatcher,
+    watched_dirs: Vec<WatchedDir>,
 }
 
 impl ConfigReloadService {
-    pub fn new(config: Arc<Config>, events: &mut events::Builder) -> eyre::Result<Self> {
+    pub fn new(
+        config: Arc<Config>,
+        restic: Arc<Restic>,
+        events: &mut events::Builder,
+    ) -> eyre::Result<Self> {
         let notify_sender = events.typed_sender::<NotifyEvent>();
         let watcher = notify::recommended_watcher(move |ev| match ev {
             Ok(event) => {
@@ -35,7 +64,9 @@ impl ConfigReloadService {
         Ok(ConfigReloadService {
             events: Subscriptions::subscribe(events),
             config,
+            restic,
             watcher,
+            watched_dirs: Vec::new(),
         })
     }
 
@@ -46,8 +77,10 @@ impl ConfigReloadService {
             match result {
                 Ok(config) => {
                     tracing::info!(path = %config_path.display(), "reloaded configuration");
+                    log_config_issues(&config, &self.restic).await;
                     let config = Arc::new(config);
                     self.config = config;
+                    self.update_watches();
                     self.events.send(ConfigReload {
                         new_config: self.config.clone(),
                     });
@@ -62,7 +95,7 @@ impl ConfigReloadService {
 
     #[tracing::instrument(name = "ConfigReloadService", skip_all)]
     pub async fn run(&mut self) -> eyre::Result<()> {
-        self.start_watch()?;
+        self.update_watches();
         loop {
             tokio::select! {
                 notify_event = self.events.NotifyEvent.recv() => self.handle_notify_event(notify_event?).await?,
@@ -74,31 +107,62 @@ impl ConfigReloadService {
         }
     }
 
-    fn start_watch(&mut self) -> eyre::Result<()> {
-        use notify::RecursiveMode::NonRecursive;
+    fn update_watches(&mut self) {
+        // included files and directories can come and go, so re-sync the watches with every
+        // loaded configuration; a directory that can't be watched shouldn't stop the service
+        use notify::RecursiveMode::{NonRecursive, Recursive};
 
-        if let Some(config_path) = &self.config.source {
-            tracing::info!(
-                path = %config_path.display(),
-                "watching configuration file for changes"
-            );
-            self.watcher.watch(config_path, NonRecursive)?;
+        let new_dirs = self.config.watched_dirs();
+        for dir in &self.watched_dirs {
+            if !new_dirs.contains(dir) {
+                tracing::info!(path = %dir.path.display(), "no longer watching for changes");
+                if let Err(error) = self.watcher.unwatch(&dir.path) {
+                    tracing::warn!(%error, path = %dir.path.display(), "failed to stop watching");
+                }
+            }
         }
-        Ok(())
+        let mut watched_dirs = Vec::new();
+        for dir in new_dirs {
+            if !self.watched_dirs.contains(&dir) {
+                tracing::info!(path = %dir.path.display(), "watching configuration for changes");
+                let mode = if dir.recursive {
+                    Recursive
+                } else {
+                    NonRecursive
+                };
+                if let Err(error) = self.watcher.watch(&dir.path, mode) {
+                    tracing::error!(%error, path = %dir.path.display(), "failed to watch for changes");
+                    continue;
+                }
+            }
+            watched_dirs.push(dir);
+        }
+        self.watched_dirs = watched_dirs;
     }
 
     async fn handle_notify_event(&mut self, ev: NotifyEvent) -> eyre::Result<()> {
-        if !ev.0.kind.is_create() && !ev.0.kind.is_modify() {
+        if !ev.0.kind.is_create() && !ev.0.kind.is_modify() && !ev.0.kind.is_remove() {
             // don't care about this one
             return Ok(());
         }
+        if !ev
+            .0
+            .paths
+            .iter()
+            .any(|path| self.config.is_affected_by(path))
+        {
+            // some other file in a watched directory
+            return Ok(());
+        }
         self.reload_config().await
     }
 
     async fn handle_shutdown(&mut self, _: ShutdownRequested) -> eyre::Result<()> {
         tracing::debug!("received shutdown event");
-        if let Some(config_path) = &self.config.source {
-            self.watcher.unwatch(config_path)?;
+        for dir in self.watched_dirs.drain(..) {
+            if let Err(error) = self.watcher.unwatch(&dir.path) {
+                tracing::warn!(%error, path = %dir.path.display(), "failed to stop watching");
+            }
         }
         self.events.send(ShutdownAcknowledged);
         Ok(())
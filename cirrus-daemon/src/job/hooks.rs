@@ -0,0 +1,454 @@
+use crate::job::{
+    self,
+    runner::{Cancellation, JobOutcome},
+};
+use eyre::WrapErr;
+use std::{process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+};
+
+/// How long hooks that run after the backup may still take once the job is cancelled.
+const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Stage {
+    Before,
+    After,
+    OnSuccess,
+    OnFailure,
+}
+
+impl Stage {
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Before => "before",
+            Stage::After => "after",
+            Stage::OnSuccess => "on_success",
+            Stage::OnFailure => "on_failure",
+        }
+    }
+
+    fn commands<'a>(&self, hooks: &'a cirrus_core::config::backup::Hooks) -> &'a [String] {
+        match self {
+            Stage::Before => &hooks.before,
+            Stage::After => &hooks.after,
+            Stage::OnSuccess => &hooks.on_success,
+            Stage::OnFailure => &hooks.on_failure,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Outcome {
+    Success,
+    Failure,
+    Cancelled,
+}
+
+impl Outcome {
+    pub(super) fn of(result: &Result<(), JobOutcome>) -> Self {
+        match result {
+            Ok(()) => Outcome::Success,
+            Err(JobOutcome::Error(_)) => Outcome::Failure,
+            Err(JobOutcome::Cancelled(_)) => Outcome::Cancelled,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Runs the hook commands of a stage one after the other. `before` commands stop at the first
+/// failure, all other stages run every command so cleanup isn't skipped.
+///
+/// Cancelling the job stops `before` commands right away. Commands of the other stages get
+/// [CANCEL_GRACE_PERIOD] to finish instead, so they can still clean up after a cancelled backup.
+pub(super) async fn run(
+    spec: &job::BackupSpec,
+    stage: Stage,
+    outcome: Option<Outcome>,
+    cancellation: &mut Cancellation<'_>,
+) -> Result<(), JobOutcome> {
+    let hooks = &spec.backup.hooks;
+    let mut env = vec![
+        ("CIRRUS_BACKUP", spec.backup_name.0.as_str()),
+        ("CIRRUS_REPOSITORY", spec.repo_name.0.as_str()),
+        ("CIRRUS_REPOSITORY_URL", spec.repo.url.0.as_str()),
+        ("CIRRUS_HOOK", stage.name()),
+    ];
+    if let Some(outcome) = outcome {
+        env.push(("CIRRUS_OUTCOME", outcome.name()));
+    }
+
+    let mut result = Ok(());
+    for command in stage.commands(hooks) {
+        let hook_result = run_command(
+            command,
+            &env,
+            hooks.timeout,
+            stage == Stage::Before,
+            cancellation,
+        )
+        .await;
+        match hook_result {
+            Ok(()) => {}
+            Err(JobOutcome::Error(error)) => {
+                let error = error.wrap_err(format!("{} hook failed", stage.name()));
+                tracing::warn!(error = format!("{:#}", error), command, "hook failed");
+                if stage == Stage::Before {
+                    return Err(error.into());
+                }
+                result = result.and(Err(error.into()));
+            }
+            Err(cancelled) => return Err(cancelled),
+        }
+    }
+    result
+}
+
+/// The shell runs in its own process group, so stopping a hook also stops the commands it
+/// started.
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        });
+    }
+    cmd
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+/// Kills the hook's shell together with everything it started.
+#[cfg(unix)]
+async fn kill(child: &mut Child) -> std::io::Result<()> {
+    let killed_group = match child.id() {
+        // a negative PID addresses the process group
+        Some(pid) => unsafe { libc::kill(-(pid as i32), libc::SIGKILL) == 0 },
+        None => false,
+    };
+    if killed_group {
+        child.wait().await.map(|_| ())
+    } else {
+        child.kill().await
+    }
+}
+
+/// Kills the hook's shell together with everything it started.
+#[cfg(windows)]
+async fn kill(child: &mut Child) -> std::io::Result<()> {
+    let killed_tree = match child.id() {
+        Some(pid) => Command::new("taskkill")
+            .args(["/F", "/T", "/PID", &pid.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .map_or(false, |status| status.success()),
+        None => false,
+    };
+    if killed_tree {
+        child.wait().await.map(|_| ())
+    } else {
+        child.kill().await
+    }
+}
+
+async fn log_lines(output: Option<impl AsyncRead + Unpin>, warn: bool) {
+    let mut lines = match output {
+        Some(output) => BufReader::new(output).lines(),
+        None => return,
+    };
+    while let Ok(Some(line)) = lines.next_line().await {
+        if warn {
+            tracing::warn!("{}", line);
+        } else {
+            tracing::info!("{}", line);
+        }
+    }
+}
+
+enum CommandResult {
+    Finished(std::io::Result<std::process::ExitStatus>),
+    TimedOut,
+    Cancelled(job::CancellationReason),
+}
+
+#[tracing::instrument(name = "hook", skip_all, fields(command = command))]
+async fn run_command(
+    command: &str,
+    env: &[(&str, &str)],
+    timeout: Duration,
+    stop_on_cancel: bool,
+    cancellation: &mut Cancellation<'_>,
+) -> Result<(), JobOutcome> {
+    let already_cancelled = cancellation.is_cancelled();
+    let timeout = if already_cancelled {
+        timeout.min(CANCEL_GRACE_PERIOD)
+    } else {
+        timeout
+    };
+    tracing::info!("running hook");
+    let mut child = shell_command(command)
+        .envs(env.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .wrap_err_with(|| format!("failed to start hook command `{}`", command))?;
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    let result = {
+        let finished = async {
+            let (_, _, status) = tokio::join!(
+                log_lines(stdout, false),
+                log_lines(stderr, true),
+                child.wait()
+            );
+            status
+        };
+        tokio::pin!(finished);
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+        let result = tokio::select! {
+            status = &mut finished => CommandResult::Finished(status),
+            _ = &mut deadline => CommandResult::TimedOut,
+            reason = cancellation.recv(), if !already_cancelled => CommandResult::Cancelled(reason),
+        };
+        match result {
+            CommandResult::Cancelled(_) if !stop_on_cancel => {
+                tracing::info!(
+                    grace_period_secs = CANCEL_GRACE_PERIOD.as_secs_f64(),
+                    "job cancelled, waiting for hook to finish"
+                );
+                let grace_deadline = tokio::time::Instant::now() + CANCEL_GRACE_PERIOD;
+                if grace_deadline < deadline.deadline() {
+                    deadline.as_mut().reset(grace_deadline);
+                }
+                tokio::select! {
+                    status = &mut finished => CommandResult::Finished(status),
+                    _ = &mut deadline => CommandResult::TimedOut,
+                }
+            }
+            result => result,
+        }
+    };
+
+    match result {
+        CommandResult::Finished(status) => {
+            let status = status.wrap_err("error getting hook command status")?;
+            if !status.success() {
+                return Err(
+                    eyre::eyre!("hook command `{}` failed with {}", command, status).into(),
+                );
+            }
+            Ok(())
+        }
+        CommandResult::TimedOut if cancellation.is_cancelled() => {
+            kill(&mut child).await?;
+            Err(eyre::eyre!(
+                "hook command `{}` was stopped because the job was cancelled",
+                command
+            )
+            .into())
+        }
+        CommandResult::TimedOut => {
+            kill(&mut child).await?;
+            Err(eyre::eyre!(
+                "hook command `{}` timed out after {}",
+                command,
+                humantime::format_duration(timeout)
+            )
+            .into())
+        }
+        CommandResult::Cancelled(reason) => {
+            kill(&mut child).await?;
+            Err(reason.into())
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use cirrus_core::config::{backup, repo};
+    use tokio::sync::oneshot;
+
+    fn spec(hooks: backup::Hooks) -> job::BackupSpec {
+        job::BackupSpec {
+            repo_name: repo::Name("repo".to_string()),
+            backup_name: backup::Name("bkp".to_string()),
+            repo: repo::Definition {
+                url: repo::Url("/srv/repo".to_string()),
+                ..Default::default()
+            },
+            backup: backup::Definition {
+                hooks,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn should_run_hooks_with_environment() {
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        let spec = spec(backup::Hooks {
+            after: vec![
+                format!(
+                    "echo $CIRRUS_BACKUP $CIRRUS_REPOSITORY $CIRRUS_REPOSITORY_URL >> {}",
+                    out.display()
+                ),
+                format!("echo $CIRRUS_HOOK $CIRRUS_OUTCOME >> {}", out.display()),
+            ],
+            ..Default::default()
+        });
+
+        run(
+            &spec,
+            Stage::After,
+            Some(Outcome::Failure),
+            &mut Cancellation::none(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "bkp repo /srv/repo\nafter failure\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_stop_before_hooks_at_first_failure() {
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        let spec = spec(backup::Hooks {
+            before: vec!["exit 3".to_string(), format!("touch {}", out.display())],
+            ..Default::default()
+        });
+
+        let result = run(&spec, Stage::Before, None, &mut Cancellation::none()).await;
+
+        assert!(matches!(result, Err(JobOutcome::Error(_))));
+        assert!(!out.exists());
+    }
+
+    #[tokio::test]
+    async fn should_run_all_after_hooks_despite_failure() {
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        let spec = spec(backup::Hooks {
+            after: vec!["exit 3".to_string(), format!("touch {}", out.display())],
+            ..Default::default()
+        });
+
+        let result = run(
+            &spec,
+            Stage::After,
+            Some(Outcome::Success),
+            &mut Cancellation::none(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(JobOutcome::Error(_))));
+        assert!(out.exists());
+    }
+
+    #[tokio::test]
+    async fn should_fail_hook_that_times_out() {
+        let spec = spec(backup::Hooks {
+            before: vec!["sleep 10".to_string()],
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        });
+
+        let result = run(&spec, Stage::Before, None, &mut Cancellation::none()).await;
+
+        assert!(matches!(result, Err(JobOutcome::Error(_))));
+    }
+
+    #[tokio::test]
+    async fn should_cancel_running_hook() {
+        let spec = spec(backup::Hooks {
+            before: vec!["sleep 10".to_string()],
+            ..Default::default()
+        });
+        let (send, mut recv) = oneshot::channel();
+        send.send(job::CancellationReason::Shutdown).unwrap();
+
+        let result = run(
+            &spec,
+            Stage::Before,
+            None,
+            &mut Cancellation::new(&mut recv),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(JobOutcome::Cancelled(job::CancellationReason::Shutdown))
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_finish_after_hooks_when_cancelled() {
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        let spec = spec(backup::Hooks {
+            after: vec!["sleep 0.2".to_string(), format!("touch {}", out.display())],
+            ..Default::default()
+        });
+        let (send, mut recv) = oneshot::channel();
+        send.send(job::CancellationReason::Shutdown).unwrap();
+
+        let result = run(
+            &spec,
+            Stage::After,
+            Some(Outcome::Success),
+            &mut Cancellation::new(&mut recv),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert!(out.exists());
+    }
+
+    #[tokio::test]
+    async fn should_kill_commands_started_by_hook_that_times_out() {
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        let spec = spec(backup::Hooks {
+            before: vec![format!("(sleep 0.5; touch {}) & wait", out.display())],
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        });
+
+        let result = run(&spec, Stage::Before, None, &mut Cancellation::none()).await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        assert!(matches!(result, Err(JobOutcome::Error(_))));
+        assert!(!out.exists());
+    }
+}
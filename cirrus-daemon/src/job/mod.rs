@@ -1,7 +1,11 @@
-use cirrus_core::config::{backup, repo};
+use cirrus_core::{
+    config::{backup, repo, retry},
+    restic::{BackupMessage, BackupSummary, FailureKind},
+};
 use std::time::Duration;
 use time::OffsetDateTime;
 
+mod hooks;
 pub mod queues;
 mod runner;
 
@@ -30,6 +34,8 @@ impl Id {
 pub struct Job {
     pub id: Id,
     pub spec: Spec,
+    /// counts from 1 and goes up every time a failed job is retried
+    pub attempt: u32,
 }
 
 impl Job {
@@ -37,19 +43,35 @@ impl Job {
         Job {
             id: Id::new(),
             spec,
+            attempt: 1,
+        }
+    }
+
+    /// The same job, run again after it failed.
+    pub(crate) fn next_attempt(&self) -> Self {
+        Job {
+            attempt: self.attempt + 1,
+            ..self.clone()
         }
     }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Spec {
-    Backup(BackupSpec),
+    Backup(Box<BackupSpec>),
+    Forget(ForgetSpec),
     FilesIndex(FilesIndexSpec),
 }
 
 impl From<BackupSpec> for Spec {
     fn from(spec: BackupSpec) -> Self {
-        Spec::Backup(spec)
+        Spec::Backup(Box::new(spec))
+    }
+}
+
+impl From<ForgetSpec> for Spec {
+    fn from(spec: ForgetSpec) -> Self {
+        Spec::Forget(spec)
     }
 }
 
@@ -63,6 +85,7 @@ impl Spec {
     pub(crate) fn repo_name(&self) -> &repo::Name {
         match self {
             Spec::Backup(spec) => &spec.repo_name,
+            Spec::Forget(spec) => &spec.repo_name,
             Spec::FilesIndex(spec) => &spec.repo_name,
         }
     }
@@ -70,13 +93,33 @@ impl Spec {
     pub(crate) fn repo(&self) -> &repo::Definition {
         match self {
             Spec::Backup(spec) => &spec.repo,
+            Spec::Forget(spec) => &spec.repo,
             Spec::FilesIndex(spec) => &spec.repo,
         }
     }
 
+    /// A backup's own retry policy takes precedence over the repository's.
+    pub(crate) fn retry_policy(&self) -> Option<&retry::Policy> {
+        match self {
+            Spec::Backup(spec) => spec.backup.retry.as_ref().or(spec.repo.retry.as_ref()),
+            Spec::Forget(spec) => spec.repo.retry.as_ref(),
+            Spec::FilesIndex(spec) => spec.repo.retry.as_ref(),
+        }
+    }
+
+    /// A backup's own timeout takes precedence over the repository's.
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        match self {
+            Spec::Backup(spec) => spec.backup.timeout.or(spec.repo.timeout),
+            Spec::Forget(spec) => spec.repo.timeout,
+            Spec::FilesIndex(spec) => spec.repo.timeout,
+        }
+    }
+
     pub fn label(&self) -> String {
         match self {
             Spec::Backup(spec) => format!("backup.{}", spec.backup_name.0),
+            Spec::Forget(spec) => format!("forget.{}", spec.backup_name.0),
             Spec::FilesIndex(spec) => format!("files-index.{}", spec.repo_name.0),
         }
     }
@@ -90,6 +133,14 @@ pub struct BackupSpec {
     pub backup: backup::Definition,
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ForgetSpec {
+    pub repo_name: repo::Name,
+    pub backup_name: backup::Name,
+    pub repo: repo::Definition,
+    pub retention: backup::Retention,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct FilesIndexSpec {
     pub repo_name: repo::Name,
@@ -102,6 +153,10 @@ pub struct StatusChange {
     pub job: Job,
     pub timestamp: OffsetDateTime,
     pub new_status: Status,
+    /// restic's summary when a backup finished successfully
+    pub backup_summary: Option<BackupSummary>,
+    /// why restic failed when a job finished with an error
+    pub failure_kind: Option<FailureKind>,
 }
 
 impl StatusChange {
@@ -110,16 +165,60 @@ impl StatusChange {
             job,
             timestamp: OffsetDateTime::now_utc(),
             new_status,
+            backup_summary: None,
+            failure_kind: None,
+        }
+    }
+
+    pub(crate) fn with_backup_summary(self, backup_summary: Option<BackupSummary>) -> Self {
+        StatusChange {
+            backup_summary,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_failure_kind(self, failure_kind: Option<FailureKind>) -> Self {
+        StatusChange {
+            failure_kind,
+            ..self
+        }
+    }
+}
+
+/// Progress reported by restic while a job is running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobProgress {
+    pub job: Job,
+    pub timestamp: OffsetDateTime,
+    pub message: BackupMessage,
+}
+
+impl JobProgress {
+    pub(crate) fn new(job: Job, message: BackupMessage) -> Self {
+        JobProgress {
+            job,
+            timestamp: OffsetDateTime::now_utc(),
+            message,
         }
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Status {
+    /// The job couldn't start because its secrets aren't available. It will be retried later.
+    Deferred,
     Started,
     // TODO it might be convenient to merge these into a single enum value
     FinishedSuccessfully,
     FinishedWithError,
+    /// The job finished with an error and runs again after the delay. `attempt` is the number of
+    /// the next attempt.
+    RetryScheduled {
+        attempt: u32,
+        delay: Duration,
+    },
+    /// The job was stopped because it ran longer than its timeout.
+    TimedOut,
     Cancelled(CancellationReason),
 }
 
@@ -127,4 +226,6 @@ pub enum Status {
 pub enum CancellationReason {
     Shutdown,
     Suspend,
+    /// only used within a job, which reports [Status::TimedOut] instead of being cancelled
+    Timeout,
 }
@@ -1,16 +1,23 @@
 use crate::{
     job,
+    secrets_cache::SecretsCache,
     shutdown::{ShutdownAcknowledged, ShutdownRequested},
     suspend::Suspend,
 };
-use cirrus_core::{cache::Cache, config, restic::Restic, secrets::Secrets};
+use cirrus_core::{
+    cache::Cache,
+    config,
+    restic::{FailureKind, Restic},
+};
 use std::{
     collections::{HashMap, VecDeque},
     sync::Arc,
+    time::Duration,
 };
-use tokio::sync::oneshot;
+use tokio::{sync::oneshot, time::Instant};
 
 const DEFAULT_PARALLEL_JOBS: u32 = 3;
+const DEFERRED_JOBS_RETRY_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Debug)]
 struct RunningJob {
@@ -18,15 +25,23 @@ struct RunningJob {
     cancellation: Option<oneshot::Sender<job::CancellationReason>>,
 }
 
+#[derive(Debug)]
+struct PendingRetry {
+    job: job::Job,
+    at: Instant,
+}
+
 #[derive(Debug)]
 struct RepositoryQueue {
     sender: events::Sender,
     restic: Arc<Restic>,
-    secrets: Arc<Secrets>,
+    secrets: SecretsCache,
     cache: Cache,
     queue: VecDeque<job::Job>,
     parallel_jobs: usize,
     running: HashMap<job::Id, RunningJob>,
+    deferred: Vec<job::Job>,
+    retries: Vec<PendingRetry>,
 }
 
 impl RepositoryQueue {
@@ -34,7 +49,7 @@ impl RepositoryQueue {
         repo: &config::repo::Definition,
         sender: events::Sender,
         restic: Arc<Restic>,
-        secrets: Arc<Secrets>,
+        secrets: SecretsCache,
         cache: Cache,
     ) -> Self {
         let parallel_jobs = repo.parallel_jobs.unwrap_or(DEFAULT_PARALLEL_JOBS) as usize;
@@ -46,6 +61,8 @@ impl RepositoryQueue {
             queue: VecDeque::new(),
             parallel_jobs,
             running: HashMap::with_capacity(parallel_jobs),
+            deferred: Vec::new(),
+            retries: Vec::new(),
         }
     }
 
@@ -58,6 +75,14 @@ impl RepositoryQueue {
             tracing::info!(id = %job.id, label = job.spec.label(), "job spec is currently in the queue, not enqueuing it again");
             return;
         }
+        if self.deferred.iter().any(|j| j.spec == job.spec) {
+            tracing::info!(id = %job.id, label = job.spec.label(), "job spec is currently deferred, not enqueuing it again");
+            return;
+        }
+        if self.retries.iter().any(|r| r.job.spec == job.spec) {
+            tracing::info!(id = %job.id, label = job.spec.label(), "job spec is waiting for a retry, not enqueuing it again");
+            return;
+        }
         tracing::info!(id = %job.id, label = job.spec.label(), "enqueuing");
         self.queue.push_back(job);
     }
@@ -111,6 +136,71 @@ impl RepositoryQueue {
         }
     }
 
+    fn job_deferred(&mut self, job: &job::Job) {
+        if let Some(running_job) = self.running.remove(&job.id) {
+            tracing::debug!(
+                id = %running_job.job.id,
+                label = ?running_job.job.spec.label(),
+                "deferring job until its secrets are available"
+            );
+            self.deferred.push(running_job.job);
+        }
+    }
+
+    fn retry_deferred(&mut self) {
+        for job in self.deferred.drain(..).rev() {
+            self.queue.push_front(job);
+        }
+    }
+
+    fn job_failed(&mut self, job: &job::Job, failure_kind: Option<FailureKind>) {
+        let job = match self.running.remove(&job.id) {
+            Some(running_job) => running_job.job,
+            None => return,
+        };
+        let delay = job
+            .spec
+            .retry_policy()
+            .and_then(|policy| policy.delay_after(job.attempt, failure_kind));
+        if let Some(delay) = delay {
+            let retry = job.next_attempt();
+            tracing::info!(
+                id = %retry.id,
+                label = retry.spec.label(),
+                attempt = retry.attempt,
+                delay = %humantime::format_duration(delay),
+                "scheduling retry"
+            );
+            self.sender.send(job::StatusChange::new(
+                job,
+                job::Status::RetryScheduled {
+                    attempt: retry.attempt,
+                    delay,
+                },
+            ));
+            self.retries.push(PendingRetry {
+                job: retry,
+                at: Instant::now() + delay,
+            });
+        }
+    }
+
+    fn next_retry(&self) -> Option<Instant> {
+        self.retries.iter().map(|retry| retry.at).min()
+    }
+
+    fn enqueue_due_retries(&mut self) {
+        let now = Instant::now();
+        let (due, pending) = std::mem::take(&mut self.retries)
+            .into_iter()
+            .partition(|retry| retry.at <= now);
+        self.retries = pending;
+        for retry in due.into_iter() {
+            tracing::info!(id = %retry.job.id, label = retry.job.spec.label(), attempt = retry.job.attempt, "retrying job");
+            self.queue.push_back(retry.job);
+        }
+    }
+
     fn cancel_all(&mut self, reason: job::CancellationReason) {
         for running_job in self.running.values_mut() {
             if let Some(cancel) = running_job.cancellation.take() {
@@ -133,7 +223,7 @@ events::subscriptions! {
 pub struct JobQueues {
     events: Subscriptions,
     restic: Arc<Restic>,
-    secrets: Arc<Secrets>,
+    secrets: SecretsCache,
     cache: Cache,
     suspend: Suspend,
     repo_queues: HashMap<config::repo::Name, RepositoryQueue>,
@@ -143,7 +233,7 @@ impl JobQueues {
     pub fn new(
         events: &mut events::Builder,
         restic: Arc<Restic>,
-        secrets: Arc<Secrets>,
+        secrets: SecretsCache,
         cache: Cache,
         suspend: Suspend,
     ) -> Self {
@@ -190,6 +280,37 @@ impl JobQueues {
         }
     }
 
+    fn job_deferred(&mut self, job: &job::Job) {
+        for queue in self.repo_queues.values_mut() {
+            queue.job_deferred(job);
+        }
+    }
+
+    fn retry_deferred(&mut self) {
+        for queue in self.repo_queues.values_mut() {
+            queue.retry_deferred();
+        }
+    }
+
+    fn job_failed(&mut self, job: &job::Job, failure_kind: Option<FailureKind>) {
+        for queue in self.repo_queues.values_mut() {
+            queue.job_failed(job, failure_kind);
+        }
+    }
+
+    fn next_retry(&self) -> Option<Instant> {
+        self.repo_queues
+            .values()
+            .filter_map(RepositoryQueue::next_retry)
+            .min()
+    }
+
+    fn enqueue_due_retries(&mut self) {
+        for queue in self.repo_queues.values_mut() {
+            queue.enqueue_due_retries();
+        }
+    }
+
     fn cancel_all(&mut self, reason: job::CancellationReason) {
         for queue in self.repo_queues.values_mut() {
             queue.cancel_all(reason);
@@ -202,13 +323,17 @@ impl JobQueues {
 
     fn handle_status_change(&mut self, status_change: job::StatusChange) {
         match status_change.new_status {
-            job::Status::Started => {}
+            job::Status::Started | job::Status::RetryScheduled { .. } => {}
+            job::Status::Deferred => self.job_deferred(&status_change.job),
             job::Status::Cancelled(job::CancellationReason::Suspend) => {
                 // jobs that were suspended will restart afterwards
                 self.job_finished(&status_change.job, true)
             }
+            job::Status::FinishedWithError => {
+                self.job_failed(&status_change.job, status_change.failure_kind)
+            }
             job::Status::FinishedSuccessfully
-            | job::Status::FinishedWithError
+            | job::Status::TimedOut
             | job::Status::Cancelled(_) => self.job_finished(&status_change.job, false),
         }
     }
@@ -237,9 +362,13 @@ impl JobQueues {
 
     #[tracing::instrument(name = "JobQueues", skip_all)]
     pub async fn run(&mut self) -> eyre::Result<()> {
+        let mut retry_interval = tokio::time::interval(DEFERRED_JOBS_RETRY_INTERVAL);
         loop {
+            let next_retry = self.next_retry();
             tokio::select! {
                 job = self.events.Job.recv() => self.push(job?),
+                _ = retry_interval.tick() => self.retry_deferred(),
+                _ = sleep_until(next_retry) => self.enqueue_due_retries(),
                 status_change = self.events.StatusChange.recv() => self.handle_status_change(status_change?),
                 suspend = self.events.Suspend.recv() => self.handle_suspend(suspend?),
                 shutdown = self.events.ShutdownRequested.recv() => {
@@ -251,3 +380,127 @@ impl JobQueues {
         }
     }
 }
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cirrus_core::{config::retry, secrets::Secrets};
+
+    fn repo_queue(events: &mut events::Builder) -> RepositoryQueue {
+        RepositoryQueue::new(
+            &Default::default(),
+            events.sender(),
+            Arc::new(Restic::new_with_path("restic")),
+            SecretsCache::new(Arc::new(Secrets), Duration::ZERO),
+            Cache::new(std::env::temp_dir()),
+        )
+    }
+
+    fn forget_job(retry: Option<retry::Policy>) -> job::Job {
+        job::Job::new(job::Spec::Forget(job::ForgetSpec {
+            repo_name: config::repo::Name("repo".to_owned()),
+            backup_name: config::backup::Name("backup".to_owned()),
+            repo: config::repo::Definition {
+                retry,
+                ..Default::default()
+            },
+            retention: Default::default(),
+        }))
+    }
+
+    fn run(queue: &mut RepositoryQueue, job: &job::Job) {
+        queue.running.insert(
+            job.id,
+            RunningJob {
+                job: job.clone(),
+                cancellation: None,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn should_schedule_retry_for_retryable_failure() {
+        let mut events = events::Builder::new_with_capacity(10);
+        let mut status_changes = events.subscribe::<job::StatusChange>();
+        let mut queue = repo_queue(&mut events);
+        let job = forget_job(Some(retry::Policy::default()));
+        run(&mut queue, &job);
+
+        queue.job_failed(&job, Some(FailureKind::Other));
+
+        let status_change = status_changes.recv().await.unwrap();
+        assert_eq!(
+            status_change.new_status,
+            job::Status::RetryScheduled {
+                attempt: 2,
+                delay: Duration::from_secs(60)
+            }
+        );
+        assert_eq!(queue.retries.len(), 1);
+        assert_eq!(queue.retries[0].job.attempt, 2);
+        assert!(queue.running.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_not_retry_failure_that_isnt_retryable() {
+        let mut events = events::Builder::new_with_capacity(10);
+        let mut queue = repo_queue(&mut events);
+        let job = forget_job(Some(retry::Policy::default()));
+        run(&mut queue, &job);
+
+        queue.job_failed(&job, Some(FailureKind::WrongPassword));
+
+        assert!(queue.retries.is_empty());
+        assert!(queue.running.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_not_retry_without_policy() {
+        let mut events = events::Builder::new_with_capacity(10);
+        let mut queue = repo_queue(&mut events);
+        let job = forget_job(None);
+        run(&mut queue, &job);
+
+        queue.job_failed(&job, Some(FailureKind::Other));
+
+        assert!(queue.retries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_enqueue_due_retries() {
+        let mut events = events::Builder::new_with_capacity(10);
+        let mut queue = repo_queue(&mut events);
+        let job = forget_job(Some(retry::Policy {
+            initial_delay: Duration::ZERO,
+            ..Default::default()
+        }));
+        run(&mut queue, &job);
+
+        queue.job_failed(&job, Some(FailureKind::Other));
+        queue.enqueue_due_retries();
+
+        assert!(queue.retries.is_empty());
+        assert_eq!(queue.queue.len(), 1);
+        assert_eq!(queue.queue[0].attempt, 2);
+    }
+
+    #[tokio::test]
+    async fn should_not_enqueue_job_waiting_for_retry() {
+        let mut events = events::Builder::new_with_capacity(10);
+        let mut queue = repo_queue(&mut events);
+        let job = forget_job(Some(retry::Policy::default()));
+        run(&mut queue, &job);
+
+        queue.job_failed(&job, Some(FailureKind::Other));
+        queue.push(forget_job(Some(retry::Policy::default())));
+
+        assert!(queue.queue.is_empty());
+    }
+}
@@ -1,10 +1,20 @@
-use crate::job;
+use crate::{
+    job::{self, hooks},
+    secrets_cache::SecretsCache,
+};
 use cirrus_core::{
     cache::Cache,
-    restic::{Options, Output, Restic, Verbosity},
-    secrets::Secrets,
+    config::repo,
+    restic::{
+        self, BackupMessage, BackupSummary, Capability, FailureKind, Options, Output, Restic,
+        ResticProcess, Verbosity, TERMINATE_GRACE_PERIOD,
+    },
+    secrets::{self, RepoWithSecrets},
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use std::{sync::Arc, time::Duration};
 use time::OffsetDateTime;
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
@@ -15,7 +25,7 @@ use tokio::{
 pub(super) struct Runner {
     sender: events::Sender,
     restic: Arc<Restic>,
-    secrets: Arc<Secrets>,
+    secrets: SecretsCache,
     cache: Cache,
 }
 
@@ -23,7 +33,7 @@ impl Runner {
     pub(super) fn new(
         sender: events::Sender,
         restic: Arc<Restic>,
-        secrets: Arc<Secrets>,
+        secrets: SecretsCache,
         cache: Cache,
     ) -> Self {
         Runner {
@@ -34,30 +44,57 @@ impl Runner {
         }
     }
 
-    #[tracing::instrument(name = "job", skip_all, fields(id = %job.id, label = job.spec.label()))]
+    #[tracing::instrument(name = "job", skip_all, fields(id = %job.id, label = job.spec.label(), attempt = job.attempt))]
     pub(super) async fn run(
         &mut self,
         job: job::Job,
         cancellation: oneshot::Receiver<job::CancellationReason>,
     ) {
+        // a job that can't get its secrets yet (e.g. a locked keyring) is deferred instead of
+        // failing so it can run once they become available
+        let repo_with_secrets = match self.secrets.get_secrets(job.spec.repo()).await {
+            Ok(repo_with_secrets) => repo_with_secrets,
+            Err(error) if secrets::is_unavailable(&error) => {
+                tracing::warn!(%error, "secrets unavailable, deferring job");
+                self.sender
+                    .send(job::StatusChange::new(job.clone(), job::Status::Deferred));
+                return;
+            }
+            Err(error) => {
+                tracing::error!(error = format!("{:#}", error), "failed to get secrets");
+                self.sender
+                    .send(job::StatusChange::new(job, job::Status::FinishedWithError));
+                return;
+            }
+        };
+
         self.sender
             .send(job::StatusChange::new(job.clone(), job::Status::Started));
+        let cancellation = match job.spec.timeout() {
+            Some(timeout) => cancel_after(timeout, cancellation),
+            None => cancellation,
+        };
         let run_result = run(
-            job.spec.clone(),
+            &job,
+            &repo_with_secrets,
             self.sender.clone(),
             self.restic.clone(),
-            self.secrets.clone(),
             self.cache.clone(),
             cancellation,
         )
         .await;
         match run_result {
-            Ok(_) => {
+            Ok(backup_summary) => {
                 tracing::info!("finished successfully");
-                self.sender.send(job::StatusChange::new(
-                    job,
-                    job::Status::FinishedSuccessfully,
-                ));
+                self.sender.send(
+                    job::StatusChange::new(job, job::Status::FinishedSuccessfully)
+                        .with_backup_summary(backup_summary),
+                );
+            }
+            Err(JobOutcome::Cancelled(job::CancellationReason::Timeout)) => {
+                tracing::error!("timed out");
+                self.sender
+                    .send(job::StatusChange::new(job, job::Status::TimedOut));
             }
             Err(JobOutcome::Cancelled(cancellation_reason)) => {
                 tracing::info!(reason = ?cancellation_reason, "cancelled");
@@ -67,16 +104,27 @@ impl Runner {
                 ));
             }
             Err(JobOutcome::Error(error)) => {
-                tracing::error!(%error, "failed");
-                self.sender
-                    .send(job::StatusChange::new(job, job::Status::FinishedWithError));
+                let failure_kind = restic_failure_kind(&error);
+                match failure_kind.and_then(|kind| kind.suggestion()) {
+                    Some(suggestion) => tracing::error!(%error, suggestion, "failed"),
+                    None => tracing::error!(%error, "failed"),
+                }
+                if failure_kind == Some(FailureKind::WrongPassword) {
+                    // the password may have been changed since it was cached, and running the job
+                    // again with the cached value would only fail the same way
+                    self.secrets.forget(&job.spec.repo().password);
+                }
+                self.sender.send(
+                    job::StatusChange::new(job, job::Status::FinishedWithError)
+                        .with_failure_kind(failure_kind),
+                );
             }
         }
     }
 }
 
 #[derive(Debug)]
-enum JobOutcome {
+pub(super) enum JobOutcome {
     Error(eyre::Report),
     Cancelled(job::CancellationReason),
 }
@@ -87,6 +135,118 @@ impl<E: Into<eyre::Report>> From<E> for JobOutcome {
     }
 }
 
+/// The cancellation of a job. Unlike the receiver, it can be waited on again after the job was
+/// cancelled.
+pub(super) struct Cancellation<'a> {
+    receiver: Option<&'a mut oneshot::Receiver<job::CancellationReason>>,
+    reason: Option<job::CancellationReason>,
+}
+
+impl<'a> Cancellation<'a> {
+    pub(super) fn new(receiver: &'a mut oneshot::Receiver<job::CancellationReason>) -> Self {
+        Cancellation {
+            receiver: Some(receiver),
+            reason: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub(super) fn none() -> Self {
+        Cancellation {
+            receiver: None,
+            reason: None,
+        }
+    }
+
+    /// Waits until the job is cancelled, which never happens if the sender is dropped.
+    pub(super) async fn recv(&mut self) -> job::CancellationReason {
+        if let Some(reason) = self.reason {
+            return reason;
+        }
+        let result = match &mut self.receiver {
+            Some(receiver) => (&mut **receiver).await,
+            None => std::future::pending().await,
+        };
+        // a completed receiver must not be polled again
+        self.receiver = None;
+        match result {
+            Ok(reason) => {
+                self.reason = Some(reason);
+                reason
+            }
+            Err(_) => std::future::pending().await,
+        }
+    }
+
+    /// Fails with the cancellation if the job has been cancelled by now, without waiting.
+    fn check(&mut self) -> Result<(), JobOutcome> {
+        if let Some(receiver) = &mut self.receiver {
+            match receiver.try_recv() {
+                Ok(reason) => self.reason = Some(reason),
+                Err(oneshot::error::TryRecvError::Closed) => self.receiver = None,
+                Err(oneshot::error::TryRecvError::Empty) => {}
+            }
+        }
+        match self.reason {
+            Some(reason) => Err(reason.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the job has been cancelled. Only known once [Cancellation::recv] completed.
+    pub(super) fn is_cancelled(&self) -> bool {
+        self.reason.is_some()
+    }
+
+    /// The outcome of a failed step of the job, which is the cancellation if restic was
+    /// terminated because the job was cancelled.
+    fn outcome(&self, error: impl Into<eyre::Report>) -> JobOutcome {
+        let error = error.into();
+        let restic_cancelled = error.chain().any(|cause| {
+            matches!(
+                cause.downcast_ref::<restic::Error>(),
+                Some(restic::Error::Cancelled)
+            )
+        });
+        match self.reason {
+            Some(reason) if restic_cancelled => JobOutcome::Cancelled(reason),
+            _ => JobOutcome::Error(error),
+        }
+    }
+}
+
+/// Passes on the cancellation of a job, and cancels it with [job::CancellationReason::Timeout]
+/// once the timeout expires. Running restic processes are then terminated like for any other
+/// cancellation.
+fn cancel_after(
+    timeout: Duration,
+    cancellation: oneshot::Receiver<job::CancellationReason>,
+) -> oneshot::Receiver<job::CancellationReason> {
+    let (mut send, recv) = oneshot::channel();
+    tokio::spawn(async move {
+        let reason = tokio::select! {
+            reason = cancellation => match reason {
+                Ok(reason) => reason,
+                // dropping the sender passes this on
+                Err(_) => return,
+            },
+            _ = tokio::time::sleep(timeout) => job::CancellationReason::Timeout,
+            // the job finished
+            _ = send.closed() => return,
+        };
+        send.send(reason).ok();
+    });
+    recv
+}
+
+/// Why restic failed if that's the cause of the error.
+fn restic_failure_kind(error: &eyre::Report) -> Option<FailureKind> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<restic::Error>())
+        .and_then(restic::Error::failure_kind)
+}
+
 impl From<job::CancellationReason> for JobOutcome {
     fn from(r: job::CancellationReason) -> Self {
         JobOutcome::Cancelled(r)
@@ -94,45 +254,361 @@ impl From<job::CancellationReason> for JobOutcome {
 }
 
 async fn run(
-    spec: job::Spec,
+    job: &job::Job,
+    repo_with_secrets: &RepoWithSecrets<'_>,
     mut sender: events::Sender,
     restic: Arc<Restic>,
-    secrets: Arc<Secrets>,
     cache: Cache,
     cancellation: oneshot::Receiver<job::CancellationReason>,
-) -> Result<(), JobOutcome> {
-    match spec {
+) -> Result<Option<BackupSummary>, JobOutcome> {
+    restic
+        .check_capabilities(required_capabilities(&job.spec))
+        .await?;
+    match &job.spec {
         job::Spec::Backup(spec) => {
-            run_backup(&spec, &mut sender, &restic, &secrets, cancellation).await
+            run_backup(
+                job,
+                spec,
+                repo_with_secrets,
+                &mut sender,
+                &restic,
+                cancellation,
+            )
+            .await
+        }
+        job::Spec::Forget(spec) => {
+            run_forget(spec, repo_with_secrets, &mut sender, &restic, cancellation)
+                .await
+                .map(|_| None)
         }
         job::Spec::FilesIndex(spec) => {
-            update_files_index(&spec, &restic, &secrets, &cache, cancellation).await
+            update_files_index(spec, repo_with_secrets, &restic, &cache, cancellation)
+                .await
+                .map(|_| None)
         }
     }
 }
 
-const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+fn required_capabilities(spec: &job::Spec) -> Vec<Capability> {
+    let mut capabilities = Capability::required_by_repo(spec.repo());
+    if let job::Spec::Backup(spec) = spec {
+        capabilities.extend(Capability::required_by_backup(&spec.backup));
+    }
+    capabilities
+}
 
 async fn run_backup(
+    job: &job::Job,
     spec: &job::BackupSpec,
+    repo_with_secrets: &RepoWithSecrets<'_>,
+    sender: &mut events::Sender,
+    restic: &Restic,
+    mut cancellation: oneshot::Receiver<job::CancellationReason>,
+) -> Result<Option<BackupSummary>, JobOutcome> {
+    let mut cancellation = Cancellation::new(&mut cancellation);
+    let mut result = hooks::run(spec, hooks::Stage::Before, None, &mut cancellation).await;
+    let should_run_backup = match &result {
+        Ok(()) => true,
+        Err(JobOutcome::Error(_)) => !spec.backup.hooks.abort_on_before_failure,
+        Err(JobOutcome::Cancelled(_)) => false,
+    };
+    let mut backup_summary = None;
+    if should_run_backup {
+        let backup_result = match run_restic_backup(
+            job,
+            spec,
+            repo_with_secrets,
+            sender,
+            restic,
+            &mut cancellation,
+        )
+        .await
+        {
+            Ok(summary) => {
+                backup_summary = summary;
+                request_follow_up_jobs(spec, sender);
+                Ok(())
+            }
+            Err(error) => Err(error),
+        };
+        result = backup_result.and(result);
+    }
+
+    // hooks that run after the backup still run when the job is cancelled so cleanup happens,
+    // but only get a short grace period then
+    let outcome = hooks::Outcome::of(&result);
+    let outcome_hooks_result = match outcome {
+        hooks::Outcome::Success => {
+            hooks::run(
+                spec,
+                hooks::Stage::OnSuccess,
+                Some(outcome),
+                &mut cancellation,
+            )
+            .await
+        }
+        hooks::Outcome::Failure => {
+            hooks::run(
+                spec,
+                hooks::Stage::OnFailure,
+                Some(outcome),
+                &mut cancellation,
+            )
+            .await
+        }
+        hooks::Outcome::Cancelled => Ok(()),
+    };
+    let after_hooks_result =
+        hooks::run(spec, hooks::Stage::After, Some(outcome), &mut cancellation).await;
+
+    result
+        .and(outcome_hooks_result)
+        .and(after_hooks_result)
+        .map(|_| backup_summary)
+}
+
+async fn run_restic_backup(
+    job: &job::Job,
+    spec: &job::BackupSpec,
+    repo_with_secrets: &RepoWithSecrets<'_>,
+    sender: &events::Sender,
+    restic: &Restic,
+    cancellation: &mut Cancellation<'_>,
+) -> Result<Option<BackupSummary>, JobOutcome> {
+    let mut lock_recovery = LockRecovery::default();
+    loop {
+        let mut process = restic.backup(
+            repo_with_secrets,
+            &spec.backup_name,
+            &spec.backup,
+            &Options {
+                stdout: Output::Capture,
+                stderr: Output::Capture,
+                json: true,
+                verbose: Verbosity::V,
+            },
+        )?;
+
+        let mut progress = BackupProgress::new(job.clone(), sender.clone());
+        let result = log_output(&mut process, cancellation, |line| {
+            progress.handle_line(line)
+        })
+        .await;
+        let result = match result {
+            Ok(()) => process.check_wait().await.map_err(JobOutcome::from),
+            Err(outcome) => Err(outcome),
+        };
+        if !lock_recovery
+            .should_retry(&result, restic, repo_with_secrets, cancellation)
+            .await?
+        {
+            return result.map(|_| progress.summary);
+        }
+    }
+}
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Turns the JSON output of `restic backup` into progress events. Status updates are throttled
+/// because restic prints them many times per second.
+#[derive(Debug)]
+struct BackupProgress {
+    job: job::Job,
+    sender: events::Sender,
+    last_status: Option<Instant>,
+    summary: Option<BackupSummary>,
+}
+
+impl BackupProgress {
+    fn new(job: job::Job, sender: events::Sender) -> Self {
+        BackupProgress {
+            job,
+            sender,
+            last_status: None,
+            summary: None,
+        }
+    }
+
+    fn handle_line(&mut self, line: String) {
+        let message = match BackupMessage::parse(&line) {
+            Ok(message) => message,
+            Err(_) => {
+                tracing::info!("{}", line);
+                return;
+            }
+        };
+        match &message {
+            BackupMessage::Status(_) => {
+                let now = Instant::now();
+                if matches!(self.last_status, Some(last) if now - last < STATUS_INTERVAL) {
+                    return;
+                }
+                self.last_status = Some(now);
+            }
+            BackupMessage::VerboseStatus(status) => {
+                tracing::info!("{} {}", status.action, status.item);
+            }
+            BackupMessage::Error(error) => {
+                tracing::warn!(during = %error.during, item = %error.item, "{}", error.error.message);
+            }
+            BackupMessage::Summary(summary) => {
+                tracing::info!(
+                    files_new = summary.files_new,
+                    files_changed = summary.files_changed,
+                    data_added = summary.data_added,
+                    duration = %humantime::format_duration(summary.duration),
+                    snapshot_id = summary.snapshot_id.as_deref().unwrap_or_default(),
+                    "backup summary"
+                );
+                self.summary = Some(summary.clone());
+            }
+        }
+        self.sender
+            .send(job::JobProgress::new(self.job.clone(), message));
+    }
+}
+
+fn request_follow_up_jobs(spec: &job::BackupSpec, sender: &mut events::Sender) {
+    match &spec.backup.retention {
+        Some(retention) => {
+            tracing::debug!("requesting forgetting old snapshots");
+            sender.send(job::Job::new(job::Spec::Forget(job::ForgetSpec {
+                repo_name: spec.repo_name.clone(),
+                backup_name: spec.backup_name.clone(),
+                repo: spec.repo.clone(),
+                retention: retention.clone(),
+            })));
+        }
+        None => request_files_index_update(sender, &spec.repo_name, &spec.repo),
+    }
+}
+
+async fn run_forget(
+    spec: &job::ForgetSpec,
+    repo_with_secrets: &RepoWithSecrets<'_>,
     sender: &mut events::Sender,
     restic: &Restic,
-    secrets: &Secrets,
     mut cancellation: oneshot::Receiver<job::CancellationReason>,
 ) -> Result<(), JobOutcome> {
-    let repo_with_secrets = secrets.get_secrets(&spec.repo)?;
-    let mut process = restic.backup(
-        &repo_with_secrets,
-        &spec.backup_name,
-        &spec.backup,
-        &Options {
-            stdout: Output::Capture,
-            stderr: Output::Capture,
-            verbose: Verbosity::V,
-            ..Default::default()
-        },
-    )?;
+    let mut cancellation = Cancellation::new(&mut cancellation);
+    let mut lock_recovery = LockRecovery::default();
+    let groups = loop {
+        let result = restic
+            .forget_snapshots(
+                repo_with_secrets,
+                &spec.backup_name,
+                &spec.retention,
+                cancellation.recv(),
+            )
+            .await
+            .map_err(|error| cancellation.outcome(error));
+        if !lock_recovery
+            .should_retry(&result, restic, repo_with_secrets, &mut cancellation)
+            .await?
+        {
+            break result?;
+        }
+    };
+    for group in groups {
+        tracing::info!(
+            kept = group.keep.len(),
+            removed = group.remove.len(),
+            host = group.host,
+            "forgot old snapshots"
+        );
+    }
+    request_files_index_update(sender, &spec.repo_name, &spec.repo);
+    Ok(())
+}
+
+/// Removes stale locks that this host left behind in a repository, e.g. because it suspended
+/// during a backup, so a job that failed because of them can run once more.
+#[derive(Debug, Default)]
+struct LockRecovery {
+    attempted: bool,
+}
+
+impl LockRecovery {
+    /// Whether the job failed because of a stale lock that has been removed now. Fails if the
+    /// job is cancelled while checking the locks.
+    async fn should_retry<T>(
+        &mut self,
+        result: &Result<T, JobOutcome>,
+        restic: &Restic,
+        repo_with_secrets: &RepoWithSecrets<'_>,
+        cancellation: &mut Cancellation<'_>,
+    ) -> Result<bool, JobOutcome> {
+        let is_locked = match result {
+            Err(JobOutcome::Error(error)) => {
+                restic_failure_kind(error) == Some(FailureKind::RepositoryLocked)
+            }
+            _ => false,
+        };
+        if !is_locked || self.attempted {
+            return Ok(false);
+        }
+        self.attempted = true;
+
+        match remove_stale_locks(restic, repo_with_secrets, cancellation).await {
+            Ok(true) => {
+                tracing::info!("removed stale locks, retrying");
+                Ok(true)
+            }
+            Ok(false) => Ok(false),
+            Err(error) => match cancellation.outcome(error) {
+                JobOutcome::Error(error) => {
+                    tracing::warn!(%error, "failed to remove stale locks");
+                    Ok(false)
+                }
+                cancelled => Err(cancelled),
+            },
+        }
+    }
+}
 
+async fn remove_stale_locks(
+    restic: &Restic,
+    repo_with_secrets: &RepoWithSecrets<'_>,
+    cancellation: &mut Cancellation<'_>,
+) -> eyre::Result<bool> {
+    let hostname = hostname::get()?.to_string_lossy().into_owned();
+    let locks = restic.locks(repo_with_secrets, cancellation.recv()).await?;
+    let now = OffsetDateTime::now_utc();
+    let (stale, active): (Vec<_>, Vec<_>) =
+        locks.iter().partition(|lock| lock.is_stale(&hostname, now));
+    for lock in &active {
+        tracing::info!(
+            id = lock.id,
+            hostname = lock.hostname,
+            pid = lock.pid,
+            "repository is locked by a running or remote process"
+        );
+    }
+    // removing the stale locks doesn't help while other locks remain
+    if stale.is_empty() || !active.is_empty() {
+        return Ok(false);
+    }
+    for lock in &stale {
+        tracing::warn!(
+            id = lock.id,
+            pid = lock.pid,
+            time = %lock.time,
+            exclusive = lock.exclusive,
+            "removing stale lock"
+        );
+    }
+    // restic only removes locks it considers stale itself
+    restic
+        .unlock(repo_with_secrets, false, cancellation.recv())
+        .await?;
+    Ok(true)
+}
+
+async fn log_output(
+    process: &mut ResticProcess,
+    cancellation: &mut Cancellation<'_>,
+    mut handle_stdout: impl FnMut(String),
+) -> Result<(), JobOutcome> {
     let mut stdout = BufReader::new(
         process
             .stdout()
@@ -149,60 +625,60 @@ async fn run_backup(
     .lines();
 
     loop {
-        // TODO: use JSON output, process into better updates
         tokio::select! {
             line = stdout.next_line() => match line? {
-                Some(line) => tracing::info!("{}", line),
+                Some(line) => handle_stdout(line),
                 None => break,
             },
             line = stderr.next_line() => match line? {
-                Some(line) => tracing::warn!("{}", line),
+                Some(line) => {
+                    tracing::warn!("{}", secrets::redact(&line));
+                    process.inspect_stderr(&line);
+                }
                 None => break,
             },
-            cancellation_reason = &mut cancellation => {
+            cancellation_reason = cancellation.recv() => {
                 process.terminate(TERMINATE_GRACE_PERIOD).await?;
-                return Err(cancellation_reason?.into());
+                return Err(cancellation_reason.into());
             }
         }
     }
+    Ok(())
+}
 
-    process.check_wait().await?;
+fn request_files_index_update(
+    sender: &mut events::Sender,
+    repo_name: &repo::Name,
+    repo: &repo::Definition,
+) {
     // TODO: this should probably happen somewhere else at some point; separate service keyed off some event?
     tracing::debug!("requesting files index update");
     sender.send(job::Job::new(job::Spec::FilesIndex(job::FilesIndexSpec {
-        repo_name: spec.repo_name.clone(),
-        repo: spec.repo.clone(),
+        repo_name: repo_name.clone(),
+        repo: repo.clone(),
         max_age: None,
     })));
-    Ok(())
-}
-
-fn check_cancellation(
-    cancellation: &mut oneshot::Receiver<job::CancellationReason>,
-) -> Result<(), JobOutcome> {
-    match cancellation.try_recv() {
-        Err(oneshot::error::TryRecvError::Empty) => Ok(()),
-        Ok(cancellation_reason) => Err(cancellation_reason.into()),
-        Err(err) => Err(err.into()),
-    }
 }
 
 async fn update_files_index(
     spec: &job::FilesIndexSpec,
+    repo_with_secrets: &RepoWithSecrets<'_>,
     restic: &Restic,
-    secrets: &Secrets,
     cache: &Cache,
     mut cancellation: oneshot::Receiver<job::CancellationReason>,
 ) -> Result<(), JobOutcome> {
+    let mut cancellation = Cancellation::new(&mut cancellation);
     tracing::info!(target: "cli", "Indexing snapshots...");
 
     // update snapshots
     let mut db = cirrus_index::Database::new(cache.get().await?, &spec.repo_name).await?;
-    let repo_with_secrets = secrets.get_secrets(&spec.repo)?;
-    let num_snapshots = cirrus_index::index_snapshots(restic, &mut db, &repo_with_secrets).await?;
+    let num_snapshots =
+        cirrus_index::index_snapshots(restic, &mut db, repo_with_secrets, cancellation.recv())
+            .await
+            .map_err(|error| cancellation.outcome(error))?;
     tracing::info!(target: "cli", "{num_snapshots} snapshots in repository.");
 
-    check_cancellation(&mut cancellation)?;
+    cancellation.check()?;
 
     if let Some(max_age) = spec.max_age.or(spec.repo.build_index) {
         let newer_than = OffsetDateTime::now_utc() - max_age;
@@ -213,11 +689,19 @@ async fn update_files_index(
             humantime::format_duration(max_age),
             unique_snapshots.len()
         );
-        check_cancellation(&mut cancellation)?;
+        cancellation.check()?;
         for snapshot in &unique_snapshots {
             tracing::info!(target: "cli", "Indexing {}...", snapshot.short_id());
-            cirrus_index::index_files(restic, &mut db, &repo_with_secrets, snapshot).await?;
-            check_cancellation(&mut cancellation)?;
+            cirrus_index::index_files(
+                restic,
+                &mut db,
+                repo_with_secrets,
+                snapshot,
+                cancellation.recv(),
+            )
+            .await
+            .map_err(|error| cancellation.outcome(error))?;
+            cancellation.check()?;
         }
         tracing::info!(target: "cli", "Finished indexing snapshot contents ({} unique snapshots).", unique_snapshots.len());
     } else {
@@ -226,3 +710,66 @@ async fn update_files_index(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_cancel_job_after_timeout() {
+        let (_send, recv) = oneshot::channel();
+
+        let cancellation = cancel_after(Duration::from_millis(1), recv);
+
+        assert_eq!(
+            cancellation.await.unwrap(),
+            job::CancellationReason::Timeout
+        );
+    }
+
+    #[tokio::test]
+    async fn should_pass_on_cancellation_before_timeout() {
+        let (send, recv) = oneshot::channel();
+
+        let cancellation = cancel_after(Duration::from_secs(60), recv);
+        send.send(job::CancellationReason::Suspend).unwrap();
+
+        assert_eq!(
+            cancellation.await.unwrap(),
+            job::CancellationReason::Suspend
+        );
+    }
+
+    #[test]
+    fn should_check_cancellation_without_waiting() {
+        let (send, mut recv) = oneshot::channel();
+        let mut cancellation = Cancellation::new(&mut recv);
+
+        assert!(cancellation.check().is_ok());
+        send.send(job::CancellationReason::Shutdown).unwrap();
+
+        assert!(matches!(
+            cancellation.check(),
+            Err(JobOutcome::Cancelled(job::CancellationReason::Shutdown))
+        ));
+        assert!(cancellation.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn should_report_cancellation_if_restic_was_terminated() {
+        let (send, mut recv) = oneshot::channel();
+        let mut cancellation = Cancellation::new(&mut recv);
+        send.send(job::CancellationReason::Suspend).unwrap();
+
+        cancellation.recv().await;
+
+        assert!(matches!(
+            cancellation.outcome(restic::Error::Cancelled),
+            JobOutcome::Cancelled(job::CancellationReason::Suspend)
+        ));
+        assert!(matches!(
+            cancellation.outcome(restic::Error::IncompleteOutput),
+            JobOutcome::Error(_)
+        ));
+    }
+}
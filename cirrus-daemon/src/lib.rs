@@ -1,6 +1,7 @@
 pub mod config_reload;
 pub mod job;
 pub mod scheduler;
+pub mod secrets_cache;
 pub mod shutdown;
 pub mod signal_handler;
 pub mod suspend;
@@ -0,0 +1,261 @@
+use crate::config_reload::ConfigReload;
+use cirrus_core::{
+    config::repo,
+    secrets::{RepoWithSecrets, SecretValue, Secrets},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Request to wipe all cached secrets from memory.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LockSecrets;
+
+#[derive(Debug)]
+struct CachedSecret {
+    value: SecretValue,
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    ttl: Duration,
+    entries: HashMap<repo::Secret, CachedSecret>,
+}
+
+/// Keeps secrets in memory for a while so jobs don't have to query the keyring, run commands or
+/// read files every time. Cached values are zeroized when they expire or the cache is locked.
+#[derive(Debug, Clone)]
+pub struct SecretsCache {
+    secrets: Arc<Secrets>,
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl SecretsCache {
+    pub fn new(secrets: Arc<Secrets>, ttl: Duration) -> Self {
+        SecretsCache {
+            secrets,
+            state: Arc::new(Mutex::new(CacheState {
+                ttl,
+                entries: HashMap::new(),
+            })),
+        }
+    }
+
+    pub async fn get_secrets<'a>(
+        &self,
+        repo: &'a repo::Definition,
+    ) -> eyre::Result<RepoWithSecrets<'a>> {
+        let repo_password = self.get_secret(&repo.password).await?;
+        let mut secrets = HashMap::with_capacity(repo.secrets.len());
+        for (name, secret) in &repo.secrets {
+            secrets.insert(name.clone(), self.get_secret(secret).await?);
+        }
+        Ok(RepoWithSecrets {
+            repo,
+            repo_password,
+            secrets,
+        })
+    }
+
+    async fn get_secret(&self, secret: &repo::Secret) -> eyre::Result<SecretValue> {
+        if let Some(value) = self.cached(secret) {
+            return Ok(value);
+        }
+
+        // resolving a secret can block for a while, e.g. when running a command
+        let secrets = self.secrets.clone();
+        let to_resolve = secret.clone();
+        let value = tokio::task::spawn_blocking(move || secrets.get_secret(&to_resolve)).await??;
+
+        let mut state = self.state.lock().unwrap();
+        if !state.ttl.is_zero() {
+            let expires_at = Instant::now() + state.ttl;
+            state.entries.insert(
+                secret.clone(),
+                CachedSecret {
+                    value: value.clone(),
+                    expires_at,
+                },
+            );
+        }
+        Ok(value)
+    }
+
+    fn cached(&self, secret: &repo::Secret) -> Option<SecretValue> {
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .get(secret)
+            .filter(|cached| cached.expires_at > Instant::now())
+            .map(|cached| cached.value.clone())
+    }
+
+    /// Drops a single cached value, e.g. because restic rejected it.
+    pub fn forget(&self, secret: &repo::Secret) {
+        let mut state = self.state.lock().unwrap();
+        if state.entries.remove(secret).is_some() {
+            tracing::debug!("forgot cached secret");
+        }
+    }
+
+    fn set_ttl(&self, ttl: Duration) {
+        let mut state = self.state.lock().unwrap();
+        if state.ttl != ttl {
+            tracing::debug!(ttl = %humantime::format_duration(ttl), "changed secrets cache TTL");
+            state.ttl = ttl;
+            // entries shouldn't outlive the new TTL
+            let latest_expiry = Instant::now() + ttl;
+            for cached in state.entries.values_mut() {
+                cached.expires_at = cached.expires_at.min(latest_expiry);
+            }
+        }
+    }
+
+    fn purge_expired(&self) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.entries.retain(|_, cached| cached.expires_at > now);
+    }
+
+    fn lock(&self) {
+        let mut state = self.state.lock().unwrap();
+        let count = state.entries.len();
+        // running jobs keep their values, which stay redacted until the jobs drop them
+        state.entries.clear();
+        tracing::info!(count, "locked secrets");
+    }
+}
+
+events::subscriptions! {
+    LockSecrets,
+    ConfigReload,
+}
+
+#[derive(Debug)]
+pub struct SecretsCacheService {
+    events: Subscriptions,
+    cache: SecretsCache,
+}
+
+impl SecretsCacheService {
+    pub fn new(cache: SecretsCache, events: &mut events::Builder) -> Self {
+        SecretsCacheService {
+            events: Subscriptions::subscribe(events),
+            cache,
+        }
+    }
+
+    #[tracing::instrument(name = "SecretsCacheService", skip_all)]
+    pub async fn run(&mut self) -> eyre::Result<()> {
+        let mut purge_interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            tokio::select! {
+                lock_secrets = self.events.LockSecrets.recv() => {
+                    lock_secrets?;
+                    self.cache.lock();
+                }
+                config_reload = self.events.ConfigReload.recv() => {
+                    let config_reload = config_reload?;
+                    self.cache.set_ttl(config_reload.new_config.daemon.secrets_cache_ttl);
+                    self.cache.purge_expired();
+                }
+                _ = purge_interval.tick() => self.cache.purge_expired(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env_secret(name: &str) -> repo::Secret {
+        repo::Secret::FromEnvVar {
+            env_var: name.to_owned(),
+        }
+    }
+
+    fn repo(password: repo::Secret) -> repo::Definition {
+        repo::Definition {
+            password,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn should_cache_secret() {
+        let cache = SecretsCache::new(Arc::new(Secrets), Duration::from_secs(60));
+        let repo = repo(env_secret("SECRETS_CACHE_TEST_CACHED"));
+        std::env::set_var("SECRETS_CACHE_TEST_CACHED", "value");
+
+        cache.get_secrets(&repo).await.unwrap();
+        std::env::remove_var("SECRETS_CACHE_TEST_CACHED");
+        let repo_with_secrets = cache.get_secrets(&repo).await.unwrap();
+
+        assert_eq!(&repo_with_secrets.repo_password.0, "value");
+    }
+
+    #[tokio::test]
+    async fn should_not_cache_secret_without_ttl() {
+        let cache = SecretsCache::new(Arc::new(Secrets), Duration::ZERO);
+        let repo = repo(env_secret("SECRETS_CACHE_TEST_NO_TTL"));
+        std::env::set_var("SECRETS_CACHE_TEST_NO_TTL", "value");
+
+        cache.get_secrets(&repo).await.unwrap();
+        std::env::remove_var("SECRETS_CACHE_TEST_NO_TTL");
+        let result = cache.get_secrets(&repo).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_forget_secrets_when_locked() {
+        let cache = SecretsCache::new(Arc::new(Secrets), Duration::from_secs(60));
+        let repo = repo(env_secret("SECRETS_CACHE_TEST_LOCKED"));
+        std::env::set_var("SECRETS_CACHE_TEST_LOCKED", "value");
+
+        cache.get_secrets(&repo).await.unwrap();
+        std::env::remove_var("SECRETS_CACHE_TEST_LOCKED");
+        cache.lock();
+        let result = cache.get_secrets(&repo).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_expire_secrets_when_ttl_is_lowered() {
+        let cache = SecretsCache::new(Arc::new(Secrets), Duration::from_secs(60));
+        let secret = env_secret("SECRETS_CACHE_TEST_EXPIRED");
+        std::env::set_var("SECRETS_CACHE_TEST_EXPIRED", "value");
+
+        cache.get_secrets(&repo(secret.clone())).await.unwrap();
+        cache.set_ttl(Duration::ZERO);
+        cache.purge_expired();
+
+        assert!(cache.cached(&secret).is_none());
+        std::env::remove_var("SECRETS_CACHE_TEST_EXPIRED");
+    }
+
+    #[tokio::test]
+    async fn should_forget_single_secret() {
+        let cache = SecretsCache::new(Arc::new(Secrets), Duration::from_secs(60));
+        let forgotten = env_secret("SECRETS_CACHE_TEST_FORGOTTEN");
+        let kept = env_secret("SECRETS_CACHE_TEST_KEPT");
+        std::env::set_var("SECRETS_CACHE_TEST_FORGOTTEN", "value");
+        std::env::set_var("SECRETS_CACHE_TEST_KEPT", "value");
+
+        cache.get_secrets(&repo(forgotten.clone())).await.unwrap();
+        cache.get_secrets(&repo(kept.clone())).await.unwrap();
+        cache.forget(&forgotten);
+
+        assert!(cache.cached(&forgotten).is_none());
+        assert!(cache.cached(&kept).is_some());
+        std::env::remove_var("SECRETS_CACHE_TEST_FORGOTTEN");
+        std::env::remove_var("SECRETS_CACHE_TEST_KEPT");
+    }
+}
@@ -1,6 +1,7 @@
 use cirrus_core::config;
 use cirrus_daemon::{
-    config_reload::ConfigReload, job, shutdown::RequestShutdown, suspend::Suspend,
+    config_reload::ConfigReload, job, secrets_cache::LockSecrets, shutdown::RequestShutdown,
+    suspend::Suspend,
 };
 use events::Sender;
 use eyre::WrapErr;
@@ -13,6 +14,7 @@ pub(crate) enum Event {
     ConfigReload(ConfigReload),
 
     ToggleSuspended,
+    LockSecrets,
     RunBackup(config::backup::Name),
     OpenConfigFile,
     Exit,
@@ -56,8 +58,11 @@ impl Model {
                     job::Status::Started => self
                         .running_jobs
                         .insert(status_change.job.id, status_change.job),
-                    job::Status::FinishedSuccessfully
+                    job::Status::Deferred
+                    | job::Status::FinishedSuccessfully
                     | job::Status::FinishedWithError
+                    | job::Status::RetryScheduled { .. }
+                    | job::Status::TimedOut
                     | job::Status::Cancelled(_) => self.running_jobs.remove(&status_change.job.id),
                 };
                 Ok(HandleEventOutcome::UpdateView)
@@ -75,6 +80,10 @@ impl Model {
                 self.sender.send(self.suspend.toggle());
                 Ok(HandleEventOutcome::Unchanged)
             }
+            Event::LockSecrets => {
+                self.sender.send(LockSecrets);
+                Ok(HandleEventOutcome::Unchanged)
+            }
             Event::RunBackup(name) => {
                 self.run_backup(name)?;
                 Ok(HandleEventOutcome::Unchanged)
@@ -151,6 +160,9 @@ impl Model {
             let job = self.running_jobs.values().next().unwrap();
             match &job.spec {
                 job::Spec::Backup(b) => format!("Backing up '{}'", b.backup_name.0).into(),
+                job::Spec::Forget(f) => {
+                    format!("Forgetting old snapshots of '{}'", f.backup_name.0).into()
+                }
                 _ => "Running a job".into(),
             }
         } else {
@@ -80,6 +80,7 @@ fn menu(model: &Model) -> trayicon::MenuBuilder<Event> {
     trayicon::MenuBuilder::new()
         .submenu("Run Backup", backups_menu)
         .checkable("Suspended", model.is_suspended(), Event::ToggleSuspended)
+        .item("Lock Secrets", Event::LockSecrets)
         .separator()
         .with(trayicon::MenuItem::Item {
             name: "Open Configuration".to_owned(),
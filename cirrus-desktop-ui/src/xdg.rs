@@ -59,6 +59,11 @@ fn menu(model: &super::Model) -> menu::Model<super::Event> {
             message: Some(super::Event::ToggleSuspended),
             ..Default::default()
         })
+        .item(menu::Item {
+            label: "Lock Secrets".to_string(),
+            message: Some(super::Event::LockSecrets),
+            ..Default::default()
+        })
         .separator()
         .item(menu::Item {
             label: "Open Configuration".to_string(),
@@ -3,48 +3,26 @@ use crate::{
     Version,
 };
 use cirrus_core::{
-    config::backup,
-    restic::{Options, Output, Restic},
+    restic::{self, Options, Output, Restic, TERMINATE_GRACE_PERIOD},
     secrets::RepoWithSecrets,
-    tag::Tag,
 };
 use futures::{StreamExt, TryStreamExt};
 use serde::Deserialize;
+use std::future::Future;
 use time::OffsetDateTime;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
-
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
-struct SnapshotJson {
-    #[serde(with = "time::serde::iso8601")]
-    time: OffsetDateTime,
-    parent: Option<SnapshotId>,
-    tree: TreeHash,
-    paths: Vec<backup::Path>,
-    hostname: String,
-    username: String,
-    uid: Option<Uid>,
-    gid: Option<Gid>,
-    #[serde(default)]
-    excludes: Vec<String>,
-    #[serde(default)]
-    tags: Vec<Tag>,
-    id: SnapshotId,
-    short_id: String,
-}
-
-impl SnapshotJson {
-    fn into_snapshot(self) -> Snapshot {
-        let backup = self.tags.iter().find_map(|tag| tag.backup_name());
-        Snapshot {
-            snapshot_id: self.id,
-            backup,
-            parent: self.parent,
-            tree_hash: self.tree,
-            hostname: self.hostname,
-            username: self.username,
-            time: self.time,
-            tags: self.tags,
-        }
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+fn into_snapshot(snapshot: restic::Snapshot) -> Snapshot {
+    let backup = snapshot.tags.iter().find_map(|tag| tag.backup_name());
+    Snapshot {
+        snapshot_id: SnapshotId(snapshot.id),
+        backup,
+        parent: snapshot.parent.map(SnapshotId),
+        tree_hash: TreeHash(snapshot.tree),
+        hostname: snapshot.hostname,
+        username: snapshot.username,
+        time: snapshot.time,
+        tags: snapshot.tags,
     }
 }
 
@@ -101,44 +79,31 @@ impl NodeJson {
 #[serde(rename_all = "lowercase")]
 #[serde(tag = "struct_type")]
 enum LsJson {
-    Snapshot(SnapshotJson),
+    Snapshot(restic::Snapshot),
     Node(NodeJson),
 }
 
+/// Imports the repository's snapshots. Restic is terminated once `cancellation` completes.
 pub async fn index_snapshots(
     restic: &Restic,
     db: &mut Database,
     repo: &RepoWithSecrets<'_>,
+    cancellation: impl Future,
 ) -> eyre::Result<u64> {
-    let mut process = restic.run(
-        Some(repo),
-        &["snapshots"],
-        &Options {
-            stdout: Output::Capture,
-            json: true,
-            ..Default::default()
-        },
-    )?;
-    let mut buf = Vec::new();
-    process
-        .stdout()
-        .as_mut()
-        .expect("should be present based on params")
-        .read_to_end(&mut buf)
-        .await?;
-    let snapshots: Vec<SnapshotJson> = serde_json::from_slice(&buf)?;
+    let snapshots = restic.snapshots(repo, None, cancellation).await?;
     let ret = db
-        .import_snapshots(snapshots.into_iter().map(|e| e.into_snapshot()))
+        .import_snapshots(snapshots.into_iter().map(into_snapshot))
         .await?;
-    process.check_wait().await?;
     Ok(ret)
 }
 
+/// Imports the files of the snapshot. Restic is terminated once `cancellation` completes.
 pub async fn index_files(
     restic: &Restic,
     db: &mut Database,
     repo: &RepoWithSecrets<'_>,
     snapshot: &Snapshot,
+    cancellation: impl Future,
 ) -> eyre::Result<u64> {
     let mut process = restic.run(
         Some(repo),
@@ -150,24 +115,36 @@ pub async fn index_files(
         },
     )?;
 
-    let files = tokio_stream::wrappers::LinesStream::new(
-        BufReader::new(
-            process
-                .stdout()
-                .as_mut()
-                .expect("should be present based on params"),
+    let imported = {
+        let files = tokio_stream::wrappers::LinesStream::new(
+            BufReader::new(
+                process
+                    .stdout()
+                    .as_mut()
+                    .expect("should be present based on params"),
+            )
+            .lines(),
         )
-        .lines(),
-    )
-    .map(|line| Ok::<_, eyre::Report>(serde_json::from_str::<LsJson>(&line?)?))
-    .try_filter_map(|json| async move {
-        match json {
-            LsJson::Snapshot(_) => Ok(None),
-            LsJson::Node(node) => Ok(Some(node.into_file_and_version())),
+        .map(|line| Ok::<_, eyre::Report>(serde_json::from_str::<LsJson>(&line?)?))
+        .try_filter_map(|json| async move {
+            match json {
+                LsJson::Snapshot(_) => Ok(None),
+                LsJson::Node(node) => Ok(Some(node.into_file_and_version())),
+            }
+        });
+
+        tokio::select! {
+            imported = db.import_files(snapshot, files) => Some(imported),
+            _ = cancellation => None,
         }
-    });
-
-    db.import_files(snapshot, files).await
+    };
+    match imported {
+        Some(imported) => imported,
+        None => {
+            process.terminate(TERMINATE_GRACE_PERIOD).await?;
+            Err(restic::Error::Cancelled.into())
+        }
+    }
 }
 
 #[cfg(test)]
@@ -176,6 +153,7 @@ mod tests {
 
     mod json {
         use super::*;
+        use cirrus_core::{config::backup, tag::Tag};
         use time::macros::datetime;
 
         #[test]
@@ -314,10 +292,12 @@ mod tests {
 
             assert_eq!(
                 result,
-                LsJson::Snapshot(SnapshotJson {
+                LsJson::Snapshot(restic::Snapshot {
+                    id: "id".to_string(),
+                    short_id: "short_id".to_string(),
                     time: datetime!(2022-10-28 18:30:26.123 +00:00),
-                    parent: Some(SnapshotId("par".to_string())),
-                    tree: TreeHash("tree".to_string()),
+                    parent: Some("par".to_string()),
+                    tree: "tree".to_string(),
                     paths: vec![backup::Path("C:\\".to_string())],
                     hostname: "test".to_string(),
                     username: "testuser".to_string(),
@@ -325,8 +305,6 @@ mod tests {
                     gid: None,
                     excludes: vec![],
                     tags: vec![Tag("testtag".to_string())],
-                    id: SnapshotId("id".to_string()),
-                    short_id: "short_id".to_string()
                 })
             );
         }
@@ -367,105 +345,6 @@ mod tests {
                 })
             );
         }
-
-        #[test]
-        fn should_parse_minimal_snapshot_json() {
-            // language=JSON
-            let json = r#"
-          {
-            "time": "2020-08-03T23:05:57.5629523+02:00",
-            "tree": "86fb8a32a6ac5c10fa2e21dbf140d8c40e5373dd891cc7926e067f125d6ad750",
-            "paths": [
-              "/root"
-            ],
-            "hostname": "testhost",
-            "username": "root",
-            "id": "3cc47d6ab8569b5bf8287d2b665b99f5279b2854a4c2a558676bae9e2741371d",
-            "short_id": "3cc47d6a"
-        }"#;
-
-            let result: SnapshotJson = serde_json::from_str(json).unwrap();
-
-            assert_eq!(
-                result,
-                SnapshotJson {
-                    time: datetime!(2020-08-03 23:05:57.5629523 +02:00),
-                    parent: None,
-                    tree: TreeHash(
-                        "86fb8a32a6ac5c10fa2e21dbf140d8c40e5373dd891cc7926e067f125d6ad750"
-                            .to_string()
-                    ),
-                    paths: vec![backup::Path("/root".to_string())],
-                    hostname: "testhost".to_string(),
-                    username: "root".to_string(),
-                    uid: None,
-                    gid: None,
-                    excludes: vec![],
-                    tags: vec![],
-                    id: SnapshotId(
-                        "3cc47d6ab8569b5bf8287d2b665b99f5279b2854a4c2a558676bae9e2741371d"
-                            .to_string()
-                    ),
-                    short_id: "3cc47d6a".to_string()
-                }
-            )
-        }
-
-        #[test]
-        fn should_parse_complete_snapshot_json() {
-            // language=JSON
-            let json = r#"
-          {
-            "time": "2020-08-03T23:05:57.5629523+02:00",
-            "tree": "86fb8a32a6ac5c10fa2e21dbf140d8c40e5373dd891cc7926e067f125d6ad750",
-            "parent": "2e8ad31a949d004194b97031427161b5b9c5a846359629b4c0671e2bbb26e6c4",
-            "paths": [
-              "/"
-            ],
-            "hostname": "host",
-            "username": "testuser",
-            "uid": 1001,
-            "gid": 1002,
-            "excludes": [
-              ".cache"
-            ],
-            "tags": [
-              "tag1",
-              "tag2.tag"
-            ],
-            "id": "3cc47d6ab8569b5bf8287d2b665b99f5279b2854a4c2a558676bae9e2741371d",
-            "short_id": "3cc47d6a"
-        }"#;
-
-            let result: SnapshotJson = serde_json::from_str(json).unwrap();
-
-            assert_eq!(
-                result,
-                SnapshotJson {
-                    time: datetime!(2020-08-03 23:05:57.5629523 +02:00),
-                    parent: Some(SnapshotId(
-                        "2e8ad31a949d004194b97031427161b5b9c5a846359629b4c0671e2bbb26e6c4"
-                            .to_string()
-                    )),
-                    tree: TreeHash(
-                        "86fb8a32a6ac5c10fa2e21dbf140d8c40e5373dd891cc7926e067f125d6ad750"
-                            .to_string()
-                    ),
-                    paths: vec![backup::Path("/".to_string())],
-                    hostname: "host".to_string(),
-                    username: "testuser".to_string(),
-                    uid: Some(Uid(1001)),
-                    gid: Some(Gid(1002)),
-                    excludes: vec![".cache".to_string()],
-                    tags: vec![Tag("tag1".to_string()), Tag("tag2.tag".to_string())],
-                    id: SnapshotId(
-                        "3cc47d6ab8569b5bf8287d2b665b99f5279b2854a4c2a558676bae9e2741371d"
-                            .to_string()
-                    ),
-                    short_id: "3cc47d6a".to_string()
-                }
-            )
-        }
     }
 
     mod get_parent {
@@ -85,6 +85,34 @@ impl Workdir {
         self
     }
 
+    /// Sets the exit status of the `invocation`th run of the binary, starting at 1, overriding
+    /// the one set with [Workdir::with_exit_status].
+    pub fn with_invocation_exit_status(self, invocation: u32, exit_status: i32) -> Self {
+        std::fs::write(
+            self.path().join(format!("exit-status.{}", invocation)),
+            exit_status.to_string(),
+        )
+        .unwrap();
+        self
+    }
+
+    /// Sets the standard output of the `invocation`th run of the binary, starting at 1,
+    /// overriding the one set with [Workdir::with_stdout].
+    pub fn with_invocation_stdout(self, invocation: u32, stdout: impl AsRef<[u8]>) -> Self {
+        std::fs::write(
+            self.path().join(format!("stdout.{}", invocation)),
+            stdout.as_ref(),
+        )
+        .unwrap();
+        self
+    }
+
+    /// Records the standard input even if the binary isn't called with `--stdin`.
+    pub fn with_stdin_capture(self) -> Self {
+        std::fs::write(self.path().join("capture-stdin"), "").unwrap();
+        self
+    }
+
     pub fn with_file(self, name: &str, contents: impl AsRef<[u8]>) -> Self {
         std::fs::write(self.path().join(name), contents.as_ref()).unwrap();
         self
@@ -107,6 +135,39 @@ impl Workdir {
         self
     }
 
+    pub fn assert_invocations(&self, invocations: u32) -> &Self {
+        let count = std::fs::read_to_string(self.path().join("invocations")).unwrap_or_default();
+        assert_eq!(count.trim().parse::<u32>().unwrap_or(0), invocations);
+        self
+    }
+
+    pub fn assert_invocation_args(&self, invocation: u32, args: &[impl AsRef<str>]) -> &Self {
+        Args::new(&self.path().join(format!("args.{}", invocation)))
+            .unwrap()
+            .assert_args(args);
+        self
+    }
+
+    pub fn assert_invocation_env_var(
+        &self,
+        invocation: u32,
+        key: impl AsRef<str>,
+        value: impl AsRef<str>,
+    ) -> &Self {
+        Env::new(&self.path().join(format!("env.{}", invocation)))
+            .unwrap()
+            .assert_var(key, value);
+        self
+    }
+
+    pub fn assert_stdin(&self, stdin: impl AsRef<[u8]>) -> &Self {
+        assert_eq!(
+            std::fs::read(self.path().join("stdin")).unwrap(),
+            stdin.as_ref()
+        );
+        self
+    }
+
     fn env(&self) -> Env {
         Env::new(&self.path().join("env")).unwrap()
     }
@@ -1,42 +1,90 @@
-use std::{error::Error, fs::File, io::Write, path::Path};
+use std::{
+    error::Error,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-fn write_args(workdir: &Path) -> std::io::Result<()> {
-    let mut file = File::create(workdir.join("args"))?;
+/// Counts the invocations of the binary in its directory and returns the number of this one,
+/// starting at 1.
+fn next_invocation(workdir: &Path) -> std::io::Result<u32> {
+    let path = workdir.join("invocations");
+    let invocation = match std::fs::read_to_string(&path) {
+        Ok(count) => count.trim().parse::<u32>().unwrap_or(0) + 1,
+        Err(_) => 1,
+    };
+    std::fs::write(&path, invocation.to_string())?;
+    Ok(invocation)
+}
+
+/// The file for this invocation if there is one, e.g. `stdout.2`, otherwise the common file.
+fn invocation_file(workdir: &Path, name: &str, invocation: u32) -> PathBuf {
+    let path = workdir.join(format!("{}.{}", name, invocation));
+    if path.exists() {
+        path
+    } else {
+        workdir.join(name)
+    }
+}
+
+fn write_args(workdir: &Path, invocation: u32) -> std::io::Result<()> {
+    let mut contents = Vec::new();
     for arg in std::env::args().skip(1) {
-        file.write_all(arg.as_bytes())?;
-        file.write_all(b"\n")?;
+        contents.extend_from_slice(arg.as_bytes());
+        contents.push(b'\n');
     }
+    std::fs::write(workdir.join("args"), &contents)?;
+    std::fs::write(workdir.join(format!("args.{}", invocation)), &contents)?;
     Ok(())
 }
 
-fn write_env(workdir: &Path) -> std::io::Result<()> {
-    let mut file = File::create(workdir.join("env"))?;
+fn write_env(workdir: &Path, invocation: u32) -> std::io::Result<()> {
+    let mut contents = Vec::new();
     for (key, value) in std::env::vars() {
-        file.write_all(key.as_bytes())?;
-        file.write_all(b"=")?;
-        file.write_all(value.as_bytes())?;
-        file.write_all(b"\n")?;
+        contents.extend_from_slice(key.as_bytes());
+        contents.push(b'=');
+        contents.extend_from_slice(value.as_bytes());
+        contents.push(b'\n');
+    }
+    std::fs::write(workdir.join("env"), &contents)?;
+    std::fs::write(workdir.join(format!("env.{}", invocation)), &contents)?;
+    Ok(())
+}
+
+fn write_stdin(workdir: &Path) -> std::io::Result<()> {
+    if std::env::args().any(|arg| arg == "--stdin") || workdir.join("capture-stdin").exists() {
+        let mut file = File::create(workdir.join("stdin"))?;
+        std::io::copy(&mut std::io::stdin(), &mut file)?;
     }
     Ok(())
 }
 
-fn get_exit_status(workdir: &Path) -> Result<i32, Box<dyn Error>> {
-    let exit_status = std::fs::read_to_string(workdir.join("exit-status"))?
+/// Keeps running for a while if there's a `sleep-ms` file, e.g. to test cancellation.
+fn sleep(workdir: &Path) -> Result<(), Box<dyn Error>> {
+    let millis = std::fs::read_to_string(workdir.join("sleep-ms"))?
+        .trim()
+        .parse()?;
+    std::thread::sleep(std::time::Duration::from_millis(millis));
+    Ok(())
+}
+
+fn get_exit_status(workdir: &Path, invocation: u32) -> Result<i32, Box<dyn Error>> {
+    let exit_status = std::fs::read_to_string(invocation_file(workdir, "exit-status", invocation))?
         .trim()
         .parse()?;
     Ok(exit_status)
 }
 
-fn copy_stdout(workdir: &Path) -> std::io::Result<()> {
-    let mut file = File::open(workdir.join("stdout"))?;
+fn copy_stdout(workdir: &Path, invocation: u32) -> std::io::Result<()> {
+    let mut file = File::open(invocation_file(workdir, "stdout", invocation))?;
     let mut stdout = std::io::stdout();
     std::io::copy(&mut file, &mut stdout)?;
     stdout.flush()?;
     Ok(())
 }
 
-fn copy_stderr(workdir: &Path) -> std::io::Result<()> {
-    let mut file = File::open(workdir.join("stderr"))?;
+fn copy_stderr(workdir: &Path, invocation: u32) -> std::io::Result<()> {
+    let mut file = File::open(invocation_file(workdir, "stderr", invocation))?;
     let mut stderr = std::io::stderr();
     std::io::copy(&mut file, &mut stderr)?;
     stderr.flush()?;
@@ -49,11 +97,14 @@ pub fn test_binary_main() {
         .parent()
         .unwrap()
         .to_owned();
-    let _ = write_args(&workdir);
-    let _ = write_env(&workdir);
-    let _ = copy_stdout(&workdir);
-    let _ = copy_stderr(&workdir);
+    let invocation = next_invocation(&workdir).unwrap_or(1);
+    let _ = write_args(&workdir, invocation);
+    let _ = write_env(&workdir, invocation);
+    let _ = write_stdin(&workdir);
+    let _ = copy_stdout(&workdir, invocation);
+    let _ = copy_stderr(&workdir, invocation);
+    let _ = sleep(&workdir);
 
-    let exit_status = get_exit_status(&workdir).unwrap_or(0);
+    let exit_status = get_exit_status(&workdir, invocation).unwrap_or(0);
     std::process::exit(exit_status);
 }
@@ -60,6 +60,61 @@ fn should_run_backup() {
         .assert_env_var("RESTIC_PASSWORD", "pwd");
 }
 
+#[test]
+fn should_run_forget() {
+    // language=JSON
+    let workdir = new_workdir()
+        .with_stdout(
+            r#"[{"tags":["cirrus.test"],"host":"host","paths":["/"],"keep":null,"remove":null}]"#,
+        )
+        .with_file(
+            "cirrus.toml",
+            toml::to_string(&toml::toml! {
+                [repositories.test]
+                url = "local:/srv/repo"
+
+                [repositories.test.password]
+                env_var = "TEST_PASSWORD"
+
+                [backups.test]
+                repository = "test"
+                path = "/"
+
+                [backups.test.retention]
+                keep-daily = 7
+                keep-monthly = 12
+            })
+            .unwrap(),
+        );
+    let assert = Command::cargo_bin("test-cirrus")
+        .unwrap()
+        .arg("--restic")
+        .arg(workdir.test_binary())
+        .arg("--config-file")
+        .arg(workdir.path().join("cirrus.toml"))
+        .args(["forget", "test", "--prune"])
+        .env("TEST_PASSWORD", "pwd")
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    assert!(stdout.contains("Kept 0 and removed 0 snapshots of backup 'test' on host 'host'"));
+    workdir
+        .assert_args(&[
+            "--repo",
+            "local:/srv/repo",
+            "forget",
+            "--tag",
+            "cirrus.test",
+            "--keep-daily",
+            "7",
+            "--keep-monthly",
+            "12",
+            "--prune",
+            "--json",
+        ])
+        .assert_env_var("RESTIC_PASSWORD", "pwd");
+}
+
 #[test]
 fn should_run_restic_subcommand_without_config_file_if_possible() {
     let workdir = new_workdir();
@@ -73,3 +128,26 @@ fn should_run_restic_subcommand_without_config_file_if_possible() {
         .assert()
         .success();
 }
+
+#[test]
+fn should_fail_config_check_for_unknown_repository() {
+    let workdir = new_workdir().with_file(
+        "cirrus.toml",
+        toml::to_string(&toml::toml! {
+            [backups.test]
+            repository = "missing"
+            path = "/"
+            disable-triggers = true
+        })
+        .unwrap(),
+    );
+    let assert = Command::cargo_bin("test-cirrus")
+        .unwrap()
+        .arg("--config-file")
+        .arg(workdir.path().join("cirrus.toml"))
+        .args(["config", "check"])
+        .assert()
+        .code(1);
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    assert!(stdout.contains("error: backup 'test' references unknown repository 'missing'"));
+}
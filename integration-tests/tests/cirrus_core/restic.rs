@@ -2,11 +2,15 @@ use crate::new_workdir;
 use cirrus_core::restic::Verbosity;
 use cirrus_core::{
     config::{backup, repo},
-    restic::{CommandConfig, Config, Options, Restic},
+    restic::{
+        keys, Capability, CommandConfig, Config, Error, Failure, FailureKind, Options, Restic,
+        ResticVersion, StatsMode,
+    },
     secrets,
 };
-use maplit::hashmap;
-use std::collections::HashMap;
+use integration_tests::EnvVarGuard;
+use maplit::{btreemap, hashmap};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
 
 #[tokio::test]
 async fn should_run_specified_restic_binary_with_explicit_arguments() {
@@ -60,10 +64,10 @@ async fn should_run_restic_with_repo_parameter_and_secrets() {
     };
     let repo_with_secrets = secrets::RepoWithSecrets {
         repo: &repo,
-        repo_password: secrets::SecretValue("repo-password".to_owned()),
+        repo_password: secrets::SecretValue::new("repo-password"),
         secrets: hashmap! {
-            repo::SecretName("SECRET1".to_owned()) => secrets::SecretValue("secret1".to_owned()),
-            repo::SecretName("SECRET2".to_owned()) => secrets::SecretValue("secret2".to_owned()),
+            repo::SecretName("SECRET1".to_owned()) => secrets::SecretValue::new("secret1"),
+            repo::SecretName("SECRET2".to_owned()) => secrets::SecretValue::new("secret2"),
         },
     };
 
@@ -89,6 +93,10 @@ async fn should_run_restic_with_repo_parameter_and_secrets() {
 const EXCLUDE_PARAM: &'static str = "--iexclude";
 #[cfg(not(windows))]
 const EXCLUDE_PARAM: &str = "--exclude";
+#[cfg(windows)]
+const EXCLUDE_FILE_PARAM: &'static str = "--iexclude-file";
+#[cfg(not(windows))]
+const EXCLUDE_FILE_PARAM: &str = "--exclude-file";
 
 #[tokio::test]
 async fn should_run_restic_backup() {
@@ -104,20 +112,24 @@ async fn should_run_restic_backup() {
     };
     let repo_with_secrets = secrets::RepoWithSecrets {
         repo: &repo,
-        repo_password: secrets::SecretValue("repo-password".to_owned()),
+        repo_password: secrets::SecretValue::new("repo-password"),
         secrets: HashMap::new(),
     };
     let backup_name = backup::Name("bkp".to_owned());
     let backup = backup::Definition {
         repository: repo::Name("repo".to_owned()),
-        path: backup::Path("/home/test".to_owned()),
+        paths: vec![backup::Path("/home/test".to_owned())],
         excludes: vec![backup::Exclude(".Trash".to_owned())],
         exclude_caches: true,
         exclude_larger_than: Some("1T".to_string()),
         ignore_unreadable_source_files: false,
+        read_concurrency: Some(4),
+        skip_if_unchanged: true,
         extra_args: vec!["--one-file-system".to_owned()],
         disable_triggers: false,
         triggers: vec![],
+        ignore_defaults: false,
+        ..Default::default()
     };
 
     restic
@@ -144,10 +156,368 @@ async fn should_run_restic_backup() {
         "--exclude-caches",
         "--exclude-larger-than",
         "1T",
+        "--read-concurrency",
+        "4",
+        "--skip-if-unchanged",
         "--one-file-system",
     ]);
 }
 
+#[tokio::test]
+async fn should_run_restic_backup_with_multiple_paths() {
+    let workdir = new_workdir();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+    let backup_name = backup::Name("bkp".to_owned());
+    let backup = backup::Definition {
+        repository: repo::Name("repo".to_owned()),
+        paths: vec![
+            backup::Path("/home/test/Documents".to_owned()),
+            backup::Path("/home/test/Projects".to_owned()),
+            backup::Path("/etc".to_owned()),
+        ],
+        ..Default::default()
+    };
+
+    restic
+        .backup(
+            &repo_with_secrets,
+            &backup_name,
+            &backup,
+            &Options::default(),
+        )
+        .unwrap()
+        .wait()
+        .await
+        .unwrap();
+
+    workdir.assert_args(&[
+        "--repo",
+        "local:/srv/repo",
+        "backup",
+        "/home/test/Documents",
+        "/home/test/Projects",
+        "/etc",
+        "--tag",
+        "cirrus.bkp",
+    ]);
+}
+
+#[tokio::test]
+async fn should_run_restic_backup_with_pattern_files() {
+    let workdir = new_workdir();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+    let backup_name = backup::Name("bkp".to_owned());
+    let backup = backup::Definition {
+        repository: repo::Name("repo".to_owned()),
+        iexcludes: vec![backup::Exclude("*.TMP".to_owned())],
+        exclude_files: vec![PathBuf::from("/etc/cirrus/excludes.txt")],
+        files_from: vec![PathBuf::from("/etc/cirrus/files.txt")],
+        files_from_raw: vec![PathBuf::from("/etc/cirrus/files.bin")],
+        ..Default::default()
+    };
+
+    restic
+        .backup(
+            &repo_with_secrets,
+            &backup_name,
+            &backup,
+            &Options::default(),
+        )
+        .unwrap()
+        .wait()
+        .await
+        .unwrap();
+
+    workdir.assert_args(&[
+        "--repo",
+        "local:/srv/repo",
+        "backup",
+        "--tag",
+        "cirrus.bkp",
+        "--iexclude",
+        "*.TMP",
+        EXCLUDE_FILE_PARAM,
+        "/etc/cirrus/excludes.txt",
+        "--files-from",
+        "/etc/cirrus/files.txt",
+        "--files-from-raw",
+        "/etc/cirrus/files.bin",
+    ]);
+}
+
+#[cfg(unix)]
+fn stdin_backup(stdin_command: &[&str]) -> backup::Definition {
+    backup::Definition {
+        repository: repo::Name("repo".to_owned()),
+        stdin_command: stdin_command.iter().map(|s| s.to_string()).collect(),
+        stdin_filename: Some("dump.sql".to_owned()),
+        ..Default::default()
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn should_pipe_stdin_command_into_restic_backup() {
+    let workdir = new_workdir();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    restic
+        .backup(
+            &repo_with_secrets,
+            &backup::Name("db".to_owned()),
+            &stdin_backup(&["echo", "database dump"]),
+            &Options::default(),
+        )
+        .unwrap()
+        .check_wait()
+        .await
+        .unwrap();
+
+    workdir
+        .assert_args(&[
+            "--repo",
+            "local:/srv/repo",
+            "backup",
+            "--stdin",
+            "--stdin-filename",
+            "dump.sql",
+            "--tag",
+            "cirrus.db",
+        ])
+        .assert_stdin("database dump\n");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn should_fail_stdin_backup_if_stdin_command_fails() {
+    let workdir = new_workdir();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition::default();
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    let result = restic
+        .backup(
+            &repo_with_secrets,
+            &backup::Name("db".to_owned()),
+            &stdin_backup(&["sh", "-c", "echo partial; exit 2"]),
+            &Options::default(),
+        )
+        .unwrap()
+        .check_wait()
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(cirrus_core::restic::Error::StdinCommandFailed(Some(2)))
+    ));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn should_fail_stdin_backup_if_restic_fails() {
+    let workdir = new_workdir().with_exit_status(1);
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition::default();
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    let result = restic
+        .backup(
+            &repo_with_secrets,
+            &backup::Name("db".to_owned()),
+            &stdin_backup(&["echo", "database dump"]),
+            &Options::default(),
+        )
+        .unwrap()
+        .check_wait()
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(cirrus_core::restic::Error::ResticError(_))
+    ));
+}
+
+#[tokio::test]
+async fn should_run_restic_forget() {
+    let workdir = new_workdir().with_stdout("[]\n");
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+    let retention = backup::Retention {
+        keep_last: Some(3),
+        keep_hourly: Some(24),
+        keep_daily: Some(7),
+        keep_weekly: Some(5),
+        keep_monthly: Some(12),
+        keep_yearly: Some(10),
+        keep_within: Some(Duration::from_secs(90 * 60)),
+        prune: true,
+    };
+
+    restic
+        .forget_snapshots(
+            &repo_with_secrets,
+            &backup::Name("bkp".to_owned()),
+            &retention,
+            std::future::pending::<()>(),
+        )
+        .await
+        .unwrap();
+
+    workdir.assert_args(&[
+        "--repo",
+        "local:/srv/repo",
+        "forget",
+        "--tag",
+        "cirrus.bkp",
+        "--keep-last",
+        "3",
+        "--keep-hourly",
+        "24",
+        "--keep-daily",
+        "7",
+        "--keep-weekly",
+        "5",
+        "--keep-monthly",
+        "12",
+        "--keep-yearly",
+        "10",
+        "--keep-within",
+        "2h",
+        "--prune",
+        "--json",
+    ]);
+}
+
+#[tokio::test]
+async fn should_forget_nothing_if_restic_prints_no_groups() {
+    let workdir = new_workdir();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+    let retention = backup::Retention {
+        keep_last: Some(3),
+        ..Default::default()
+    };
+
+    let groups = restic
+        .forget_snapshots(
+            &repo_with_secrets,
+            &backup::Name("bkp".to_owned()),
+            &retention,
+            std::future::pending::<()>(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(groups, vec![]);
+}
+
+#[tokio::test]
+async fn should_run_restic_with_repo_options() {
+    let workdir = new_workdir();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("sftp:host:/srv/repo".to_owned()),
+        options: repo::Options {
+            limit_upload: Some(1024),
+            limit_download: Some(2048),
+            compression: Some(repo::Compression::Max),
+            pack_size: Some(64),
+            cache_dir: Some(PathBuf::from("/var/cache/restic")),
+            insecure_tls: true,
+            extended: btreemap! {
+                "sftp.command".to_owned() => "ssh -p 2222 host -s sftp".to_owned(),
+            },
+        },
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    restic
+        .run(
+            Some(&repo_with_secrets),
+            &["snapshots"],
+            &Options::default(),
+        )
+        .unwrap()
+        .wait()
+        .await
+        .unwrap();
+
+    workdir.assert_args(&[
+        "--repo",
+        "sftp:host:/srv/repo",
+        "--limit-upload",
+        "1024",
+        "--limit-download",
+        "2048",
+        "--compression",
+        "max",
+        "--pack-size",
+        "64",
+        "--cache-dir",
+        "/var/cache/restic",
+        "--insecure-tls",
+        "--option",
+        "sftp.command=ssh -p 2222 host -s sftp",
+        "snapshots",
+    ]);
+}
+
 #[tokio::test]
 async fn should_run_restic_with_options() {
     let workdir = new_workdir();
@@ -181,3 +551,613 @@ async fn should_get_restic_version_string() {
     assert_eq!(&version_string, "restic version line");
     workdir.assert_args(&["version"]);
 }
+
+#[tokio::test]
+async fn should_get_restic_version() {
+    let workdir =
+        new_workdir().with_stdout(b"restic 0.16.4 compiled with go1.21.6 on linux/amd64\n");
+    let restic = Restic::new_with_path(workdir.test_binary());
+
+    let version = restic.version().await.unwrap();
+
+    assert_eq!(version, ResticVersion::new(0, 16, 4));
+}
+
+#[tokio::test]
+async fn should_reject_unsupported_restic_version() {
+    let workdir =
+        new_workdir().with_stdout(b"restic 0.9.6 compiled with go1.13.4 on linux/amd64\n");
+    let restic = Restic::new_with_path(workdir.test_binary());
+
+    let result = restic.check_version().await;
+
+    assert!(matches!(
+        result,
+        Err(Error::UnsupportedResticVersion(version)) if version == ResticVersion::new(0, 9, 6)
+    ));
+}
+
+#[tokio::test]
+async fn should_reject_capability_unsupported_by_restic_version() {
+    let workdir = new_workdir().with_stdout(b"restic 0.13.1 compiled with go1.18 on linux/amd64\n");
+    let restic = Restic::new_with_path(workdir.test_binary());
+
+    let result = restic.check_capabilities([Capability::Compression]).await;
+
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "the setting 'options.compression' requires restic 0.14.0 or newer, but restic 0.13.1 is installed"
+    );
+}
+
+#[tokio::test]
+async fn should_accept_capabilities_if_restic_version_is_unknown() {
+    let workdir = new_workdir().with_stdout(b"restic version line\n");
+    let restic = Restic::new_with_path(workdir.test_binary());
+
+    let result = restic
+        .check_capabilities([Capability::SkipIfUnchanged])
+        .await;
+
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn should_list_restic_keys() {
+    // language=JSON
+    let workdir = new_workdir().with_stdout(
+        r#"[{"current":true,"id":"3ac6c4d2","userName":"user","hostName":"host","created":"2023-01-14 12:00:00"}]"#,
+    );
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    let keys = restic.list_keys(&repo_with_secrets).await.unwrap();
+
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].id, "3ac6c4d2");
+    assert!(keys[0].current);
+    workdir.assert_args(&["--repo", "local:/srv/repo", "key", "list", "--json"]);
+}
+
+#[tokio::test]
+async fn should_remove_restic_key() {
+    let workdir = new_workdir();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    restic
+        .remove_key(&repo_with_secrets, "3ac6c4d2")
+        .await
+        .unwrap();
+
+    workdir.assert_args(&["--repo", "local:/srv/repo", "key", "remove", "3ac6c4d2"]);
+}
+
+#[tokio::test]
+async fn should_add_restic_key_with_password_on_stdin() {
+    let workdir = new_workdir().with_stdin_capture();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    restic
+        .add_key(
+            &repo_with_secrets,
+            &secrets::SecretValue::new("new-password"),
+        )
+        .await
+        .unwrap();
+
+    workdir
+        .assert_args(&["--repo", "local:/srv/repo", "key", "add"])
+        .assert_env_var("RESTIC_PASSWORD", "repo-password")
+        .assert_stdin("new-password\n");
+}
+
+fn key_list(keys: &[(&str, bool)]) -> String {
+    let keys = keys
+        .iter()
+        .map(|(id, current)| {
+            format!(
+                r#"{{"current":{},"id":"{}","userName":"user","hostName":"host","created":"2023-01-14 12:00:00"}}"#,
+                current, id
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", keys.join(","))
+}
+
+/// Sets up the fake restic for a rotation from key `old` to key `new`: `key add`, `key list` with
+/// the old password, `key list` with the new password, then `key remove`.
+fn rotation_workdir() -> integration_tests::Workdir {
+    new_workdir()
+        .with_invocation_stdout(2, key_list(&[("old", true), ("new", false)]))
+        .with_invocation_stdout(3, key_list(&[("old", false), ("new", true)]))
+}
+
+fn password_from_env_var(env_var: &str) -> repo::Definition {
+    repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        password: repo::Secret::FromEnvVar {
+            env_var: env_var.to_owned(),
+        },
+        ..Default::default()
+    }
+}
+
+fn old_keys() -> Vec<keys::Key> {
+    vec![keys::Key {
+        id: "old".to_owned(),
+        current: true,
+        user_name: "user".to_owned(),
+        host_name: "host".to_owned(),
+        created: "2023-01-14 12:00:00".to_owned(),
+    }]
+}
+
+#[tokio::test]
+async fn should_rotate_restic_key() {
+    let _env = EnvVarGuard::default().with_var("CIRRUS_TEST_ROTATE_SUCCESS", "old-password");
+    let workdir = rotation_workdir();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = password_from_env_var("CIRRUS_TEST_ROTATE_SUCCESS");
+    let old = secrets::Secrets.get_secrets(&repo).unwrap();
+
+    let new_key_id = restic
+        .rotate_key(
+            &secrets::Secrets,
+            &old,
+            &old_keys(),
+            secrets::SecretValue::new("new-password"),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(new_key_id, "new");
+    assert_eq!(
+        std::env::var("CIRRUS_TEST_ROTATE_SUCCESS").unwrap(),
+        "new-password"
+    );
+    workdir
+        .assert_invocations(4)
+        .assert_invocation_args(1, &["--repo", "local:/srv/repo", "key", "add"])
+        .assert_invocation_env_var(1, "RESTIC_PASSWORD", "old-password")
+        .assert_invocation_args(2, &["--repo", "local:/srv/repo", "key", "list", "--json"])
+        .assert_invocation_env_var(2, "RESTIC_PASSWORD", "old-password")
+        .assert_invocation_args(3, &["--repo", "local:/srv/repo", "key", "list", "--json"])
+        .assert_invocation_env_var(3, "RESTIC_PASSWORD", "new-password")
+        .assert_invocation_args(4, &["--repo", "local:/srv/repo", "key", "remove", "old"])
+        .assert_invocation_env_var(4, "RESTIC_PASSWORD", "new-password");
+}
+
+#[tokio::test]
+async fn should_not_rotate_restic_key_if_adding_key_fails() {
+    let _env = EnvVarGuard::default().with_var("CIRRUS_TEST_ROTATE_ADD_FAILS", "old-password");
+    let workdir = rotation_workdir().with_invocation_exit_status(1, 1);
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = password_from_env_var("CIRRUS_TEST_ROTATE_ADD_FAILS");
+    let old = secrets::Secrets.get_secrets(&repo).unwrap();
+
+    let result = restic
+        .rotate_key(
+            &secrets::Secrets,
+            &old,
+            &old_keys(),
+            secrets::SecretValue::new("new-password"),
+        )
+        .await;
+
+    assert!(result.is_err());
+    assert_eq!(
+        std::env::var("CIRRUS_TEST_ROTATE_ADD_FAILS").unwrap(),
+        "old-password"
+    );
+    workdir.assert_invocations(1);
+}
+
+#[tokio::test]
+async fn should_remove_new_restic_key_if_new_password_doesnt_open_repository() {
+    let _env = EnvVarGuard::default().with_var("CIRRUS_TEST_ROTATE_VERIFY_FAILS", "old-password");
+    let workdir = rotation_workdir().with_invocation_exit_status(3, 1);
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = password_from_env_var("CIRRUS_TEST_ROTATE_VERIFY_FAILS");
+    let old = secrets::Secrets.get_secrets(&repo).unwrap();
+
+    let error = restic
+        .rotate_key(
+            &secrets::Secrets,
+            &old,
+            &old_keys(),
+            secrets::SecretValue::new("new-password"),
+        )
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "failed to rotate password, kept the old password"
+    );
+    assert_eq!(
+        std::env::var("CIRRUS_TEST_ROTATE_VERIFY_FAILS").unwrap(),
+        "old-password"
+    );
+    workdir
+        .assert_invocations(4)
+        .assert_invocation_args(4, &["--repo", "local:/srv/repo", "key", "remove", "new"])
+        .assert_invocation_env_var(4, "RESTIC_PASSWORD", "old-password");
+}
+
+#[tokio::test]
+async fn should_restore_old_password_if_old_restic_key_cant_be_removed() {
+    let _env = EnvVarGuard::default().with_var("CIRRUS_TEST_ROTATE_REMOVE_FAILS", "old-password");
+    let workdir = rotation_workdir().with_invocation_exit_status(4, 1);
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = password_from_env_var("CIRRUS_TEST_ROTATE_REMOVE_FAILS");
+    let old = secrets::Secrets.get_secrets(&repo).unwrap();
+
+    let error = restic
+        .rotate_key(
+            &secrets::Secrets,
+            &old,
+            &old_keys(),
+            secrets::SecretValue::new("new-password"),
+        )
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "failed to rotate password, kept the old password"
+    );
+    assert_eq!(
+        std::env::var("CIRRUS_TEST_ROTATE_REMOVE_FAILS").unwrap(),
+        "old-password"
+    );
+    workdir
+        .assert_invocations(5)
+        .assert_invocation_args(4, &["--repo", "local:/srv/repo", "key", "remove", "old"])
+        .assert_invocation_env_var(4, "RESTIC_PASSWORD", "new-password")
+        .assert_invocation_args(5, &["--repo", "local:/srv/repo", "key", "remove", "new"])
+        .assert_invocation_env_var(5, "RESTIC_PASSWORD", "old-password");
+}
+
+#[tokio::test]
+async fn should_report_new_restic_key_if_it_cant_be_removed_on_rollback() {
+    let _env = EnvVarGuard::default().with_var("CIRRUS_TEST_ROTATE_ROLLBACK_FAILS", "old-password");
+    let workdir = rotation_workdir()
+        .with_invocation_exit_status(4, 1)
+        .with_invocation_exit_status(5, 1);
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = password_from_env_var("CIRRUS_TEST_ROTATE_ROLLBACK_FAILS");
+    let old = secrets::Secrets.get_secrets(&repo).unwrap();
+
+    let error = restic
+        .rotate_key(
+            &secrets::Secrets,
+            &old,
+            &old_keys(),
+            secrets::SecretValue::new("new-password"),
+        )
+        .await
+        .unwrap_err();
+
+    assert!(error
+        .to_string()
+        .contains("couldn't remove the new restic key new"));
+    assert_eq!(
+        std::env::var("CIRRUS_TEST_ROTATE_ROLLBACK_FAILS").unwrap(),
+        "old-password"
+    );
+    workdir.assert_invocations(5);
+}
+
+#[tokio::test]
+async fn should_get_snapshots_of_backup() {
+    // language=JSON
+    let workdir = new_workdir().with_stdout(
+        r#"[{"time":"2023-01-14T12:00:00+01:00","tree":"tree","paths":["/"],"hostname":"host","username":"user","tags":["cirrus.bkp"],"id":"3cc47d6a","short_id":"3cc47d6a"}]"#,
+    );
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    let snapshots = restic
+        .snapshots(
+            &repo_with_secrets,
+            Some(&backup::Name("bkp".to_owned())),
+            std::future::pending::<()>(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].id, "3cc47d6a");
+    workdir.assert_args(&[
+        "--repo",
+        "local:/srv/repo",
+        "snapshots",
+        "--tag",
+        "cirrus.bkp",
+        "--json",
+    ]);
+}
+
+#[tokio::test]
+async fn should_terminate_restic_when_cancelled() {
+    let workdir = new_workdir()
+        .with_stdout("[]")
+        .with_file("sleep-ms", "10000");
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition::default();
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        restic.snapshots(
+            &repo_with_secrets,
+            None,
+            tokio::time::sleep(Duration::from_millis(100)),
+        ),
+    )
+    .await
+    .expect("restic should have been terminated");
+
+    assert!(matches!(result, Err(Error::Cancelled)));
+}
+
+#[tokio::test]
+async fn should_get_restic_stats() {
+    // language=JSON
+    let workdir = new_workdir().with_stdout(r#"{"total_size":4096,"total_file_count":12}"#);
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    let stats = restic
+        .stats(
+            &repo_with_secrets,
+            StatsMode::RawData,
+            std::future::pending::<()>(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(stats.total_size, 4096);
+    assert_eq!(stats.total_file_count, 12);
+    workdir.assert_args(&[
+        "--repo",
+        "local:/srv/repo",
+        "stats",
+        "--mode",
+        "raw-data",
+        "--json",
+    ]);
+}
+
+#[tokio::test]
+async fn should_restore_snapshot_paths() {
+    let workdir = new_workdir();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    restic
+        .restore(
+            &repo_with_secrets,
+            "3cc47d6a",
+            std::path::Path::new("/tmp/restore"),
+            &["/etc".to_string()],
+            std::future::pending::<()>(),
+        )
+        .await
+        .unwrap();
+
+    workdir.assert_args(&[
+        "--repo",
+        "local:/srv/repo",
+        "restore",
+        "3cc47d6a",
+        "--target",
+        "/tmp/restore",
+        "--include",
+        "/etc",
+    ]);
+}
+
+#[tokio::test]
+async fn should_fail_on_invalid_restic_output() {
+    let workdir = new_workdir().with_stdout("not JSON");
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition::default();
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    let result = restic
+        .snapshots(&repo_with_secrets, None, std::future::pending::<()>())
+        .await;
+
+    assert!(matches!(result, Err(Error::InvalidOutput(_))));
+}
+
+#[tokio::test]
+async fn should_list_locks_without_locking_repository() {
+    // language=JSON
+    let workdir = new_workdir()
+        .with_invocation_stdout(1, "3ac6c4d2\n")
+        .with_invocation_stdout(
+            2,
+            r#"{"time":"2023-01-14T12:00:00+01:00","exclusive":true,"hostname":"laptop","username":"user","pid":1234}"#,
+        );
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    let locks = restic
+        .locks(&repo_with_secrets, std::future::pending::<()>())
+        .await
+        .unwrap();
+
+    assert_eq!(locks.len(), 1);
+    assert_eq!(locks[0].id, "3ac6c4d2");
+    assert_eq!(locks[0].hostname, "laptop");
+    assert!(locks[0].exclusive);
+    workdir
+        .assert_invocations(2)
+        .assert_invocation_args(
+            1,
+            &["--repo", "local:/srv/repo", "list", "locks", "--no-lock"],
+        )
+        .assert_invocation_args(
+            2,
+            &[
+                "--repo",
+                "local:/srv/repo",
+                "cat",
+                "lock",
+                "3ac6c4d2",
+                "--no-lock",
+                "--json",
+            ],
+        );
+}
+
+#[tokio::test]
+async fn should_unlock_repository() {
+    let workdir = new_workdir();
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition {
+        url: repo::Url("local:/srv/repo".to_owned()),
+        ..Default::default()
+    };
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    restic
+        .unlock(&repo_with_secrets, true, std::future::pending::<()>())
+        .await
+        .unwrap();
+
+    workdir.assert_args(&["--repo", "local:/srv/repo", "unlock", "--remove-all"]);
+}
+
+#[tokio::test]
+async fn should_fail_typed_command_if_restic_fails() {
+    let workdir = new_workdir().with_exit_status(1);
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition::default();
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    let result = restic
+        .check(&repo_with_secrets, None, std::future::pending::<()>())
+        .await;
+
+    assert!(matches!(result, Err(Error::ResticError(_))));
+}
+
+#[tokio::test]
+async fn should_classify_restic_failure_by_exit_status() {
+    let workdir = new_workdir().with_exit_status(12);
+    let restic = Restic::new_with_path(workdir.test_binary());
+
+    let result = restic
+        .run(None, &["snapshots"], &Options::default())
+        .unwrap()
+        .check_wait()
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(Error::ResticError(Failure {
+            code: Some(12),
+            kind: FailureKind::WrongPassword
+        }))
+    ));
+}
+
+#[tokio::test]
+async fn should_classify_restic_failure_by_error_output() {
+    let workdir = new_workdir()
+        .with_exit_status(1)
+        .with_stderr(b"unable to create lock in backend: repository is already locked by PID 1234 on laptop by user (UID 1000, GID 1000)\n");
+    let restic = Restic::new_with_path(workdir.test_binary());
+    let repo = repo::Definition::default();
+    let repo_with_secrets = secrets::RepoWithSecrets {
+        repo: &repo,
+        repo_password: secrets::SecretValue::new("repo-password"),
+        secrets: HashMap::new(),
+    };
+
+    let result = restic
+        .check(&repo_with_secrets, None, std::future::pending::<()>())
+        .await;
+
+    assert_eq!(
+        result.unwrap_err().failure_kind(),
+        Some(FailureKind::RepositoryLocked)
+    );
+}
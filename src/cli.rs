@@ -180,8 +180,11 @@ pub enum Cmd {
     /// Runs a configured backup
     Backup(backup::Cli),
 
-    /// Prints the active configuration
-    Config,
+    /// Forgets old snapshots of a configured backup according to its retention policy
+    Forget(forget::Cli),
+
+    /// Prints or checks the active configuration
+    Config(config::Cli),
 
     /// Gets and sets secrets
     #[command(alias = "secrets")]
@@ -229,6 +232,36 @@ pub mod backup {
     }
 }
 
+pub mod forget {
+    #[derive(clap::Parser)]
+    pub struct Cli {
+        /// The backup to forget old snapshots of
+        #[arg(value_name = "BACKUP")]
+        pub backup: String,
+
+        /// Prunes the repository afterwards, even if the retention policy doesn't say so
+        #[arg(long)]
+        pub prune: bool,
+    }
+}
+
+pub mod config {
+    #[derive(clap::Parser)]
+    pub struct Cli {
+        #[command(subcommand)]
+        pub subcommand: Option<Cmd>,
+    }
+
+    #[derive(clap::Parser)]
+    pub enum Cmd {
+        /// Checks the configuration for problems.
+        /// Exits with status 1 if there are errors and with status 2 if there are only warnings
+        Check,
+        /// Prints a JSON Schema of the configuration file format
+        Schema,
+    }
+}
+
 pub mod secret {
     #[derive(clap::Parser)]
     pub struct Cli {
@@ -243,6 +276,9 @@ pub mod secret {
 
         /// Lists all configured secrets and whether they are currently set
         List(List),
+
+        /// Changes a repository password by replacing its restic key
+        Rotate(Rotate),
     }
 
     #[derive(clap::Parser)]
@@ -261,6 +297,13 @@ pub mod secret {
         #[arg(long)]
         pub show_passwords: bool,
     }
+
+    #[derive(clap::Parser)]
+    pub struct Rotate {
+        /// Repository whose password to change
+        #[arg(value_name = "REPOSITORY")]
+        pub repository: String,
+    }
 }
 
 pub mod restic {
@@ -0,0 +1,53 @@
+use cirrus_core::{
+    config::{check::Severity, schema::config_schema_json, Config},
+    restic::Restic,
+    secrets::Secrets,
+};
+
+pub fn print(config: &Config) -> eyre::Result<()> {
+    print!("{}", toml::to_string_pretty(config)?);
+    if !config.filtered.is_empty() {
+        println!();
+        println!("# not applicable to this host:");
+        for filtered in &config.filtered {
+            println!("# {}", filtered);
+        }
+    }
+    Ok(())
+}
+
+pub fn schema() -> eyre::Result<()> {
+    print!("{}", config_schema_json());
+    Ok(())
+}
+
+pub async fn check(restic: &Restic, secrets: &Secrets, config: &Config) -> eyre::Result<()> {
+    let mut issues = config.check(Some(secrets));
+    match restic.version().await {
+        Ok(version) => issues.extend(config.check_restic_version(version)),
+        Err(error) => {
+            tracing::warn!(%error, "can't check if restic supports the configured settings")
+        }
+    }
+    for issue in &issues {
+        println!("{}: {}", issue.severity(), issue);
+    }
+
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity() == Severity::Error)
+        .count();
+    let warnings = issues.len() - errors;
+    if issues.is_empty() {
+        println!("No problems found.");
+    } else {
+        println!("Found {} error(s) and {} warning(s).", errors, warnings);
+    }
+
+    if errors > 0 {
+        std::process::exit(1);
+    } else if warnings > 0 {
+        std::process::exit(2);
+    }
+    Ok(())
+}
@@ -1,5 +1,10 @@
 use crate::cli;
-use cirrus_core::{cache::Cache, config::Config, restic::Restic, secrets::Secrets};
+use cirrus_core::{
+    cache::Cache,
+    config::Config,
+    restic::{self, Restic},
+    secrets::Secrets,
+};
 use cirrus_daemon::*;
 use std::{path::PathBuf, sync::Arc};
 use tokio::process::Command;
@@ -15,17 +20,21 @@ async fn run_daemon(
     let config = Arc::new(config);
     let mut events = events::Builder::new_with_capacity(128);
 
+    let secrets_cache =
+        secrets_cache::SecretsCache::new(secrets.clone(), config.daemon.secrets_cache_ttl);
+    let mut secrets_cache_service =
+        secrets_cache::SecretsCacheService::new(secrets_cache.clone(), &mut events);
     let mut suspend_service = suspend::SuspendService::new(&mut events);
     let mut job_queues = job::queues::JobQueues::new(
         &mut events,
         restic.clone(),
-        secrets.clone(),
+        secrets_cache,
         cache.clone(),
         *suspend_service.get_suspend(),
     );
     let mut scheduler = scheduler::Scheduler::new(config.clone(), &mut events);
     let mut config_reload_service =
-        config_reload::ConfigReloadService::new(config.clone(), &mut events)?;
+        config_reload::ConfigReloadService::new(config.clone(), restic.clone(), &mut events)?;
     let mut shutdown_service = shutdown::ShutdownService::new(&mut events);
     let mut signal_handler = signal_handler::SignalHandler::new(&mut events);
     let status_icon = cirrus_desktop_ui::StatusIcon::new(
@@ -40,16 +49,15 @@ async fn run_daemon(
     if let Some(version) = cirrus_core::VERSION {
         tracing::info!(cirrus_version = %version);
     }
-    match restic.version_string().await {
-        Ok(restic_version) => tracing::info!(%restic_version),
-        Err(error) => tracing::warn!(%error, "failed to query restic version"),
-    }
+    check_restic_version(&restic).await?;
+    config_reload::log_config_issues(&config, &restic).await;
 
     tokio::spawn(async move { job_queues.run().await.unwrap() });
     tokio::spawn(async move { scheduler.run().await.unwrap() });
     tokio::spawn(async move { config_reload_service.run().await.unwrap() });
     tokio::spawn(async move { shutdown_service.run().await.unwrap() });
     tokio::spawn(async move { suspend_service.run().await.unwrap() });
+    tokio::spawn(async move { secrets_cache_service.run().await.unwrap() });
     tokio::spawn(async move { signal_handler.run().await.unwrap() });
     tokio::spawn(async move {
         if let Err(error) = status_icon.run().await {
@@ -61,6 +69,17 @@ async fn run_daemon(
     futures::future::pending::<eyre::Result<()>>().await
 }
 
+/// Refuses to start with a restic that's known to be too old, but carries on if the version
+/// can't be determined.
+async fn check_restic_version(restic: &Restic) -> eyre::Result<()> {
+    match restic.check_version().await {
+        Ok(restic_version) => tracing::info!(%restic_version),
+        Err(error @ restic::Error::UnsupportedResticVersion(_)) => return Err(error.into()),
+        Err(error) => tracing::warn!(%error, "failed to query restic version"),
+    }
+    Ok(())
+}
+
 async fn log_file_dir() -> eyre::Result<PathBuf> {
     use dirs_next as dirs;
 
@@ -99,6 +118,8 @@ pub async fn main(
     cache: Cache,
 ) -> eyre::Result<()> {
     if args.supervisor {
+        // the daemon would fail the same check, don't restart it over and over
+        check_restic_version(&restic).await?;
         run_supervisor().await
     } else {
         run_daemon(restic, secrets, cache, config).await
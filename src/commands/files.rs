@@ -5,8 +5,10 @@ use cirrus_core::{
     restic::Restic,
     secrets::Secrets,
 };
-use cirrus_daemon::{job::Status, shutdown::RequestShutdown, suspend::Suspend};
-use std::sync::Arc;
+use cirrus_daemon::{
+    job::Status, secrets_cache::SecretsCache, shutdown::RequestShutdown, suspend::Suspend,
+};
+use std::{sync::Arc, time::Duration};
 
 pub async fn main(
     restic: Restic,
@@ -34,13 +36,14 @@ async fn update(
     args: Index,
 ) -> eyre::Result<()> {
     let restic = Arc::new(restic);
-    let secrets = Arc::new(secrets);
+    // a one-off job has no use for caching secrets
+    let secrets = SecretsCache::new(Arc::new(secrets), Duration::ZERO);
     let mut events = events::Builder::new_with_capacity(128);
 
     let mut job_queues = cirrus_daemon::job::queues::JobQueues::new(
         &mut events,
         restic.clone(),
-        secrets.clone(),
+        secrets,
         cache.clone(),
         Suspend::NotSuspended,
     );
@@ -65,12 +68,20 @@ async fn update(
         let status_change = status_changes.recv().await?;
         if status_change.job.id == id {
             match status_change.new_status {
-                Status::Cancelled(_) | Status::FinishedSuccessfully | Status::FinishedWithError => {
+                Status::Deferred => {
+                    // the job would only run once the secrets become available, which a one-off
+                    // command can't wait for
+                    eyre::bail!("the repository's secrets are unavailable, e.g. because the keyring is locked");
+                }
+                Status::Cancelled(_)
+                | Status::FinishedSuccessfully
+                | Status::FinishedWithError
+                | Status::TimedOut => {
                     events
                         .typed_sender::<RequestShutdown>()
                         .send(RequestShutdown);
                 }
-                Status::Started => (),
+                Status::Started | Status::RetryScheduled { .. } => (),
             }
         }
     }
@@ -1,10 +1,11 @@
 use crate::cli;
 use cirrus_core::{
     config::{backup, repo, Config},
-    restic::{Options, Restic},
+    restic::{self, Capability, Options, Restic, ResticVersion},
     secrets::Secrets,
 };
 
+pub mod config;
 pub mod daemon;
 pub mod files;
 pub mod repo_contents;
@@ -22,6 +23,9 @@ pub async fn restic(
             let config = maybe_config?;
             let repo = config.repository(&repo_name)?;
             let repo_with_secrets = secrets.get_secrets(repo)?;
+            restic
+                .check_capabilities(Capability::required_by_repo(repo))
+                .await?;
             restic
                 .run(
                     Some(&repo_with_secrets),
@@ -51,6 +55,13 @@ pub async fn backup(
     let backup = config.backup(&backup_name)?;
     let repo = config.repository_for_backup(backup)?;
     let repo_with_secrets = secrets.get_secrets(repo)?;
+    restic
+        .check_capabilities(
+            Capability::required_by_repo(repo)
+                .into_iter()
+                .chain(Capability::required_by_backup(backup)),
+        )
+        .await?;
     restic
         .backup(
             &repo_with_secrets,
@@ -63,8 +74,41 @@ pub async fn backup(
     Ok(())
 }
 
-pub fn config(config: &Config) -> eyre::Result<()> {
-    print!("{}", toml::to_string_pretty(config)?);
+pub async fn forget(
+    restic: &Restic,
+    secrets: &Secrets,
+    config: &Config,
+    args: cli::forget::Cli,
+) -> eyre::Result<()> {
+    let backup_name = backup::Name(args.backup);
+    let backup = config.backup(&backup_name)?;
+    let mut retention = backup
+        .retention
+        .clone()
+        .ok_or_else(|| eyre::eyre!("backup '{}' has no retention policy", backup_name.0))?;
+    retention.prune |= args.prune;
+    let repo = config.repository_for_backup(backup)?;
+    let repo_with_secrets = secrets.get_secrets(repo)?;
+    restic
+        .check_capabilities(Capability::required_by_repo(repo))
+        .await?;
+    let groups = restic
+        .forget_snapshots(
+            &repo_with_secrets,
+            &backup_name,
+            &retention,
+            std::future::pending::<()>(),
+        )
+        .await?;
+    for group in groups {
+        println!(
+            "Kept {} and removed {} snapshots of backup '{}' on host '{}'",
+            group.keep.len(),
+            group.remove.len(),
+            backup_name.0,
+            group.host
+        );
+    }
     Ok(())
 }
 
@@ -76,7 +120,18 @@ pub async fn version(restic: &Restic) -> eyre::Result<()> {
     }
 
     match restic.version_string().await {
-        Ok(restic_version) => println!("restic: {}", restic_version),
+        Ok(restic_version) => {
+            println!("restic: {}", restic_version);
+            match ResticVersion::parse(&restic_version) {
+                Some(version) if !version.is_supported() => {
+                    println!("{}", restic::Error::UnsupportedResticVersion(version))
+                }
+                Some(_) => {}
+                None => {
+                    println!("Could not parse the restic version, is restic installed correctly?")
+                }
+            }
+        }
         Err(err) => println!(
             "Could not determine restic version ({}), is restic installed correctly?",
             err
@@ -40,7 +40,13 @@ async fn index(
     let cache_dir = cache.get().await?;
     let repo_with_secrets = secrets.get_secrets(repo)?;
     let mut db = cirrus_index::Database::new(cache_dir, repo_name).await?;
-    let snapshots = cirrus_index::index_snapshots(restic, &mut db, &repo_with_secrets).await?;
+    let snapshots = cirrus_index::index_snapshots(
+        restic,
+        &mut db,
+        &repo_with_secrets,
+        std::future::pending::<()>(),
+    )
+    .await?;
     println!("{snapshots} snapshots saved");
     let to_index = db
         .get_unindexed_snapshots_number(args.snapshots_count as u64)
@@ -48,7 +54,14 @@ async fn index(
     println!("indexing {} snapshots...", to_index.len());
     for snapshot in &to_index {
         println!("indexing {}...", snapshot.short_id());
-        cirrus_index::index_files(restic, &mut db, &repo_with_secrets, snapshot).await?;
+        cirrus_index::index_files(
+            restic,
+            &mut db,
+            &repo_with_secrets,
+            snapshot,
+            std::future::pending::<()>(),
+        )
+        .await?;
     }
     Ok(())
 }
@@ -1,6 +1,7 @@
 use crate::cli;
 use cirrus_core::{
     config::{repo, Config},
+    restic::{keys, Restic},
     secrets::{SecretValue, Secrets},
 };
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
@@ -85,3 +86,51 @@ pub fn list(secrets: &Secrets, config: &Config, args: cli::secret::List) -> eyre
 
     Ok(())
 }
+
+fn prompt_new_password(repo_name: &repo::Name) -> eyre::Result<SecretValue> {
+    let prompt = format!("New password for repository '{}': ", repo_name.0);
+    let value = SecretValue::new(rpassword::prompt_password(&prompt)?);
+    let confirmation = SecretValue::new(rpassword::prompt_password("Repeat new password: ")?);
+    if value.0.is_empty() {
+        return Err(eyre::eyre!("the new password must not be empty"));
+    }
+    if value.0 != confirmation.0 {
+        return Err(eyre::eyre!("passwords don't match"));
+    }
+    Ok(value)
+}
+
+pub async fn rotate(
+    restic: &Restic,
+    secrets: &Secrets,
+    config: &Config,
+    args: cli::secret::Rotate,
+) -> eyre::Result<()> {
+    let repo_name = repo::Name(args.repository);
+    let repo = config.repository(&repo_name)?;
+    if !secrets.can_store(&repo.password) {
+        return Err(eyre::eyre!(
+            "the password of repository '{}' is a {} secret, which has to be changed externally",
+            repo_name.0,
+            repo.password.label()
+        ));
+    }
+
+    let old = secrets.get_secrets(repo)?;
+    let keys_before = restic.list_keys(&old).await?;
+    keys::current_key(&keys_before)?;
+    let new_password = prompt_new_password(&repo_name)?;
+    let new_key_id = restic
+        .rotate_key(secrets, &old, &keys_before, new_password)
+        .await?;
+
+    println!(
+        "Rotated password for repository '{}', new restic key is {}",
+        repo_name.0, new_key_id
+    );
+    println!(
+        "A running cirrus daemon keeps using the cached old password for up to \
+         `secrets-cache-ttl`; choose \"Lock Secrets\" in its status icon menu or restart it"
+    );
+    Ok(())
+}
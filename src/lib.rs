@@ -11,6 +11,7 @@ use tracing_subscriber::{
 
 mod cli;
 mod commands;
+mod redact;
 
 async fn load_config(args: &cli::Cli) -> eyre::Result<Config> {
     let config = if let Some(config_string) = &args.config_string {
@@ -69,6 +70,7 @@ fn setup_cli_logger(log_level: Option<LogLevel>) -> eyre::Result<()> {
                 .with_target(false)
                 .with_file(false)
                 .with_line_number(false)
+                .with_writer(redact::MakeRedactingWriter(std::io::stdout))
                 .with_filter(Targets::new().with_target("cli", LevelFilter::INFO)),
         )
         .with(
@@ -76,6 +78,7 @@ fn setup_cli_logger(log_level: Option<LogLevel>) -> eyre::Result<()> {
                 .with_ansi(true)
                 .with_target(false)
                 .without_time()
+                .with_writer(redact::MakeRedactingWriter(std::io::stdout))
                 .with_filter(
                     Targets::new()
                         .with_target("cli", LevelFilter::OFF)
@@ -90,7 +93,13 @@ fn setup_cli_logger(log_level: Option<LogLevel>) -> eyre::Result<()> {
 fn setup_daemon_logger(log_level: LogLevel, log_file: Option<&PathBuf>) -> eyre::Result<()> {
     let builder = registry()
         .with(LevelFilter::from_level(log_level.into()))
-        .with(layer().with_ansi(true).with_target(false).without_time());
+        .with(
+            layer()
+                .with_ansi(true)
+                .with_target(false)
+                .without_time()
+                .with_writer(redact::MakeRedactingWriter(std::io::stdout)),
+        );
 
     if let Some(log_file) = log_file {
         let time_format = time::macros::format_description!(
@@ -107,7 +116,7 @@ fn setup_daemon_logger(log_level: LogLevel, log_file: Option<&PathBuf>) -> eyre:
                     .with_ansi(false)
                     .with_span_events(FmtSpan::CLOSE)
                     .with_timer(LocalTime::new(time_format))
-                    .with_writer(file),
+                    .with_writer(redact::MakeRedactingWriter(file)),
             )
             .try_init()?;
     } else {
@@ -148,15 +157,25 @@ pub async fn main() -> eyre::Result<()> {
         .join("cirrus");
     let cache = Cache::new(cache_dir);
 
-    match args.subcommand {
+    let result = match args.subcommand {
         cli::Cmd::Daemon(args) => {
             commands::daemon::main(args, restic, secrets, maybe_config?, cache).await
         }
         cli::Cmd::Backup(args) => commands::backup(&restic, &secrets, &maybe_config?, args).await,
-        cli::Cmd::Config => commands::config(&maybe_config?),
+        cli::Cmd::Forget(args) => commands::forget(&restic, &secrets, &maybe_config?, args).await,
+        cli::Cmd::Config(args) => match args.subcommand {
+            None => commands::config::print(&maybe_config?),
+            Some(cli::config::Cmd::Check) => {
+                commands::config::check(&restic, &secrets, &maybe_config?).await
+            }
+            Some(cli::config::Cmd::Schema) => commands::config::schema(),
+        },
         cli::Cmd::Secret(args) => match args.subcommand {
             cli::secret::Cmd::Set(args) => commands::secret::set(&secrets, &maybe_config?, args),
             cli::secret::Cmd::List(args) => commands::secret::list(&secrets, &maybe_config?, args),
+            cli::secret::Cmd::Rotate(args) => {
+                commands::secret::rotate(&restic, &secrets, &maybe_config?, args).await
+            }
         },
         cli::Cmd::Restic(args) => commands::restic(&restic, &secrets, maybe_config, args).await,
         #[cfg(feature = "cirrus-self")]
@@ -169,5 +188,21 @@ pub async fn main() -> eyre::Result<()> {
                 .await
         }
         cli::Cmd::Version => commands::version(&restic).await,
+    };
+    result.map_err(with_restic_suggestion)
+}
+
+/// Tells the user what to do about restic failures with a known cause.
+fn with_restic_suggestion(report: eyre::Report) -> eyre::Report {
+    use color_eyre::Section;
+
+    let suggestion = report
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<restic::Error>())
+        .and_then(restic::Error::failure_kind)
+        .and_then(|kind| kind.suggestion());
+    match suggestion {
+        Some(suggestion) => report.suggestion(suggestion),
+        None => report,
     }
 }
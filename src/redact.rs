@@ -0,0 +1,34 @@
+use cirrus_core::secrets;
+use std::io::Write;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Wraps the writer of a logging layer and scrubs all known secret values from the output.
+/// The layer formats each event into a buffer before writing it, so a secret never ends up split
+/// across writes.
+#[derive(Debug)]
+pub(crate) struct MakeRedactingWriter<M>(pub(crate) M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for MakeRedactingWriter<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(text) => self.0.write_all(secrets::redact(text).as_bytes())?,
+            Err(_) => self.0.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}